@@ -0,0 +1,584 @@
+// User accounts.
+//
+// Authentication is off unless (auth_enabled) is set. When it is on, every
+// request, other than for the login page and static files, must be made by
+// a known user, in one of three ways:
+//
+// Login : the web UI logs in with a user name and password from the users
+//         file (users_file), and stays logged in for the session, for up
+//         to (login_keep_secs) seconds.
+// Basic : API clients give a user name and password from the users file
+//         with HTTP basic authentication, on every request.
+// Bearer : API clients give a token from (api_tokens) in settings as an
+//          HTTP bearer token, on every request.
+//
+// After a few failed logins for a user name, by either Login or Basic,
+// more tries for it are refused for a time that doubles with each failure,
+// up to a few minutes. Unknown user names are checked against a dummy hash,
+// so they take as long as known ones and can't be told apart.
+//
+// The users file is made with the adduser command, and holds a PBKDF2 hash
+// of each user's password, never the password itself. API tokens are made
+// with the token command, and only their SHA-256 hashes are kept in
+// settings. Each user is either a plain user or an admin.
+//
+// Every user has images and secrets folders of their own, named for them
+// inside the thumbnails and secrets folders, and a steganography instance
+// of their own, so users never see each other's images, files or jobs.
+// Admins can also view how much every user is using.
+//
+// With authentication off, everyone is the one anonymous admin user, using
+// the thumbnails and secrets folders themselves.
+
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::{header, Method};
+use actix_web::middleware::Next;
+use actix_web::{get, post, web, Error, FromRequest, HttpMessage, HttpRequest, HttpResponse, Responder};
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use futures::future::{ready, Ready};
+use log::{info, warn};
+use ring::{digest, pbkdf2};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::num::NonZeroU32;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use utoipa::ToSchema;
+
+use crate::api::{ApiError, ErrorCode};
+use crate::downloads::{self, Session};
+use crate::retention;
+use crate::settings::Settings;
+use crate::steg::{container, Steganography};
+use crate::SharedSteg;
+
+// Name of the user when authentication is off.
+pub const ANONYMOUS: &str = "anonymous";
+
+// Scheme of the password hashes in the users file.
+const HASH_SCHEME: &str = "pbkdf2-sha256";
+
+// Length of password salts and hashes.
+const HASH_LEN: usize = 32;
+
+// Failed logins for a user name before more tries are held back.
+const FREE_LOGIN_FAILURES: u32 = 3;
+
+// Longest time, in seconds, more tries for a user name are held back.
+const MAX_LOGIN_BACKOFF_SECS: u64 = 300;
+
+// Role of a user.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Admin,
+}
+
+// Struct to hold a user in the users file.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UserEntry {
+    pub name: String,
+    pub role: Role,
+    pub password: String,
+}
+
+// Struct to hold an API token in settings.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ApiToken {
+    pub name: String,
+    pub role: Role,
+    pub token_sha256: String,
+}
+
+// Helper function to check a user name is one that can be used,
+// as it is also the name of the user's folders.
+pub fn valid_name(name:&str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && !name.starts_with('.')
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b == b'.')
+}
+
+// Helper function to format bytes as hex digits.
+fn to_hex(bytes:&[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Helper function to parse hex digits. Returns None if they aren't valid.
+fn from_hex(text:&str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|idx| u8::from_str_radix(&text[idx..idx + 2], 16).ok()).collect()
+}
+
+// Helper function to hash a password for the users file, with a new
+// random salt: scheme$iterations$salt$hash.
+pub fn hash_password(password:&str, iterations:u32) -> String {
+    let iterations = NonZeroU32::new(iterations).unwrap_or(NonZeroU32::MIN);
+    let salt = container::random_bytes(HASH_LEN);
+    let mut hash = [0u8; HASH_LEN];
+    pbkdf2::derive(pbkdf2::PBKDF2_HMAC_SHA256, iterations, &salt, password.as_bytes(), &mut hash);
+    format!("{}${}${}${}", HASH_SCHEME, iterations, to_hex(&salt), to_hex(&hash))
+}
+
+// Helper function to check a password against a hash from the users file.
+// A hash that can't be read never matches.
+pub fn verify_password(password:&str, password_hash:&str) -> bool {
+    let mut fields = password_hash.split('$');
+    let (Some(HASH_SCHEME), Some(iterations), Some(salt), Some(hash), None) =
+        (fields.next(), fields.next(), fields.next(), fields.next(), fields.next()) else {
+        return false;
+    };
+    let (Some(iterations), Some(salt), Some(hash)) =
+        (iterations.parse().ok().and_then(NonZeroU32::new), from_hex(salt), from_hex(hash)) else {
+        return false;
+    };
+    pbkdf2::verify(pbkdf2::PBKDF2_HMAC_SHA256, iterations, &salt, password.as_bytes(), &hash).is_ok()
+}
+
+// Helper function to make a new random API token.
+pub fn new_token() -> String {
+    to_hex(&container::random_bytes(HASH_LEN))
+}
+
+// Helper function to hash an API token, for settings.
+pub fn hash_token(token:&str) -> String {
+    to_hex(digest::digest(&digest::SHA256, token.as_bytes()).as_ref())
+}
+
+// Helper function to read the users file.
+// A users file that doesn't exist has no users.
+pub fn load_users(path:&str) -> io::Result<Vec<UserEntry>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    if contents.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    serde_yaml::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// Helper function to save the users file, only readable by the owner.
+pub fn save_users(path:&str, users:&[UserEntry]) -> io::Result<()> {
+    let contents = serde_yaml::to_string(users).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    io::Write::write_all(&mut options.open(path)?, contents.as_bytes())
+}
+
+// User a request is made by, with their own steganography instance,
+// and settings pointing at their own folders.
+#[derive(Clone)]
+pub struct User {
+    pub name: String,
+    pub role: Role,
+    pub steg: SharedSteg,
+    pub settings: Settings,
+}
+
+impl User {
+    pub fn is_admin(&self) -> bool {
+        self.role == Role::Admin
+    }
+}
+
+impl FromRequest for User {
+    type Error = Error;
+    type Future = Ready<Result<Self, Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(req.extensions().get::<User>().cloned().ok_or_else(|| actix_web::error::ErrorUnauthorized("Login required")))
+    }
+}
+
+// Struct to hold what a user has been doing since the service started.
+#[derive(Clone, Default)]
+struct Activity {
+    requests: u64,
+    last_seen: Option<DateTime<Utc>>,
+}
+
+// Struct to hold a login to the web UI.
+struct Login {
+    name: String,
+    created: Instant,
+}
+
+// Struct to hold the failed logins for a user name.
+struct Failures {
+    count: u32,
+    last: Instant,
+}
+
+impl Failures {
+    // Time more tries are held back for after the last failure.
+    fn backoff(&self) -> Duration {
+        let doublings = self.count.saturating_sub(FREE_LOGIN_FAILURES).min(16);
+        match doublings {
+            0 => Duration::ZERO,
+            _ => Duration::from_secs((1u64 << (doublings - 1)).min(MAX_LOGIN_BACKOFF_SECS)),
+        }
+    }
+}
+
+// Struct to hold the number of files in a folder, and the bytes they take up.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct FolderUsage {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+// Struct to hold the usage of one user, for admins.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct UserUsage {
+    pub name: String,
+    pub role: Role,
+    pub images: FolderUsage,
+    pub secrets: FolderUsage,
+    pub requests: u64,
+    pub logins: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<String>,
+}
+
+// Struct to hold the users, who is logged in, and each user's
+// steganography instance.
+pub struct Accounts {
+    settings: Settings,
+    users: HashMap<String, UserEntry>,
+    tokens: HashMap<String, (String, Role)>,
+    dummy_hash: String,
+    failures: Mutex<HashMap<String, Failures>>,
+    logins: Mutex<HashMap<String, Login>>,
+    stegs: Mutex<HashMap<String, SharedSteg>>,
+    activity: Mutex<HashMap<String, Activity>>,
+}
+
+impl Accounts {
+    // Load the users file and API tokens, if authentication is on.
+    // Users and tokens with names that can't be used are left out.
+    pub fn new(settings:&Settings) -> io::Result<Self> {
+        let mut users = HashMap::new();
+        let mut tokens = HashMap::new();
+        if settings.auth_enabled {
+            for user in load_users(&settings.users_file)? {
+                if valid_name(&user.name) {
+                    users.insert(user.name.clone(), user);
+                }
+                else {
+                    warn!("Invalid user name in users file: {}", user.name);
+                }
+            }
+            for token in &settings.api_tokens {
+                if valid_name(&token.name) {
+                    tokens.insert(token.token_sha256.to_lowercase(), (token.name.clone(), token.role));
+                }
+                else {
+                    warn!("Invalid user name for API token: {}", token.name);
+                }
+            }
+            info!("Authentication on, with {} users and {} API tokens.", users.len(), tokens.len());
+            if users.is_empty() && tokens.is_empty() {
+                warn!("No users can log in, use the adduser command to add one.");
+            }
+        }
+        // Unknown user names are checked against this, so they take as long
+        // as known ones.
+        let dummy_hash = match settings.auth_enabled {
+            true => hash_password("", settings.kdf_iterations),
+            false => String::new(),
+        };
+        Ok(Accounts {
+            settings: settings.clone(),
+            users,
+            tokens,
+            dummy_hash,
+            failures: Mutex::new(HashMap::new()),
+            logins: Mutex::new(HashMap::new()),
+            stegs: Mutex::new(HashMap::new()),
+            activity: Mutex::new(HashMap::new()),
+        })
+    }
+
+    // Settings for a user, with the thumbnails and secrets folders
+    // their own when authentication is on.
+    fn user_settings(&self, name:&str) -> Settings {
+        let mut settings = self.settings.clone();
+        if self.settings.auth_enabled {
+            settings.thumb_folder = Path::new(&settings.thumb_folder).join(name).to_string_lossy().into_owned();
+            settings.secret_folder = Path::new(&settings.secret_folder).join(name).to_string_lossy().into_owned();
+        }
+        settings
+    }
+
+    // Get a user, with their steganography instance,
+    // which is made the first time they are seen.
+    fn user(&self, name:&str, role:Role) -> User {
+        let settings = self.user_settings(name);
+        let steg = self.stegs.lock().unwrap()
+            .entry(name.to_string())
+            .or_insert_with(|| {
+                for folder in [&settings.thumb_folder, &settings.secret_folder] {
+                    if let Err(e) = fs::create_dir_all(folder) {
+                        warn!("Unable to create folder for user {}: {}", name, e);
+                    }
+                }
+                let mut steg = Steganography::init();
+                steg.settings = settings.clone();
+                Arc::new(tokio::sync::Mutex::new(steg))
+            })
+            .clone();
+        User { name: name.to_string(), role, steg, settings }
+    }
+
+    // Check a user name and password against the users file. Failures are
+    // counted for the user name, and a success clears them.
+    fn check_password(&self, name:&str, password:&str) -> Option<User> {
+        let entry = self.users.get(name);
+        let password_hash = entry.map_or(&self.dummy_hash, |entry| &entry.password);
+        let verified = verify_password(password, password_hash);
+        let mut failures = self.failures.lock().unwrap();
+        match entry {
+            Some(entry) if verified => {
+                failures.remove(name);
+                Some(self.user(name, entry.role))
+            }
+            _ => {
+                let keep = Duration::from_secs(MAX_LOGIN_BACKOFF_SECS);
+                failures.retain(|_, failed| failed.last.elapsed() <= failed.backoff() + keep);
+                let failed = failures.entry(name.to_string()).or_insert(Failures { count: 0, last: Instant::now() });
+                failed.count = failed.count.saturating_add(1);
+                failed.last = Instant::now();
+                None
+            }
+        }
+    }
+
+    // Get how long before a user name can be tried again, if it is held
+    // back after failed logins.
+    fn login_backoff(&self, name:&str) -> Option<Duration> {
+        let failures = self.failures.lock().unwrap();
+        let failed = failures.get(name)?;
+        failed.backoff().checked_sub(failed.last.elapsed()).filter(|wait| !wait.is_zero())
+    }
+
+    // Get the user for a request. Returns Ok(None) if the request doesn't
+    // say who it is from, and Err if it gives credentials that are wrong.
+    async fn authenticate(self: &Arc<Self>, req:&ServiceRequest) -> Result<Option<User>, ()> {
+        if !self.settings.auth_enabled {
+            return Ok(Some(self.user(ANONYMOUS, Role::Admin)));
+        }
+
+        // Credentials given with the request.
+        if let Some(value) = req.headers().get(header::AUTHORIZATION) {
+            let value = value.to_str().map_err(|_| ())?;
+            let (scheme, credentials) = value.split_once(' ').ok_or(())?;
+            if scheme.eq_ignore_ascii_case("bearer") {
+                let (name, role) = self.tokens.get(&hash_token(credentials.trim())).ok_or(())?;
+                return Ok(Some(self.user(name, *role)));
+            }
+            if scheme.eq_ignore_ascii_case("basic") {
+                let decoded = base64::engine::general_purpose::STANDARD.decode(credentials.trim()).map_err(|_| ())?;
+                let decoded = String::from_utf8(decoded).map_err(|_| ())?;
+                let (name, password) = decoded.split_once(':').ok_or(())?;
+                let (name, password) = (name.to_string(), password.to_string());
+                if self.login_backoff(&name).is_some() {
+                    warn!("Refused login held back after failures for user: {}", name);
+                    return Err(());
+                }
+
+                // Hashing the password takes a while, so it's done on the
+                // blocking thread pool.
+                let accounts = self.clone();
+                return web::block(move || accounts.check_password(&name, &password))
+                    .await
+                    .map_err(|_| ())?
+                    .map(Some)
+                    .ok_or(());
+            }
+            return Err(());
+        }
+
+        // Logged in to the web UI.
+        let Some(session) = req.extensions().get::<Session>().cloned() else {
+            return Ok(None);
+        };
+        let mut logins = self.logins.lock().unwrap();
+        let keep = Duration::from_secs(self.settings.login_keep_secs);
+        logins.retain(|_, login| login.created.elapsed() <= keep);
+        Ok(logins.get(&session.0).and_then(|login| {
+            let role = self.users.get(&login.name)?.role;
+            Some(self.user(&login.name, role))
+        }))
+    }
+
+    // Record that a user has made a request.
+    fn record(&self, name:&str) {
+        let mut activity = self.activity.lock().unwrap();
+        let activity = activity.entry(name.to_string()).or_default();
+        activity.requests += 1;
+        activity.last_seen = Some(Utc::now());
+    }
+
+    // Get the usage of every user, by name.
+    pub fn usage(&self) -> Vec<UserUsage> {
+        let mut roles: BTreeMap<String, Role> = BTreeMap::new();
+        if self.settings.auth_enabled {
+            roles.extend(self.users.values().map(|user| (user.name.clone(), user.role)));
+            roles.extend(self.tokens.values().cloned());
+        }
+        else {
+            roles.insert(ANONYMOUS.to_string(), Role::Admin);
+        }
+
+        let activity = self.activity.lock().unwrap().clone();
+        let mut logins: HashMap<String, usize> = HashMap::new();
+        for login in self.logins.lock().unwrap().values() {
+            *logins.entry(login.name.clone()).or_default() += 1;
+        }
+        roles.into_iter().map(|(name, role)| {
+            let settings = self.user_settings(&name);
+            let (image_files, image_bytes) = retention::folder_usage(Path::new(&settings.thumb_folder));
+            let (secret_files, secret_bytes) = retention::folder_usage(Path::new(&settings.secret_folder));
+            let user_activity = activity.get(&name).cloned().unwrap_or_default();
+            UserUsage {
+                images: FolderUsage { files: image_files, bytes: image_bytes },
+                secrets: FolderUsage { files: secret_files, bytes: secret_bytes },
+                requests: user_activity.requests,
+                logins: logins.get(&name).copied().unwrap_or_default(),
+                last_seen: user_activity.last_seen.map(|time| time.to_rfc3339()),
+                name,
+                role,
+            }
+        }).collect()
+    }
+}
+
+// Helper function to check a path can be used without logging in.
+fn is_public(path:&str) -> bool {
+    path == "/login" || path.starts_with("/static/")
+}
+
+// Helper function to make sure every request is made by a user, adding
+// the user to the request. Requests that aren't are sent to the login
+// page if they are from a browser, or refused.
+pub async fn require_user(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let Some(accounts) = req.app_data::<web::Data<Accounts>>().cloned() else {
+        return Err(actix_web::error::ErrorInternalServerError("No accounts"));
+    };
+    let accounts = accounts.into_inner();
+    match accounts.authenticate(&req).await {
+        Ok(Some(user)) => {
+            accounts.record(&user.name);
+            req.extensions_mut().insert(user);
+        }
+        Ok(None) if is_public(req.path()) => {}
+        Ok(None) if req.method() == Method::GET && !req.path().starts_with("/api/") => {
+            let response = HttpResponse::SeeOther().insert_header((header::LOCATION, "/login")).finish();
+            return Ok(req.into_response(response).map_into_right_body());
+        }
+        Ok(None) => {
+            return Ok(req.into_response(unauthorized("Login required")).map_into_right_body());
+        }
+        Err(()) => {
+            warn!("Refused request with invalid credentials: {}", req.path());
+            return Ok(req.into_response(unauthorized("Invalid credentials")).map_into_right_body());
+        }
+    }
+    Ok(next.call(req).await?.map_into_left_body())
+}
+
+// Helper function to respond that a request needs a user.
+fn unauthorized(error:&str) -> HttpResponse {
+    HttpResponse::Unauthorized()
+        .insert_header((header::WWW_AUTHENTICATE, "Basic realm=\"peekaboo\""))
+        .json(ApiError::new(ErrorCode::Unauthorized, error))
+}
+
+// Struct to hold the login form.
+#[derive(Deserialize)]
+struct LoginForm {
+    name: String,
+    password: String,
+}
+
+// Helper function to get the login page, with a message if any.
+fn login_page(message:&str) -> HttpResponse {
+    let page = include_str!("../static/login.html").replace("{{message}}", message);
+    HttpResponse::Ok().content_type("text/html").body(page)
+}
+
+#[get("/login")]
+async fn login_form() -> impl Responder {
+    login_page("")
+}
+
+// Log in to the web UI. The session is replaced with a new one on logging
+// in, so a session made before can't be used to act as the user.
+#[post("/login")]
+async fn log_in(form: web::Form<LoginForm>, accounts: web::Data<Accounts>) -> impl Responder {
+    let LoginForm { name, password } = form.into_inner();
+    if !accounts.settings.auth_enabled {
+        return HttpResponse::SeeOther().insert_header((header::LOCATION, "/")).finish();
+    }
+    if let Some(wait) = accounts.login_backoff(&name) {
+        warn!("Refused login held back after failures for user: {}", name);
+        return login_page(&format!("Too many failed logins, try again in {} seconds.", wait.as_secs_f64().ceil() as u64));
+    }
+    let checker = accounts.clone();
+    let checked_name = name.clone();
+    let user = web::block(move || checker.check_password(&checked_name, &password)).await.ok().flatten();
+    let Some(user) = user else {
+        warn!("Failed login for user: {}", name);
+        return login_page("Incorrect user name or password.");
+    };
+
+    let session = downloads::new_token();
+    accounts.logins.lock().unwrap().insert(session.clone(), Login { name: user.name.clone(), created: Instant::now() });
+    info!("User logged in: {}", user.name);
+    HttpResponse::SeeOther()
+        .insert_header((header::LOCATION, "/"))
+        .cookie(downloads::session_cookie(session))
+        .finish()
+}
+
+// Log out of the web UI.
+#[post("/logout")]
+async fn log_out(session: Session, accounts: web::Data<Accounts>) -> impl Responder {
+    if let Some(login) = accounts.logins.lock().unwrap().remove(&session.0) {
+        info!("User logged out: {}", login.name);
+    }
+    HttpResponse::SeeOther().insert_header((header::LOCATION, "/login")).finish()
+}
+
+// Get who is logged in, for the web UI.
+#[get("/account")]
+async fn account(user: User, accounts: web::Data<Accounts>) -> impl Responder {
+    HttpResponse::Ok().json(serde_json::json!({
+        "name": user.name,
+        "role": user.role,
+        "auth": accounts.settings.auth_enabled,
+    }))
+}
+
+// Helper function to add the login endpoints to the web service.
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(login_form)
+        .service(log_in)
+        .service(log_out)
+        .service(account);
+}
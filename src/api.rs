@@ -0,0 +1,922 @@
+// Versioned JSON API.
+//
+// The endpoints under /api/v1 cover the main steganography workflow:
+// loading an image, embedding files into it, listing the embedded files,
+// extracting them, and following background jobs. Unlike the endpoints
+// used by the web UI, responses are typed, with real booleans, numbers
+// and arrays, and every error is a json object with a fixed error code
+// and a message:
+//
+//     { "code": "incorrect_password", "error": "Incorrect password provided" }
+//
+// The one-shot endpoints under /api/v1/oneshot take the image along with
+// everything else needed in a single request, and work on it without
+// touching the loaded image, so API clients don't need to upload first
+// and can't get in each other's way. Embedding returns the embedded image
+// itself, or a link to download it from, and extraction returns a zip, or
+// tar, of the extracted files, which are never written to disk.
+//
+// Links to extracted files and embedded images only work for the session
+// they were made for, identified by the session cookie, so clients need to
+// keep cookies to use them.
+//
+// When authentication is on, every request needs HTTP basic or bearer
+// token credentials, and is refused with an unauthorized error without
+// them. Admins can also get the usage of every user.
+//
+// The OpenAPI document for the API is generated from the types and
+// handlers here, and served at /api/v1/openapi.json.
+
+use actix_multipart::Multipart;
+use actix_web::http::StatusCode;
+use actix_web::{delete, get, post, web, HttpResponse, Responder};
+use futures_util::stream::TryStreamExt;
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::{self, File as StdFile};
+use std::io::{self, Write};
+use std::path::Path;
+use sanitize_filename::sanitize;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::accounts::{Accounts, FolderUsage, Role, User, UserUsage};
+use crate::archive::{self, ArchiveFormat};
+use crate::downloads::SessionLinks;
+use crate::jobs::{JobState, JobStatus, Jobs};
+use crate::steg::{self, container, Steganography, SteganographyError};
+use crate::steg::file_tree::SourceFile;
+use crate::{read_upload, respond_to_job, run_own_steg_job, run_steg_job, save_output_image, UploadFolder, SETTINGS};
+
+// Json body of a response, and the status to respond with.
+type ApiResponse = (StatusCode, serde_json::Value);
+
+// Error codes returned by the API.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    IncorrectPassword,
+    IncorrectKey,
+    NoKeySlots,
+    NoFreeKeySlot,
+    InvalidKeySlot,
+    LastKeySlot,
+    RekeyNotSupported,
+    MissingParts,
+    MixedSets,
+    NoParts,
+    NotEnoughShares,
+    NoShares,
+    InvalidShares,
+    Cancelled,
+    NoImage,
+    NotCoded,
+    InvalidRequest,
+    CapacityExceeded,
+    EmbedFailed,
+    JobNotFound,
+    JobFinished,
+    ProcessingFailed,
+    Unauthorized,
+    AdminOnly,
+}
+
+// Struct to hold an error response.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct ApiError {
+    pub code: ErrorCode,
+    pub error: String,
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, error: impl Into<String>) -> Self {
+        ApiError { code, error: error.into() }
+    }
+
+    // Status to respond with for the error.
+    pub fn status(&self) -> StatusCode {
+        match self.code {
+            ErrorCode::IncorrectPassword | ErrorCode::IncorrectKey | ErrorCode::AdminOnly => StatusCode::FORBIDDEN,
+            ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorCode::JobNotFound => StatusCode::NOT_FOUND,
+            ErrorCode::Cancelled | ErrorCode::JobFinished => StatusCode::CONFLICT,
+            ErrorCode::CapacityExceeded => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorCode::MissingParts
+            | ErrorCode::MixedSets
+            | ErrorCode::NoParts
+            | ErrorCode::NotEnoughShares
+            | ErrorCode::NoShares
+            | ErrorCode::InvalidShares => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::EmbedFailed | ErrorCode::ProcessingFailed => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    // Get the error as a response.
+    pub fn response(self) -> ApiResponse {
+        (self.status(), serde_json::to_value(self).unwrap_or_default())
+    }
+}
+
+impl From<&SteganographyError> for ApiError {
+    fn from(e: &SteganographyError) -> Self {
+        let code = match e {
+            SteganographyError::IncorrectPassword => ErrorCode::IncorrectPassword,
+            SteganographyError::IncorrectKey => ErrorCode::IncorrectKey,
+            SteganographyError::NoKeySlots => ErrorCode::NoKeySlots,
+            SteganographyError::NoFreeKeySlot => ErrorCode::NoFreeKeySlot,
+            SteganographyError::InvalidKeySlot => ErrorCode::InvalidKeySlot,
+            SteganographyError::LastKeySlot => ErrorCode::LastKeySlot,
+            SteganographyError::RekeyNotSupported => ErrorCode::RekeyNotSupported,
+            SteganographyError::MissingParts { .. } => ErrorCode::MissingParts,
+            SteganographyError::MixedSets => ErrorCode::MixedSets,
+            SteganographyError::NoParts => ErrorCode::NoParts,
+            SteganographyError::NotEnoughShares { .. } => ErrorCode::NotEnoughShares,
+            SteganographyError::NoShares => ErrorCode::NoShares,
+            SteganographyError::InvalidShares => ErrorCode::InvalidShares,
+            SteganographyError::Cancelled => ErrorCode::Cancelled,
+        };
+        ApiError::new(code, e.to_string())
+    }
+}
+
+impl From<&io::Error> for ApiError {
+    fn from(e: &io::Error) -> Self {
+        let code = match e.kind() {
+            io::ErrorKind::Interrupted => ErrorCode::Cancelled,
+            io::ErrorKind::StorageFull => ErrorCode::CapacityExceeded,
+            _ => ErrorCode::EmbedFailed,
+        };
+        ApiError::new(code, e.to_string())
+    }
+}
+
+// Helper function to get a successful response.
+fn success<T: Serialize>(body: T) -> ApiResponse {
+    (StatusCode::OK, serde_json::to_value(body).unwrap_or_default())
+}
+
+// Helper function to respond with an error.
+fn error_response(e: ApiError) -> HttpResponse {
+    let (status, body) = e.response();
+    HttpResponse::build(status).json(body)
+}
+
+// Whether a loaded image holds embedded files.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CodedState {
+    // No embedded files found.
+    Uncoded,
+    // Embedded files found.
+    Coded,
+    // May hold a headerless volume, which can only be found with its password.
+    Unknown,
+}
+
+// Split set details of an image.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct PartDetails {
+    pub set: String,
+    pub part: u16,
+    pub parts: u16,
+}
+
+// Threshold set details of an image.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct ShareDetails {
+    pub set: String,
+    pub share: u16,
+    pub shares: u16,
+    pub threshold: u16,
+}
+
+// Details of a loaded image.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct ImageInfo {
+    pub coded: CodedState,
+    pub password: bool,
+    pub recipients: bool,
+    pub key_slots: bool,
+    pub editable: bool,
+    // Bytes of files that can be embedded, before the per file overhead.
+    pub capacity: u64,
+    // Bytes used by each embedded file for its name, length and metadata.
+    pub overhead_per_file: u64,
+    // Bytes of files that can be embedded in each deniable volume.
+    pub volume_capacity: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub part: Option<PartDetails>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub share: Option<ShareDetails>,
+}
+
+// Form to upload an image.
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct ImageForm {
+    #[schema(value_type = String, format = Binary)]
+    pub image: Vec<u8>,
+}
+
+// Form to embed files into the loaded image.
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct EmbedForm {
+    #[schema(value_type = Vec<String>, format = Binary)]
+    pub files: Vec<Vec<u8>>,
+    // Empty, or left out, for no password.
+    pub password: Option<String>,
+    // Fill the rest of the last bit plane written to with random data.
+    pub noise: Option<bool>,
+    // Run as a background job.
+    #[schema(rename = "async")]
+    pub background: Option<bool>,
+}
+
+// Form to embed files into an image in one request.
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct OneShotEmbedForm {
+    #[schema(value_type = String, format = Binary)]
+    pub image: Vec<u8>,
+    #[schema(value_type = Vec<String>, format = Binary)]
+    pub files: Vec<Vec<u8>>,
+    // Empty, or left out, for no password.
+    pub password: Option<String>,
+    // Fill the rest of the last bit plane written to with random data.
+    pub noise: Option<bool>,
+    // "image" for the embedded image itself, the default,
+    // or "url" for a link to download it from.
+    pub response: Option<String>,
+}
+
+// Form to extract the files from an image in one request.
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct OneShotExtractForm {
+    #[schema(value_type = String, format = Binary)]
+    pub image: Vec<u8>,
+    // Password, or private key as hex digits for public key recipients.
+    pub password: Option<String>,
+    // Comma separated file names, or indexes from 1, of the files to
+    // extract. All files are extracted if left out.
+    pub select: Option<String>,
+    // "zip" for a zip of the extracted files, the default, or "tar".
+    pub format: Option<String>,
+}
+
+// Result of embedding files.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct EmbedResult {
+    // Link to download the embedded image from, which only works
+    // for the session that embedded it, for a limited time.
+    pub image: String,
+    pub duration_secs: f64,
+}
+
+// Request to extract the embedded files.
+#[derive(Clone, Debug, Deserialize, ToSchema)]
+pub struct ExtractRequest {
+    // Password, or private key as hex digits for public key recipients.
+    #[serde(default)]
+    pub password: String,
+    // File names, or indexes from 1, of the files to extract.
+    // All files are extracted if none are given.
+    #[serde(default)]
+    pub select: Vec<String>,
+    // Run as a background job.
+    #[serde(default, rename = "async")]
+    #[schema(rename = "async")]
+    pub background: bool,
+}
+
+// A file that has been extracted.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct ExtractedFile {
+    pub name: String,
+    // Link to download the file from, which only works for the
+    // session that extracted it, for a limited time.
+    pub path: String,
+    pub mime_type: String,
+    // Whether the file is itself an image with embedded files.
+    pub coded: bool,
+}
+
+// Who signed the files, and if the signature checks out.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct SignatureDetails {
+    pub verified: bool,
+    pub signer: String,
+    pub trusted: bool,
+}
+
+// Result of extracting files.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct ExtractResult {
+    pub files: Vec<ExtractedFile>,
+    pub duration_secs: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<SignatureDetails>,
+}
+
+// Request to list the embedded files.
+#[derive(Clone, Debug, Deserialize, ToSchema)]
+pub struct ManifestRequest {
+    #[serde(default)]
+    pub password: String,
+}
+
+// A file embedded in the image.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct ManifestFile {
+    pub index: u16,
+    pub name: String,
+    pub size: u64,
+    pub mime_type: String,
+    // SHA-256 of the file contents, as hex digits.
+    pub checksum: String,
+    // Modification time, in seconds since the Unix epoch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modified: Option<u64>,
+    // Permissions, as octal digits.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+}
+
+// Files embedded in the image.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct Manifest {
+    pub files: Vec<ManifestFile>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<SignatureDetails>,
+}
+
+// Response to a request run as a background job.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct JobAccepted {
+    pub job: String,
+}
+
+// Generated OpenAPI document for the API.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Peek-A-Boo API", description = "Steganography as applied to digital images."),
+    paths(
+        upload_image, embed_files, extract_files, list_manifest, oneshot_embed, oneshot_extract,
+        job_status, cancel_job, admin_usage, openapi_document,
+    ),
+    components(schemas(
+        ApiError, ErrorCode, ImageInfo, CodedState, PartDetails, ShareDetails, ImageForm,
+        EmbedForm, EmbedResult, OneShotEmbedForm, OneShotExtractForm, ExtractRequest, ExtractResult, ExtractedFile, SignatureDetails,
+        ManifestRequest, Manifest, ManifestFile, JobAccepted, JobStatus, JobState,
+        UserUsage, FolderUsage, Role,
+    ))
+)]
+struct ApiDoc;
+
+// Helper function to add the API endpoints to the web service.
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/api/v1")
+            .service(upload_image)
+            .service(embed_files)
+            .service(extract_files)
+            .service(list_manifest)
+            .service(oneshot_embed)
+            .service(oneshot_extract)
+            .service(job_status)
+            .service(cancel_job)
+            .service(admin_usage)
+            .service(openapi_document),
+    );
+}
+
+// Helper function to get the details of the loaded image.
+fn image_info(steg: &Steganography) -> ImageInfo {
+    let coded = if steg.pic_coded {
+        CodedState::Coded
+    } else if steg.pic_unknown {
+        CodedState::Unknown
+    } else {
+        CodedState::Uncoded
+    };
+    ImageInfo {
+        coded,
+        password: (steg.pic_coded && steg.pic_has_pw) || steg.pic_unknown,
+        recipients: steg.pic_coded && steg.pic_recipients,
+        key_slots: steg.pic_coded && steg.pic_key_slots,
+        editable: steg.pic_coded && steg.is_editable(),
+        capacity: steg.embed_capacity,
+        overhead_per_file: steg.overhead_per_file as u64,
+        volume_capacity: steg.volume_capacity(),
+        part: steg.pic_part.as_ref().map(|part_info| PartDetails {
+            set: part_info.set_id.clone(),
+            part: part_info.part_idx,
+            parts: part_info.part_count,
+        }),
+        share: steg.pic_share.as_ref().map(|share_info| ShareDetails {
+            set: share_info.set_id.clone(),
+            share: share_info.share_idx,
+            shares: share_info.share_count,
+            threshold: share_info.threshold,
+        }),
+    }
+}
+
+// Helper function to get who signed the files read from the image.
+fn signature_details(steg: &Steganography) -> Option<SignatureDetails> {
+    steg.signature.as_ref().map(|signature| SignatureDetails {
+        verified: signature.verified,
+        signer: signature.fingerprint.clone(),
+        trusted: signature.trusted,
+    })
+}
+
+// Helper function to get the files extracted from the image,
+// with a download link to each file. Files extracted to memory are
+// moved behind their links.
+fn extracted_files(steg: &mut Steganography, links: &SessionLinks) -> Vec<ExtractedFile> {
+    if steg.in_memory() {
+        return std::mem::take(&mut steg.memory_files)
+            .into_iter()
+            .map(|mut file| {
+                let extension = Path::new(&file.file_name).extension().unwrap_or_default().to_string_lossy().to_lowercase();
+                ExtractedFile {
+                    path: links.link_extracted(&mut file),
+                    name: file.file_name.clone(),
+                    mime_type: steg::get_mime_type(&extension).to_string(),
+                    coded: false,
+                }
+            })
+            .collect();
+    }
+    steg.embedded_files
+        .iter()
+        .map(|file| {
+            // Files in directories keep their path under the secrets folder.
+            let file_path = Path::new(&file.file_name);
+            let name = file_path
+                .strip_prefix(&steg.settings.secret_folder)
+                .unwrap_or_else(|_| Path::new(file_path.file_name().unwrap_or_default()))
+                .to_string_lossy()
+                .replace('\\', "/");
+            ExtractedFile {
+                path: links.link(&file.file_name),
+                name,
+                mime_type: file.file_type.clone(),
+                coded: file.file_type == "image/png" && file.file_coded,
+            }
+        })
+        .collect()
+}
+
+// Result of a one-shot request, either a file to send back, or json.
+enum OneShotResult {
+    File { bytes: Vec<u8>, content_type: &'static str, file_name: &'static str },
+    Json(ApiResponse),
+}
+
+// Helper function to respond with the result of a one-shot request.
+fn oneshot_response(result: Result<OneShotResult, actix_web::error::BlockingError>) -> HttpResponse {
+    match result {
+        Ok(OneShotResult::File { bytes, content_type, file_name }) => HttpResponse::Ok()
+            .content_type(content_type)
+            .insert_header(("Content-Disposition", format!("attachment; filename=\"{}\"", file_name)))
+            .body(bytes),
+        Ok(OneShotResult::Json((status, body))) => HttpResponse::build(status).json(body),
+        Err(e) => {
+            error!("Steganography job failed: {}", e);
+            error_response(ApiError::new(ErrorCode::ProcessingFailed, "Processing failed"))
+        }
+    }
+}
+
+// Helper function to save an uploaded file to a folder, or in memory only
+// mode to hold it in memory instead. Returns None if the field isn't a file.
+// Files are saved with a random prefix, so that uploads with the same name
+// don't overwrite each other, but keep their own name to embed them as.
+async fn save_upload(field: &mut actix_multipart::Field, folder: &Path) -> io::Result<Option<SourceFile>> {
+    let filename = match field.content_disposition().get_filename() {
+        Some(filename) => sanitize(filename),
+        None => return Ok(None),
+    };
+    let memory_only = SETTINGS.lock().unwrap().memory_only;
+    if memory_only {
+        return Ok(Some(SourceFile::in_memory(&filename, read_upload(field).await?)));
+    }
+    let prefix: String = container::random_bytes(4).iter().map(|b| format!("{:02x}", b)).collect();
+    let file_path = folder.join(format!("{}-{}", prefix, filename));
+    fs::create_dir_all(folder)?;
+    let mut file = StdFile::create(&file_path)?;
+    while let Some(chunk) = field.try_next().await.map_err(io::Error::other)? {
+        file.write_all(&chunk)?;
+    }
+    Ok(Some(SourceFile { path: file_path.to_string_lossy().into_owned(), file_name: filename, memory: None }))
+}
+
+// Helper function to find a file name given more than once, as only one
+// of the files could be extracted under that name.
+fn duplicate_name(files: &[SourceFile]) -> Option<&str> {
+    let mut names = HashSet::new();
+    files.iter().map(|file| file.file_name.as_str()).find(|name| !names.insert(*name))
+}
+
+// Helper function to read a text field of a multipart form.
+async fn read_text(field: &mut actix_multipart::Field) -> io::Result<String> {
+    let mut text = Vec::new();
+    while let Some(chunk) = field.try_next().await.map_err(io::Error::other)? {
+        text.extend_from_slice(&chunk);
+    }
+    String::from_utf8(text).map_err(io::Error::other)
+}
+
+// Load an image to work on, replacing any image already loaded.
+#[utoipa::path(
+    context_path = "/api/v1",
+    request_body(content = ImageForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "Image loaded", body = ImageInfo),
+        (status = 400, description = "No image, or not a supported image", body = ApiError),
+    )
+)]
+#[post("/images")]
+async fn upload_image(mut payload: Multipart, user: User) -> impl Responder {
+    let thumb_folder = user.settings.thumb_folder.clone();
+
+    // Only the first file uploaded is loaded.
+    let mut image_path = None;
+    while let Ok(Some(mut field)) = payload.try_next().await {
+        if image_path.is_none() {
+            match save_upload(&mut field, Path::new(&thumb_folder)).await {
+                Ok(path) => image_path = path,
+                Err(e) => return error_response(ApiError::new(ErrorCode::InvalidRequest, e.to_string())),
+            }
+        }
+    }
+    let image_path = match image_path {
+        Some(image_path) => image_path,
+        None => return error_response(ApiError::new(ErrorCode::NoImage, "No image uploaded")),
+    };
+
+    let result = run_steg_job(&user.steg, move |steg| {
+        // The last image loaded is wiped before the next is loaded.
+        if steg.settings.memory_only {
+            steg.wipe_memory();
+        }
+        steg.load_source(&image_path);
+        if !steg.img_to_proc {
+            return ApiError::new(ErrorCode::NoImage, "Not a supported image").response();
+        }
+        success(image_info(steg))
+    }).await;
+    crate::job_response(result)
+}
+
+// Embed files into the loaded image, saving the embedded image as a new file.
+#[utoipa::path(
+    context_path = "/api/v1",
+    request_body(content = EmbedForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "Files embedded", body = EmbedResult),
+        (status = 202, description = "Embedding started as a background job", body = JobAccepted),
+        (status = 400, description = "No image loaded, or no files given", body = ApiError),
+        (status = 409, description = "Embedding cancelled", body = ApiError),
+        (status = 413, description = "Files too big for the image", body = ApiError),
+    )
+)]
+#[post("/embed")]
+async fn embed_files(mut payload: Multipart, user: User, jobs: web::Data<Jobs>, links: SessionLinks) -> impl Responder {
+    let settings = user.settings.clone();
+    let mut files: Vec<SourceFile> = Vec::new();
+    let mut password = String::new();
+    let mut noise_fill = settings.noise_fill;
+    let mut background = false;
+    let uploads = UploadFolder::new();
+
+    while let Ok(Some(mut field)) = payload.try_next().await {
+        let field_name = field.content_disposition().get_name().unwrap_or_default().to_string();
+        let result = match field_name.as_str() {
+            "files" => save_upload(&mut field, &uploads.path).await.map(|path| files.extend(path)),
+            "password" => read_text(&mut field).await.map(|text| password = text),
+            "noise" => read_text(&mut field).await.map(|text| noise_fill = text.eq_ignore_ascii_case("true")),
+            "async" => read_text(&mut field).await.map(|text| background = text.eq_ignore_ascii_case("true")),
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            return error_response(ApiError::new(ErrorCode::InvalidRequest, e.to_string()));
+        }
+    }
+    if files.is_empty() {
+        return error_response(ApiError::new(ErrorCode::InvalidRequest, "No files to embed"));
+    }
+    if let Some(name) = duplicate_name(&files) {
+        return error_response(ApiError::new(ErrorCode::InvalidRequest, format!("File given more than once: {}", name)));
+    }
+
+    respond_to_job(&user, &jobs, background, "embed", move |steg| {
+        // Uploaded files are removed once done with.
+        let _uploads = uploads;
+        if !steg.img_to_proc {
+            return ApiError::new(ErrorCode::NoImage, "No image loaded").response();
+        }
+        steg.noise_fill = noise_fill;
+        steg.headerless = false;
+        steg.signing_key = None;
+        let embedded = steg.embed_sources(!password.is_empty(), &password, &files)
+            .and_then(|_| save_output_image(steg, &settings, &links));
+        match embedded {
+            Ok((_, link)) => success(EmbedResult {
+                image: link,
+                duration_secs: steg.embed_duration.as_secs_f64(),
+            }),
+            Err(e) => ApiError::from(&e).response(),
+        }
+    }).await
+}
+
+// Extract the files embedded in the loaded image into the secrets folder.
+#[utoipa::path(
+    context_path = "/api/v1",
+    request_body = ExtractRequest,
+    responses(
+        (status = 200, description = "Files extracted", body = ExtractResult),
+        (status = 202, description = "Extraction started as a background job", body = JobAccepted),
+        (status = 403, description = "Incorrect password or private key", body = ApiError),
+        (status = 409, description = "Extraction cancelled", body = ApiError),
+        (status = 422, description = "Other images of a set are needed", body = ApiError),
+    )
+)]
+#[post("/extract")]
+async fn extract_files(request: web::Json<ExtractRequest>, user: User, jobs: web::Data<Jobs>, links: SessionLinks) -> impl Responder {
+    let request = request.into_inner();
+    respond_to_job(&user, &jobs, request.background, "extract", move |steg| {
+        if !steg.img_to_proc {
+            return ApiError::new(ErrorCode::NoImage, "No image loaded").response();
+        }
+        if !steg.pic_coded && !steg.pic_unknown {
+            return ApiError::new(ErrorCode::NotCoded, "Image has no embedded files").response();
+        }
+        match steg.extract_selected(request.password, request.select) {
+            Ok(()) => success(ExtractResult {
+                files: extracted_files(steg, &links),
+                duration_secs: steg.extract_duration.as_secs_f64(),
+                signature: signature_details(steg),
+            }),
+            Err(e) => ApiError::from(&e).response(),
+        }
+    }).await
+}
+
+// List the files embedded in the loaded image, without extracting them.
+#[utoipa::path(
+    context_path = "/api/v1",
+    request_body = ManifestRequest,
+    responses(
+        (status = 200, description = "Embedded files", body = Manifest),
+        (status = 403, description = "Incorrect password or private key", body = ApiError),
+    )
+)]
+#[post("/manifest")]
+async fn list_manifest(request: web::Json<ManifestRequest>, user: User) -> impl Responder {
+    let password = request.into_inner().password;
+    let result = run_steg_job(&user.steg, move |steg| {
+        if !steg.img_to_proc {
+            return ApiError::new(ErrorCode::NoImage, "No image loaded").response();
+        }
+        match steg.list_manifest(password) {
+            Ok(listed_files) => success(Manifest {
+                files: listed_files
+                    .into_iter()
+                    .map(|file| ManifestFile {
+                        index: file.file_idx,
+                        name: file.file_name,
+                        size: file.file_len,
+                        mime_type: file.file_type,
+                        checksum: file.checksum,
+                        modified: file.meta.map(|meta| meta.modified),
+                        mode: file.meta.map(|meta| format!("{:o}", meta.mode)),
+                    })
+                    .collect(),
+                signature: signature_details(steg),
+            }),
+            Err(e) => ApiError::from(&e).response(),
+        }
+    }).await;
+    crate::job_response(result)
+}
+
+// Embed files into an image, all in one request, without using the loaded image.
+#[utoipa::path(
+    context_path = "/api/v1",
+    request_body(content = OneShotEmbedForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "Embedded image, or a link to it if asked for", content_type = "image/png", body = EmbedResult),
+        (status = 400, description = "No image, more than one image, not a supported image, or no files given", body = ApiError),
+        (status = 413, description = "Files too big for the image", body = ApiError),
+    )
+)]
+#[post("/oneshot/embed")]
+async fn oneshot_embed(mut payload: Multipart, user: User, links: SessionLinks) -> impl Responder {
+    let settings = user.settings.clone();
+    let folder = UploadFolder::new();
+    let mut image_path = None;
+    let mut files: Vec<SourceFile> = Vec::new();
+    let mut password = String::new();
+    let mut noise_fill = settings.noise_fill;
+    let mut want_url = false;
+
+    while let Ok(Some(mut field)) = payload.try_next().await {
+        let field_name = field.content_disposition().get_name().unwrap_or_default().to_string();
+        let result = match field_name.as_str() {
+            "image" if image_path.is_some() => Err(io::Error::other("Only one image can be uploaded")),
+            "image" => save_upload(&mut field, &folder.path).await.map(|path| image_path = path),
+            "files" => save_upload(&mut field, &folder.path).await.map(|path| files.extend(path)),
+            "password" => read_text(&mut field).await.map(|text| password = text),
+            "noise" => read_text(&mut field).await.map(|text| noise_fill = text.eq_ignore_ascii_case("true")),
+            "response" => read_text(&mut field).await.map(|text| want_url = text.eq_ignore_ascii_case("url")),
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            return error_response(ApiError::new(ErrorCode::InvalidRequest, e.to_string()));
+        }
+    }
+    let image_path = match image_path {
+        Some(image_path) => image_path,
+        None => return error_response(ApiError::new(ErrorCode::NoImage, "No image uploaded")),
+    };
+    if files.is_empty() {
+        return error_response(ApiError::new(ErrorCode::InvalidRequest, "No files to embed"));
+    }
+    if let Some(name) = duplicate_name(&files) {
+        return error_response(ApiError::new(ErrorCode::InvalidRequest, format!("File given more than once: {}", name)));
+    }
+
+    let result = run_own_steg_job(move |steg| {
+        // Uploaded files are removed once done with.
+        let _folder = folder;
+        steg.load_source(&image_path);
+        if !steg.img_to_proc {
+            return OneShotResult::Json(ApiError::new(ErrorCode::NoImage, "Not a supported image").response());
+        }
+        steg.noise_fill = noise_fill;
+        if let Err(e) = steg.embed_sources(!password.is_empty(), &password, &files) {
+            return OneShotResult::Json(ApiError::from(&e).response());
+        }
+
+        // Either save the image to download later, or send it straight back.
+        if want_url {
+            return OneShotResult::Json(match save_output_image(steg, &settings, &links) {
+                Ok((_, link)) => success(EmbedResult {
+                    image: link,
+                    duration_secs: steg.embed_duration.as_secs_f64(),
+                }),
+                Err(e) => ApiError::new(ErrorCode::ProcessingFailed, e.to_string()).response(),
+            });
+        }
+        match steg.image_png_bytes() {
+            Ok(bytes) => OneShotResult::File { bytes, content_type: "image/png", file_name: "embedded.png" },
+            Err(e) => OneShotResult::Json(ApiError::new(ErrorCode::ProcessingFailed, e.to_string()).response()),
+        }
+    }).await;
+    oneshot_response(result)
+}
+
+// Extract the files from an image, all in one request, without using the
+// loaded image. The files are sent back as an archive, and not kept.
+#[utoipa::path(
+    context_path = "/api/v1",
+    request_body(content = OneShotExtractForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "Zip, or tar, of the extracted files", content_type = "application/zip"),
+        (status = 400, description = "No image, more than one image, not a supported image, or no embedded files", body = ApiError),
+        (status = 403, description = "Incorrect password or private key", body = ApiError),
+        (status = 422, description = "Other images of a set are needed", body = ApiError),
+    )
+)]
+#[post("/oneshot/extract")]
+async fn oneshot_extract(mut payload: Multipart) -> impl Responder {
+    let folder = UploadFolder::new();
+    let mut image_path = None;
+    let mut password = String::new();
+    let mut selection: Vec<String> = Vec::new();
+    let mut format = ArchiveFormat::Zip;
+
+    while let Ok(Some(mut field)) = payload.try_next().await {
+        let field_name = field.content_disposition().get_name().unwrap_or_default().to_string();
+        let result = match field_name.as_str() {
+            "image" if image_path.is_some() => Err(io::Error::other("Only one image can be uploaded")),
+            "image" => save_upload(&mut field, &folder.path).await.map(|path| image_path = path),
+            "password" => read_text(&mut field).await.map(|text| password = text),
+            "select" => read_text(&mut field).await.map(|text| {
+                selection = text.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect();
+            }),
+            "format" => read_text(&mut field).await.and_then(|text| {
+                format = ArchiveFormat::from_name(&text).ok_or_else(|| io::Error::other(format!("Unknown archive format: {}", text)))?;
+                Ok(())
+            }),
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            return error_response(ApiError::new(ErrorCode::InvalidRequest, e.to_string()));
+        }
+    }
+    let image_path = match image_path {
+        Some(image_path) => image_path,
+        None => return error_response(ApiError::new(ErrorCode::NoImage, "No image uploaded")),
+    };
+
+    let result = run_own_steg_job(move |steg| {
+        // Uploaded image is removed once done with, and the
+        // extracted files are only ever kept in memory.
+        let _folder = folder;
+        steg.extract_to_memory = true;
+        steg.load_source(&image_path);
+        if !steg.img_to_proc {
+            return OneShotResult::Json(ApiError::new(ErrorCode::NoImage, "Not a supported image").response());
+        }
+        if !steg.pic_coded && !steg.pic_unknown {
+            return OneShotResult::Json(ApiError::new(ErrorCode::NotCoded, "Image has no embedded files").response());
+        }
+        if let Err(e) = steg.extract_selected(password, selection) {
+            return OneShotResult::Json(ApiError::from(&e).response());
+        }
+
+        match archive::extracted_entries(steg).and_then(|entries| archive::build_archive(format, &entries)) {
+            Ok(bytes) => OneShotResult::File { bytes, content_type: format.content_type(), file_name: format.file_name() },
+            Err(e) => OneShotResult::Json(ApiError::new(ErrorCode::ProcessingFailed, e.to_string()).response()),
+        }
+    }).await;
+    oneshot_response(result)
+}
+
+// Get the progress of a background job, and its result once finished.
+#[utoipa::path(
+    context_path = "/api/v1",
+    params(("id" = String, Path, description = "Job ID")),
+    responses(
+        (status = 200, description = "Job status", body = JobStatus),
+        (status = 404, description = "No such job", body = ApiError),
+    )
+)]
+#[get("/jobs/{id}")]
+async fn job_status(path: web::Path<String>, jobs: web::Data<Jobs>, user: User) -> impl Responder {
+    let id = path.into_inner();
+    match jobs.get(&user.name, &id) {
+        Some(job) => HttpResponse::Ok().json(job.status(&id)),
+        None => error_response(ApiError::new(ErrorCode::JobNotFound, "Job not found")),
+    }
+}
+
+// Cancel a background job. The job stops at the next chunk.
+#[utoipa::path(
+    context_path = "/api/v1",
+    params(("id" = String, Path, description = "Job ID")),
+    responses(
+        (status = 202, description = "Job cancelling", body = JobStatus),
+        (status = 404, description = "No such job", body = ApiError),
+        (status = 409, description = "Job already finished", body = ApiError),
+    )
+)]
+#[delete("/jobs/{id}")]
+async fn cancel_job(path: web::Path<String>, jobs: web::Data<Jobs>, user: User) -> impl Responder {
+    let id = path.into_inner();
+    match jobs.get(&user.name, &id) {
+        Some(job) if job.cancel() => HttpResponse::Accepted().json(job.status(&id)),
+        Some(_) => error_response(ApiError::new(ErrorCode::JobFinished, "Job already finished")),
+        None => error_response(ApiError::new(ErrorCode::JobNotFound, "Job not found")),
+    }
+}
+
+// Get how much every user is using: the files in their images and
+// secrets folders, and their requests since the service started.
+// Only for admins.
+#[utoipa::path(
+    context_path = "/api/v1",
+    responses(
+        (status = 200, description = "Usage of every user", body = [UserUsage]),
+        (status = 403, description = "Not an admin", body = ApiError),
+    )
+)]
+#[get("/admin/usage")]
+async fn admin_usage(user: User, accounts: web::Data<Accounts>) -> impl Responder {
+    if !user.is_admin() {
+        return error_response(ApiError::new(ErrorCode::AdminOnly, "Only admins can view usage"));
+    }
+    // Reading the folders is blocking.
+    let accounts = accounts.into_inner();
+    match web::block(move || accounts.usage()).await {
+        Ok(usage) => HttpResponse::Ok().json(usage),
+        Err(_) => error_response(ApiError::new(ErrorCode::ProcessingFailed, "Processing failed")),
+    }
+}
+
+// Get the OpenAPI document for the API.
+#[utoipa::path(
+    context_path = "/api/v1",
+    responses((status = 200, description = "OpenAPI document", content_type = "application/json"))
+)]
+#[get("/openapi.json")]
+async fn openapi_document() -> impl Responder {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
@@ -0,0 +1,161 @@
+// Archives of extracted files.
+//
+// Extracted files can be downloaded together as a single zip or tar
+// archive, rather than one at a time. Each file is stored under its
+// embedded name, so any directories are kept, along with its modification
+// time and Unix permissions if they were recorded.
+//
+// Files can be archived from where they were extracted to in the secrets
+// folder, or straight from memory when extracted to memory, in which case
+// they are never written to disk.
+
+use std::fs;
+use std::io::{self, Cursor, Write};
+use std::path::Path;
+
+use chrono::{Datelike, Timelike};
+use tar::{Builder, Header};
+use zip::write::SimpleFileOptions;
+use zeroize::Zeroizing;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::steg::file_tree::{self, FileMeta};
+use crate::steg::Steganography;
+
+// Archive formats.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+}
+
+impl ArchiveFormat {
+    // Get the archive format from its name, or a file name ending in it.
+    pub fn from_name(name:&str) -> Option<Self> {
+        let name = name.to_ascii_lowercase();
+        if name == "zip" || name.ends_with(".zip") {
+            Some(ArchiveFormat::Zip)
+        }
+        else if name == "tar" || name.ends_with(".tar") {
+            Some(ArchiveFormat::Tar)
+        }
+        else {
+            None
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "application/zip",
+            ArchiveFormat::Tar => "application/x-tar",
+        }
+    }
+
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "extracted.zip",
+            ArchiveFormat::Tar => "extracted.tar",
+        }
+    }
+}
+
+// Struct to hold a file to add to an archive, with the name to store it as.
+#[derive(Clone, Debug)]
+pub struct ArchiveEntry {
+    pub name: String,
+    pub data: Zeroizing<Vec<u8>>,
+    pub meta: Option<FileMeta>,
+}
+
+// Helper function to get the files extracted from an image, ready to
+// archive. Files are taken from memory if extracted to memory, otherwise
+// read back from the secrets folder.
+pub fn extracted_entries(steg:&Steganography) -> io::Result<Vec<ArchiveEntry>> {
+    if steg.in_memory() {
+        return steg.memory_files
+            .iter()
+            .map(|file| {
+                // Embedded names are checked just as when writing to disk.
+                let name = file_tree::safe_relative_path(&file.file_name)
+                    .ok_or_else(|| io::Error::other(format!("Invalid file name: {}", file.file_name)))?;
+                Ok(ArchiveEntry {
+                    name: name.to_string_lossy().replace('\\', "/"),
+                    data: Zeroizing::new(file.data.clone()),
+                    meta: file.meta,
+                })
+            })
+            .collect();
+    }
+
+    steg.embedded_files
+        .iter()
+        .map(|file| {
+            let path = Path::new(&file.file_name);
+            let name = path
+                .strip_prefix(&steg.settings.secret_folder)
+                .unwrap_or_else(|_| Path::new(path.file_name().unwrap_or_default()))
+                .to_string_lossy()
+                .replace('\\', "/");
+            Ok(ArchiveEntry {
+                name,
+                data: Zeroizing::new(fs::read(path)?),
+                meta: Some(file_tree::read_meta(&file.file_name)?),
+            })
+        })
+        .collect()
+}
+
+// Helper function to build an archive of files in memory.
+pub fn build_archive(format:ArchiveFormat, entries:&[ArchiveEntry]) -> io::Result<Vec<u8>> {
+    match format {
+        ArchiveFormat::Zip => zip_entries(entries),
+        ArchiveFormat::Tar => tar_entries(entries),
+    }
+}
+
+// Helper function to build a zip archive.
+fn zip_entries(entries:&[ArchiveEntry]) -> io::Result<Vec<u8>> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    for entry in entries {
+        let mut options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .large_file(entry.data.len() as u64 >= u32::MAX as u64);
+        if let Some(meta) = entry.meta {
+            options = options.unix_permissions(meta.permissions());
+            if let Some(modified) = zip_time(meta.modified) {
+                options = options.last_modified_time(modified);
+            }
+        }
+        zip.start_file(entry.name.as_str(), options).map_err(io::Error::other)?;
+        zip.write_all(&entry.data)?;
+    }
+    let cursor = zip.finish().map_err(io::Error::other)?;
+    Ok(cursor.into_inner())
+}
+
+// Helper function to convert a modification time, in seconds since the
+// Unix epoch, to a zip time. Zip times can't be before 1980.
+fn zip_time(modified:u64) -> Option<zip::DateTime> {
+    let time = chrono::DateTime::from_timestamp(modified as i64, 0)?;
+    zip::DateTime::from_date_and_time(
+        time.year().try_into().ok()?,
+        time.month() as u8,
+        time.day() as u8,
+        time.hour() as u8,
+        time.minute() as u8,
+        time.second() as u8,
+    ).ok()
+}
+
+// Helper function to build a tar archive.
+fn tar_entries(entries:&[ArchiveEntry]) -> io::Result<Vec<u8>> {
+    let mut tar = Builder::new(Vec::new());
+    for entry in entries {
+        let mut header = Header::new_gnu();
+        header.set_size(entry.data.len() as u64);
+        header.set_mode(entry.meta.map(|meta| meta.permissions()).unwrap_or(0o644));
+        header.set_mtime(entry.meta.map(|meta| meta.modified).unwrap_or(0));
+        tar.append_data(&mut header, &entry.name, entry.data.as_slice())?;
+    }
+    tar.into_inner()
+}
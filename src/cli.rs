@@ -0,0 +1,324 @@
+// Command line interface.
+//
+// With no arguments the application runs the web service. Otherwise the
+// first argument is a command to run from the command line:
+//
+// keygen <name> : generate a key pair for public key recipients, saved
+//                 as <name>.key (private key) and <name>.pub (public key).
+// signkey <name> : generate a signing key pair, saved as <name>.sign.key
+//                  (private key) and <name>.sign.pub (public key).
+// rekey <image> [output] : change the password of a pic coded image. The
+//                          old and new passwords are read from standard
+//                          input, one per line. The image is overwritten
+//                          unless an output file is given.
+// embed <image> <output> <files...> : embed files, and directories with
+//                                     all the files in them, into an image.
+//                                     The password is read from standard
+//                                     input, empty for no password.
+// extract <image> [archive] : extract the files from an image into the
+//                             secrets folder, recreating any directories.
+//                             The password is read from standard input,
+//                             empty for no password. If an archive ending
+//                             in .zip or .tar is given, the files are
+//                             extracted to memory and saved in it instead,
+//                             never touching disk. An archive of "-" writes
+//                             a tar to standard output.
+//
+// Embedding and extraction can be cancelled with Ctrl-C, leaving no output
+// image, or partly extracted files, behind. A second Ctrl-C quits at once.
+// adduser <name> [admin] : add a user to the users file, or change the
+//                          password and role of one already there. The
+//                          password is read from standard input.
+// deluser <name> : remove a user from the users file.
+// token <name> [admin] : make a new API token for a user, printing the
+//                        token, and the entry to add to api_tokens in
+//                        settings, which only holds the token's hash.
+// bench [width] [height] [bytes] : time embedding and extracting random
+//                                  data in a random image, against the
+//                                  original pixel at a time methods.
+//                                  Defaults to a 24 megapixel image and
+//                                  1 MiB of data.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, Write};
+
+use crate::accounts::{self, Role, UserEntry};
+use crate::archive::{self, ArchiveFormat};
+use crate::steg::{bench, recipients, signing, Steganography};
+use crate::SETTINGS;
+
+// Helper function to run a command line command.
+pub fn run(args:&[String]) -> io::Result<()> {
+    match args.first().map(|s| s.as_str()) {
+        Some("keygen") => match args.get(1) {
+            Some(name) => keygen(name),
+            None => usage(),
+        },
+        Some("signkey") => match args.get(1) {
+            Some(name) => signkey(name),
+            None => usage(),
+        },
+        Some("rekey") => match args.get(1) {
+            Some(image) => rekey(image, args.get(2).map(|s| s.as_str()).unwrap_or("")),
+            None => usage(),
+        },
+        Some("embed") if args.len() > 3 => embed(&args[1], &args[2], &args[3..]),
+        Some("extract") => match args.get(1) {
+            Some(image) => extract(image, args.get(2).map(|s| s.as_str())),
+            None => usage(),
+        },
+        Some("adduser") => match args.get(1) {
+            Some(name) => add_user(name, parse_role(args.get(2))?),
+            None => usage(),
+        },
+        Some("deluser") => match args.get(1) {
+            Some(name) => remove_user(name),
+            None => usage(),
+        },
+        Some("token") => match args.get(1) {
+            Some(name) => new_token(name, parse_role(args.get(2))?),
+            None => usage(),
+        },
+        Some("bench") => {
+            let width = parse_arg(args.get(1), 6000)?;
+            let height = parse_arg(args.get(2), 4000)?;
+            let data_len = parse_arg(args.get(3), 1 << 20)?;
+            bench::run_benchmark(width as u32, height as u32, data_len)
+        }
+        _ => usage(),
+    }
+}
+
+// Helper function to print the command line usage.
+fn usage() -> io::Result<()> {
+    eprintln!("Usage: peekaboo [command]");
+    eprintln!();
+    eprintln!("With no command the web service is started.");
+    eprintln!();
+    eprintln!("Commands:");
+    eprintln!("  keygen <name>    Generate <name>.key and <name>.pub key files");
+    eprintln!("  signkey <name>   Generate <name>.sign.key and <name>.sign.pub signing key files");
+    eprintln!("  rekey <image> [output]");
+    eprintln!("                   Change the password of an image, reading the old");
+    eprintln!("                   and new passwords from standard input");
+    eprintln!("  embed <image> <output> <files...>");
+    eprintln!("                   Embed files and directories into an image, reading");
+    eprintln!("                   the password from standard input");
+    eprintln!("  extract <image> [archive]");
+    eprintln!("                   Extract the files from an image, reading the");
+    eprintln!("                   password from standard input, optionally into a");
+    eprintln!("                   .zip or .tar archive, or a tar on standard output (-)");
+    eprintln!("  adduser <name> [admin]");
+    eprintln!("                   Add or change a user in the users file, reading");
+    eprintln!("                   the password from standard input");
+    eprintln!("  deluser <name>   Remove a user from the users file");
+    eprintln!("  token <name> [admin]");
+    eprintln!("                   Make an API token, printing the entry for settings");
+    eprintln!("  bench [width] [height] [bytes]");
+    eprintln!("                   Time embedding and extracting against the original methods");
+    Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid command"))
+}
+
+// Helper function to generate a recipient key pair and save it.
+fn keygen(name:&str) -> io::Result<()> {
+    let (private_key, public_key) = recipients::generate_keypair();
+    write_key_files(name, "", &private_key, &public_key)
+}
+
+// Helper function to generate a signing key pair and save it.
+fn signkey(name:&str) -> io::Result<()> {
+    let (private_key, public_key) = signing::generate_signing_key();
+    write_key_files(name, ".sign", &private_key, &public_key)
+}
+
+// Helper function to save a key pair as hex digits.
+// The private key file is only readable by the owner,
+// and existing key files are never overwritten.
+fn write_key_files(name:&str, kind:&str, private_key:&[u8], public_key:&[u8]) -> io::Result<()> {
+    let private_path = format!("{}{}.key", name, kind);
+    let public_path = format!("{}{}.pub", name, kind);
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut private_file = options.open(&private_path)?;
+    writeln!(private_file, "{}", recipients::key_to_hex(private_key))?;
+
+    let mut public_file = OpenOptions::new().write(true).create_new(true).open(&public_path)?;
+    writeln!(public_file, "{}", recipients::key_to_hex(public_key))?;
+
+    println!("Private key: {}", private_path);
+    println!("Public key:  {}", public_path);
+    Ok(())
+}
+
+// Helper function to change the password of an image.
+// An empty new password removes password protection.
+fn rekey(image:&str, output:&str) -> io::Result<()> {
+    let mut steg = load_image(image)?;
+    let old_pw = read_line("Old password: ")?;
+    let new_pw = read_line("New password: ")?;
+    steg.rekey(&old_pw, &new_pw).map_err(io::Error::other)?;
+    steg.save_image(output.to_string());
+    println!("Password changed: {}", if output.is_empty() { image } else { output });
+    Ok(())
+}
+
+// Helper function to embed files and directories into an image.
+fn embed(image:&str, output:&str, files:&[String]) -> io::Result<()> {
+    let mut steg = load_image(image)?;
+    let pw = read_line("Password: ")?;
+    let files_ref: Vec<&str> = files.iter().map(|s| s.as_str()).collect();
+    cancel_on_ctrl_c(&steg)?;
+    steg.embed_files(!pw.is_empty(), &pw, &files_ref)?;
+    steg.save_image(output.to_string());
+    println!("Files embedded: {}", output);
+    Ok(())
+}
+
+// Helper function to extract the files from an image,
+// either to the secrets folder or into an archive.
+fn extract(image:&str, archive:Option<&str>) -> io::Result<()> {
+    let format = match archive {
+        Some("-") => Some(ArchiveFormat::Tar),
+        Some(name) => Some(ArchiveFormat::from_name(name).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("Archive must end in .zip or .tar: {}", name))
+        })?),
+        None => None,
+    };
+
+    let mut steg = load_image(image)?;
+    let pw = read_line("Password: ")?;
+    cancel_on_ctrl_c(&steg)?;
+    steg.extract_to_memory = format.is_some();
+    steg.extract_data(pw).map_err(io::Error::other)?;
+
+    let (Some(archive), Some(format)) = (archive, format) else {
+        for file in &steg.embedded_files {
+            println!("Extracted: {}", file.file_name);
+        }
+        return Ok(());
+    };
+
+    let entries = archive::extracted_entries(&steg)?;
+    let bytes = archive::build_archive(format, &entries)?;
+    if archive == "-" {
+        io::stdout().lock().write_all(&bytes)?;
+    }
+    else {
+        fs::write(archive, &bytes)?;
+    }
+    // Report to standard error, as the archive may be on standard output.
+    for entry in &entries {
+        eprintln!("Archived: {}", entry.name);
+    }
+    Ok(())
+}
+
+// Helper function to cancel the embedding or extraction on Ctrl-C.
+// If it has already been cancelled, quit straight away.
+fn cancel_on_ctrl_c(steg:&Steganography) -> io::Result<()> {
+    let progress = steg.progress.clone();
+    ctrlc::set_handler(move || {
+        if progress.is_cancelled() {
+            std::process::exit(130);
+        }
+        eprintln!("Cancelling...");
+        progress.cancel();
+    }).map_err(io::Error::other)
+}
+
+// Helper function to load an image to work on.
+fn load_image(image:&str) -> io::Result<Steganography> {
+    let mut steg = Steganography::init();
+    steg.load_new_file(image.to_string());
+    if !steg.img_to_proc {
+        return Err(io::Error::other(format!("Unable to load image: {}", image)));
+    }
+    Ok(steg)
+}
+
+// Helper function to parse an optional role argument, a user by default.
+fn parse_role(arg:Option<&String>) -> io::Result<Role> {
+    match arg.map(|s| s.as_str()) {
+        None | Some("user") => Ok(Role::User),
+        Some("admin") => Ok(Role::Admin),
+        Some(arg) => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid role: {}", arg))),
+    }
+}
+
+// Helper function to check a user name can be used.
+fn check_name(name:&str) -> io::Result<()> {
+    if accounts::valid_name(name) {
+        Ok(())
+    }
+    else {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid user name: {}", name)))
+    }
+}
+
+// Helper function to add a user to the users file,
+// or change the password and role of one already there.
+fn add_user(name:&str, role:Role) -> io::Result<()> {
+    check_name(name)?;
+    let settings = SETTINGS.lock().unwrap().clone();
+    let password = read_line("Password: ")?;
+    if password.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Password is empty"));
+    }
+    let entry = UserEntry {
+        name: name.to_string(),
+        role,
+        password: accounts::hash_password(&password, settings.kdf_iterations),
+    };
+    let mut users = accounts::load_users(&settings.users_file)?;
+    match users.iter_mut().find(|user| user.name == name) {
+        Some(user) => *user = entry,
+        None => users.push(entry),
+    }
+    accounts::save_users(&settings.users_file, &users)?;
+    println!("User saved: {}", settings.users_file);
+    Ok(())
+}
+
+// Helper function to remove a user from the users file.
+fn remove_user(name:&str) -> io::Result<()> {
+    let users_file = SETTINGS.lock().unwrap().users_file.clone();
+    let mut users = accounts::load_users(&users_file)?;
+    let count = users.len();
+    users.retain(|user| user.name != name);
+    if users.len() == count {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("No such user: {}", name)));
+    }
+    accounts::save_users(&users_file, &users)?;
+    println!("User removed: {}", users_file);
+    Ok(())
+}
+
+// Helper function to make a new API token for a user.
+fn new_token(name:&str, role:Role) -> io::Result<()> {
+    check_name(name)?;
+    let token = accounts::new_token();
+    let role = if role == Role::Admin { "admin" } else { "user" };
+    println!("Token: {}", token);
+    println!("Add to api_tokens in settings:");
+    println!("  - {{ name: \"{}\", role: \"{}\", token_sha256: \"{}\" }}", name, role, accounts::hash_token(&token));
+    Ok(())
+}
+
+// Helper function to parse an optional number argument.
+fn parse_arg(arg:Option<&String>, default:usize) -> io::Result<usize> {
+    match arg {
+        Some(arg) => arg.parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid number: {}", arg))),
+        None => Ok(default),
+    }
+}
+
+// Helper function to prompt for, and read, a line from standard input.
+fn read_line(prompt:&str) -> io::Result<String> {
+    eprint!("{}", prompt);
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line)?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
@@ -0,0 +1,245 @@
+// Download links.
+//
+// Extracted files and embedded images are never served from a directory
+// listing. Instead each file is given a download link of its own, with an
+// unguessable token, that only works for the session it was made for, and
+// only for (link_keep_secs) seconds.
+//
+// In memory only mode the files are never on disk, so a link holds the
+// file itself, which is wiped from memory once the link expires. Expired
+// links are forgotten every (link_keep_secs) seconds, at most a minute.
+//
+// A session is identified by a random cookie, which is handed out with the
+// response to any request that doesn't already have one.
+
+use actix_files::NamedFile;
+use actix_web::body::MessageBody;
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{get, web, Error, FromRequest, HttpMessage, HttpRequest, HttpResponse, Responder};
+use bytes::Bytes;
+use futures::future::{ready, Ready};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use zeroize::Zeroizing;
+
+use crate::steg::container::{self, ContainerFile};
+use crate::steg::get_mime_type;
+
+// Name of the session cookie.
+pub const SESSION_COOKIE: &str = "peekaboo_session";
+
+// Helper function to make a new random token, as hex digits.
+pub fn new_token() -> String {
+    container::random_bytes(16).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Helper function to check a token is one that could have been made here.
+fn valid_token(token: &str) -> bool {
+    token.len() == 32 && token.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+// Session ID of a request.
+#[derive(Clone, Debug)]
+pub struct Session(pub String);
+
+impl FromRequest for Session {
+    type Error = Error;
+    type Future = Ready<Result<Self, Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(req.extensions().get::<Session>().cloned().ok_or_else(|| actix_web::error::ErrorInternalServerError("No session")))
+    }
+}
+
+// Helper function to make the session cookie for a session.
+pub fn session_cookie(id: String) -> Cookie<'static> {
+    Cookie::build(SESSION_COOKIE, id)
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Strict)
+        .finish()
+}
+
+// Helper function to give every request a session, setting the session
+// cookie on the response if the request didn't have one, and the
+// response doesn't start a new session itself.
+pub async fn ensure_session(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let existing = req.cookie(SESSION_COOKIE)
+        .map(|cookie| cookie.value().to_string())
+        .filter(|id| valid_token(id));
+    let id = existing.clone().unwrap_or_else(new_token);
+    req.extensions_mut().insert(Session(id.clone()));
+
+    let mut res = next.call(req).await?;
+    let new_session = res.response().cookies().any(|cookie| cookie.name() == SESSION_COOKIE);
+    if existing.is_none() && !new_session {
+        res.response_mut().add_cookie(&session_cookie(id))?;
+    }
+    Ok(res)
+}
+
+// What a download link is for, a file on disk or a file held in memory.
+#[derive(Clone)]
+pub enum LinkContent {
+    File(PathBuf),
+    Memory { file_name: String, data: Arc<Zeroizing<Vec<u8>>> },
+}
+
+// Struct to hold a download link, what it is for, and who can use it.
+struct Link {
+    session: String,
+    content: LinkContent,
+    created: Instant,
+}
+
+// Struct to hold all the download links, by token.
+pub struct Downloads {
+    links: Mutex<HashMap<String, Link>>,
+    keep: Duration,
+}
+
+impl Downloads {
+    pub fn new(keep_secs: u64) -> Self {
+        Downloads {
+            links: Mutex::new(HashMap::new()),
+            keep: Duration::from_secs(keep_secs),
+        }
+    }
+
+    // Forget any links that expired, wiping any files they held in memory
+    // unless they are still being downloaded.
+    pub fn prune(&self) {
+        self.links.lock().unwrap().retain(|_, link| link.created.elapsed() <= self.keep);
+    }
+
+    // Make a download link for a session,
+    // forgetting any links that expired.
+    pub fn add(&self, session: &str, content: LinkContent) -> String {
+        let token = new_token();
+        self.prune();
+        let mut links = self.links.lock().unwrap();
+        links.insert(token.clone(), Link { session: session.to_string(), content, created: Instant::now() });
+        format!("/download/{}", token)
+    }
+
+    // Make a download link to a file on disk for a session.
+    pub fn link(&self, session: &str, path: impl AsRef<Path>) -> String {
+        self.add(session, LinkContent::File(path.as_ref().to_path_buf()))
+    }
+
+    // Get what a download link is for, if it was made for the
+    // session and hasn't expired, forgetting any links that expired.
+    pub fn get(&self, session: &str, token: &str) -> Option<LinkContent> {
+        self.prune();
+        let links = self.links.lock().unwrap();
+        links.get(token)
+            .filter(|link| link.session == session)
+            .map(|link| link.content.clone())
+    }
+}
+
+// Helper function to forget expired links every (link_keep_secs) seconds,
+// or every minute if that is longer, so that files held in memory
+// don't outlive their links by long.
+pub async fn run_expiry(downloads: web::Data<Downloads>) {
+    let period = downloads.keep.clamp(Duration::from_secs(1), Duration::from_secs(60));
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        downloads.prune();
+    }
+}
+
+// Struct to make download links for the session of a request, which can
+// be moved into a steganography job.
+#[derive(Clone)]
+pub struct SessionLinks {
+    downloads: web::Data<Downloads>,
+    session: String,
+}
+
+impl SessionLinks {
+    // Make a download link to a file for the session.
+    pub fn link(&self, path: impl AsRef<Path>) -> String {
+        self.downloads.link(&self.session, path)
+    }
+
+    // Make a download link to a file extracted to memory for the session,
+    // moving the file's data behind the link.
+    pub fn link_extracted(&self, file: &mut ContainerFile) -> String {
+        let file_name = Path::new(&file.file_name).file_name().unwrap_or_default().to_string_lossy().into_owned();
+        self.link_data(&file_name, Zeroizing::new(std::mem::take(&mut file.data)))
+    }
+
+    // Make a download link to a file held in memory for the session.
+    pub fn link_data(&self, file_name: &str, data: Zeroizing<Vec<u8>>) -> String {
+        self.downloads.add(&self.session, LinkContent::Memory { file_name: file_name.to_string(), data: Arc::new(data) })
+    }
+}
+
+impl FromRequest for SessionLinks {
+    type Error = Error;
+    type Future = Ready<Result<Self, Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let session = req.extensions().get::<Session>().cloned();
+        let downloads = req.app_data::<web::Data<Downloads>>().cloned();
+        ready(match (session, downloads) {
+            (Some(session), Some(downloads)) => Ok(SessionLinks { downloads, session: session.0 }),
+            _ => Err(actix_web::error::ErrorInternalServerError("No session")),
+        })
+    }
+}
+
+// Download a file by its download link.
+// The file is sent under its own name.
+#[get("/download/{token}")]
+pub async fn download(
+    req: HttpRequest,
+    path: web::Path<String>,
+    session: Session,
+    downloads: web::Data<Downloads>,
+) -> impl Responder {
+    // Links that don't exist, have expired, or belong to another session
+    // all look the same.
+    let token = path.into_inner();
+    match downloads.get(&session.0, &token) {
+        Some(LinkContent::File(path)) => match NamedFile::open(path) {
+            Ok(file) => file.into_response(&req),
+            Err(_) => not_found(),
+        },
+        Some(LinkContent::Memory { file_name, data }) => {
+            // Shown in the browser if it can be, like files from disk.
+            let extension = Path::new(&file_name).extension().unwrap_or_default().to_string_lossy().to_lowercase();
+            let content_type = get_mime_type(&extension);
+            let disposition = if content_type.starts_with("image/") || content_type.starts_with("text/") { "inline" } else { "attachment" };
+            HttpResponse::Ok()
+                .content_type(content_type)
+                .insert_header(("Content-Disposition", format!("{}; filename=\"{}\"", disposition, file_name.replace(['"', '\\'], "_"))))
+                .body(Bytes::from_owner(SharedData(data)))
+        }
+        None => not_found(),
+    }
+}
+
+// Struct to send a file held in memory straight from its link,
+// without copying it to a buffer that wouldn't be wiped.
+struct SharedData(Arc<Zeroizing<Vec<u8>>>);
+
+impl AsRef<[u8]> for SharedData {
+    fn as_ref(&self) -> &[u8] {
+        self.0.as_slice()
+    }
+}
+
+// Helper function to respond that a download wasn't found.
+fn not_found() -> HttpResponse {
+    HttpResponse::NotFound().json(HashMap::from([("error", "Download not found")]))
+}
@@ -0,0 +1,160 @@
+// Background jobs.
+//
+// Long embeds and extractions can be run as background jobs, with the job
+// ID returned straight away instead of waiting for the result. The job's
+// progress, and its result once finished, are kept here so they can be
+// polled, or streamed to the UI as server-sent events.
+//
+// Finished jobs are kept for (job_keep_secs) seconds, so the result can
+// still be collected, and then forgotten.
+//
+// A running job can be cancelled, in which case it stops at the next
+// chunk and finishes with a 409 Conflict result.
+//
+// Jobs belong to the user that started them, and can't be seen or
+// cancelled by anyone else.
+
+use actix_web::http::StatusCode;
+use log::info;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::steg::container;
+use crate::steg::progress::Progress;
+use utoipa::ToSchema;
+
+// Json map of a response, and the status to respond with.
+pub type JobResponse = (StatusCode, HashMap<&'static str, String>);
+
+// Json body of a finished job's response, and the status it responded with.
+pub type JobResult = (StatusCode, serde_json::Value);
+
+// State of a background job.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+// Struct to hold the status of a background job, for reporting.
+// The response status and result are only included once it has finished.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct JobStatus {
+    pub id: String,
+    pub kind: String,
+    pub state: JobState,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    pub file: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<serde_json::Value>,
+}
+
+// Struct to hold a background job, its progress, and its result.
+pub struct Job {
+    pub user: String,
+    pub kind: String,
+    pub progress: Arc<Progress>,
+    result: Mutex<Option<(JobResult, Instant)>>,
+}
+
+impl Job {
+    // Record the result of the job once it has finished.
+    pub fn finish(&self, result: JobResult) {
+        *self.result.lock().unwrap() = Some((result, Instant::now()));
+    }
+
+    // Check if the job has finished.
+    pub fn is_finished(&self) -> bool {
+        self.result.lock().unwrap().is_some()
+    }
+
+    // Cancel the job, if it is still running.
+    // Returns false if the job had already finished.
+    pub fn cancel(&self) -> bool {
+        if self.is_finished() {
+            return false;
+        }
+        self.progress.cancel();
+        true
+    }
+
+    // Get the status of the job, including the result if finished.
+    pub fn status(&self, id: &str) -> JobStatus {
+        let progress = self.progress.report();
+        let mut status = JobStatus {
+            id: id.to_string(),
+            kind: self.kind.clone(),
+            state: JobState::Running,
+            bytes_done: progress.bytes_done,
+            bytes_total: progress.bytes_total,
+            file: progress.current_file,
+            status: None,
+            result: None,
+        };
+        if let Some(((code, response_data), _)) = &*self.result.lock().unwrap() {
+            status.state = if code.is_success() {
+                JobState::Done
+            } else if *code == StatusCode::CONFLICT && self.progress.is_cancelled() {
+                JobState::Cancelled
+            } else {
+                JobState::Failed
+            };
+            status.status = Some(code.as_u16());
+            status.result = Some(response_data.clone());
+        }
+        status
+    }
+
+    // Check if the job finished long enough ago to be forgotten.
+    fn expired(&self, keep: Duration) -> bool {
+        match &*self.result.lock().unwrap() {
+            Some((_, finished)) => finished.elapsed() > keep,
+            None => false,
+        }
+    }
+}
+
+// Struct to hold all the background jobs, by job ID.
+pub struct Jobs {
+    jobs: Mutex<HashMap<String, Arc<Job>>>,
+    keep: Duration,
+}
+
+impl Jobs {
+    pub fn new(keep_secs: u64) -> Self {
+        Jobs {
+            jobs: Mutex::new(HashMap::new()),
+            keep: Duration::from_secs(keep_secs),
+        }
+    }
+
+    // Add a new job for a user, forgetting any jobs that expired.
+    // Returns the job ID and the job.
+    pub fn add(&self, user: &str, kind: &str) -> (String, Arc<Job>) {
+        let id: String = container::random_bytes(16).iter().map(|b| format!("{:02x}", b)).collect();
+        let job = Arc::new(Job {
+            user: user.to_string(),
+            kind: kind.to_string(),
+            progress: Arc::new(Progress::new()),
+            result: Mutex::new(None),
+        });
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.retain(|_, job| !job.expired(self.keep));
+        jobs.insert(id.clone(), job.clone());
+        info!("Started {} job: {}", kind, id);
+        (id, job)
+    }
+
+    // Get a user's job by job ID.
+    pub fn get(&self, user: &str, id: &str) -> Option<Arc<Job>> {
+        self.jobs.lock().unwrap().get(id).filter(|job| job.user == user).cloned()
+    }
+}
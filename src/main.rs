@@ -1,15 +1,16 @@
 // Steganography application.
 
-use log::info;
-use log4rs;
-use actix_files as fsx;
+use log::{error, info, warn};
 use actix_multipart::Multipart;
-use actix_web::{get, post, web, App, HttpServer, HttpResponse, Responder};
+use actix_web::error::BlockingError;
+use actix_web::http::StatusCode;
+use actix_web::{delete, get, post, web, App, HttpServer, HttpResponse, Responder};
 use chrono::Utc;
-use futures_util::stream::{StreamExt, TryStreamExt};
+use futures_util::stream::TryStreamExt;
 use lazy_static::lazy_static;
+use serde::Serialize;
 use std::fs;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::env::temp_dir;
 use std::fs::create_dir_all;
 use std::fs::File as StdFile;
@@ -20,10 +21,28 @@ use std::sync::{Arc, Mutex};
 use sanitize_filename::sanitize;
 use tokio::fs::File;
 use tokio::io::AsyncReadExt;
+use tokio::sync::Semaphore;
+use zeroize::Zeroizing;
 
+use crate::accounts::{Accounts, User};
+use crate::archive::ArchiveFormat;
+use crate::downloads::{Downloads, SessionLinks};
+use crate::jobs::{JobResponse, JobResult, Jobs};
 use crate::settings::Settings;
-use crate::steg::Steganography;
+use crate::steg::{Steganography, SteganographyError};
+use crate::steg::progress::Progress;
+use crate::steg::{container, recipients, signing};
+use crate::steg::edit::FileSection;
+use crate::steg::file_tree::{self, SourceFile};
+use crate::steg::volumes::Volume;
 
+pub mod accounts;
+pub mod api;
+pub mod archive;
+pub mod cli;
+pub mod downloads;
+pub mod jobs;
+pub mod retention;
 pub mod settings;
 pub mod steg;
 
@@ -40,6 +59,99 @@ lazy_static! {
         let settings: Settings = serde_yaml::from_str(&contents).expect("Unable to parse YAML");
         Mutex::new(settings)
     };
+
+    // Slots for steganography jobs, so that only (max_jobs) jobs
+    // can be running on the blocking thread pool at once.
+    static ref JOB_SLOTS: Semaphore = Semaphore::new(SETTINGS.lock().unwrap().max_jobs.max(1) as usize);
+}
+
+// Steganography instance shared by the request handlers.
+// Locked asynchronously, so requests waiting their turn don't
+// hold up the async workers.
+type SharedSteg = Arc<tokio::sync::Mutex<Steganography>>;
+
+// Helper function to run steganography work on the blocking thread pool,
+// so the async workers stay free to answer other requests while an image
+// is loaded, embedded or extracted. Waits for a job slot, and then for
+// the steganography instance, without blocking.
+async fn run_steg_job<F, R>(steg: &SharedSteg, job: F) -> Result<R, BlockingError>
+where
+    F: FnOnce(&mut Steganography) -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let _slot = JOB_SLOTS.acquire().await.expect("Job slots closed");
+    let mut steg = steg.clone().lock_owned().await;
+    web::block(move || {
+        // Each job starts with its own progress, so a cancelled
+        // job doesn't stop the next one.
+        steg.progress = Arc::new(Progress::new());
+        job(&mut steg)
+    }).await
+}
+
+// Helper function to run steganography work on the blocking thread pool
+// with a steganography instance of its own, for requests that bring their
+// own image rather than working on the loaded image. In memory only mode
+// the instance is wiped once done with.
+async fn run_own_steg_job<F, R>(job: F) -> Result<R, BlockingError>
+where
+    F: FnOnce(&mut Steganography) -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let _slot = JOB_SLOTS.acquire().await.expect("Job slots closed");
+    web::block(move || {
+        let mut steg = Steganography::init();
+        let result = job(&mut steg);
+        if steg.settings.memory_only {
+            steg.wipe_memory();
+        }
+        result
+    }).await
+}
+
+// Helper function to run a steganography job for a user, either waiting
+// for the result, or in the background, responding straight away with a
+// job ID that the progress and result can be got from.
+async fn respond_to_job<F, T>(user: &User, jobs: &Jobs, background: bool, kind: &str, job: F) -> HttpResponse
+where
+    F: FnOnce(&mut Steganography) -> (StatusCode, T) + Send + 'static,
+    T: Serialize + Send + 'static,
+{
+    if !background {
+        return job_response(run_steg_job(&user.steg, job).await);
+    }
+
+    // Job reports its progress to the job entry.
+    let (id, job_entry) = jobs.add(&user.name, kind);
+    let steg = user.steg.clone();
+    actix_web::rt::spawn(async move {
+        let progress = job_entry.progress.clone();
+        let result = run_steg_job(&steg, move |steg| {
+            steg.progress = progress;
+            job(steg)
+        }).await;
+        job_entry.finish(job_result(result));
+    });
+    HttpResponse::Accepted().json(HashMap::from([("job", id)]))
+}
+
+// Helper function to get the status and json body of the result of a
+// steganography job.
+fn job_result<T: Serialize>(result: Result<(StatusCode, T), BlockingError>) -> JobResult {
+    match result {
+        Ok((status, response_data)) => (status, serde_json::to_value(response_data).unwrap_or_default()),
+        Err(e) => {
+            // Job panicked, so there is no result to respond with.
+            error!("Steganography job failed: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, serde_json::json!({"code": "processing_failed", "error": "Processing failed"}))
+        }
+    }
+}
+
+// Helper function to respond with the result of a steganography job.
+fn job_response<T: Serialize>(result: Result<(StatusCode, T), BlockingError>) -> HttpResponse {
+    let (status, response_data) = job_result(result);
+    HttpResponse::build(status).json(response_data)
 }
 
 #[get("/")]
@@ -48,213 +160,1230 @@ async fn intro() -> impl Responder {
 }
 
 #[post("/upload")]
-async fn upload(mut payload: Multipart, steg: web::Data<Arc<Mutex<Steganography>>>,) -> impl Responder {
-    // Get steg instance in scope.
-    let steg = steg.clone();
+async fn upload(mut payload: Multipart, user: User) -> impl Responder {
+    // Get the user's settings in scope.
+    let settings: Settings = user.settings.clone();
 
-    // Get application settings in scope.
-    let settings: Settings = SETTINGS.lock().unwrap().clone();
-
-    // Json map of response to upload request
-    // following analysis by Steganography methods.
-    let mut response_data = HashMap::new();
+    // Uploaded images, to be loaded once the upload is complete.
+    // In memory only mode they are held in memory instead.
+    let mut uploaded_files: Vec<String> = Vec::new();
+    let mut memory_uploads: Vec<(String, Zeroizing<Vec<u8>>)> = Vec::new();
 
     while let Ok(Some(mut field)) = payload.try_next().await {
-        let content_disposition = field.content_disposition();
-        if let Some(filename) = content_disposition.get_filename() {
-            let filepath = format!("{}/{}", settings.thumb_folder, sanitize(&filename));
-            let filepath_clone = filepath.clone();
-
-            // File::create is a blocking operation, use threadpool.
-            let mut f = web::block(move || StdFile::create(filepath)).await.unwrap().unwrap();
-
-            // Field in turn is stream of *Bytes* object.
-            while let Some(chunk) = field.next().await {
-                let data = chunk.unwrap();
-                // Filesystem operations are blocking, we have to use threadpool.
-                f = web::block(move || {
-                    let mut file = f;
-                    file.write_all(&data).map(|_| file)
-                }).await.unwrap().unwrap();
-            }
-
-            // Process the uploaded file with Steganography instance.
-            let mut steg = steg.lock().unwrap();
-        
+        let Some(filename) = field.content_disposition().get_filename().map(sanitize) else {
+            continue;
+        };
+        let result = if settings.memory_only {
+            read_upload(&mut field).await.map(|data| memory_uploads.push((filename, data)))
+        }
+        else {
+            // Images are kept under unique names, so uploads of
+            // images with the same name don't overwrite each other.
+            let file_path = unique_upload_path(&settings.thumb_folder, &filename);
+            save_upload(&mut field, &file_path).await.map(|path| uploaded_files.push(path))
+        };
+        if let Err(e) = result {
+            return bad_form_response(e);
+        }
+    }
+
+    // Process the uploaded files with Steganography instance.
+    let result = run_steg_job(&user.steg, move |steg| {
+        // Json map of response to upload request
+        // following analysis by Steganography methods.
+        let mut response_data = HashMap::new();
+
+        // The last image loaded is wiped before the next is loaded.
+        if settings.memory_only {
+            steg.wipe_memory();
+        }
+        let uploads = uploaded_files.into_iter().map(|filepath| (filepath, None))
+            .chain(memory_uploads.into_iter().map(|(filename, data)| (filename, Some(data))));
+        for (filepath, data) in uploads {
             // Load a file for analysis.
             // This includes whether or not it is coded.
-            steg.load_new_file(filepath_clone);
+            match data {
+                Some(data) => steg.load_image_bytes(filepath, &data),
+                None => steg.load_new_file(filepath),
+            }
 
             // Construct image file analysis results for display to the user.
             response_data.insert("coded", "False".to_string());
             response_data.insert("password", "False".to_string());
             response_data.insert("capacity", steg.embed_capacity.to_string());
             response_data.insert("overhead", steg.overhead_per_file.to_string());
-            if steg.pic_coded == true {
+            response_data.insert("volume_capacity", steg.volume_capacity().to_string());
+            if steg.pic_coded {
                 response_data.insert("coded", "True".to_string());
-                if steg.pic_has_pw == true {
+                if steg.pic_has_pw {
                     response_data.insert("password", "True".to_string());
                 }
+                if steg.pic_recipients {
+                    response_data.insert("recipients", "True".to_string());
+                }
+                if steg.pic_key_slots {
+                    response_data.insert("key_slots", "True".to_string());
+                }
+                if steg.is_editable() {
+                    response_data.insert("editable", "True".to_string());
+                }
+            }
+            else if steg.pic_unknown {
+                // Image may hold a headerless volume, which needs a password.
+                response_data.insert("coded", "Unknown".to_string());
+                response_data.insert("password", "True".to_string());
+            }
+
+            // Include details if image is part of a split set.
+            if let Some(part_info) = &steg.pic_part {
+                response_data.insert("set", part_info.set_id.clone());
+                response_data.insert("part", part_info.part_idx.to_string());
+                response_data.insert("parts", part_info.part_count.to_string());
             }
+
+            // Include details if image is a share of a threshold set.
+            if let Some(share_info) = &steg.pic_share {
+                response_data.insert("set", share_info.set_id.clone());
+                response_data.insert("share", share_info.share_idx.to_string());
+                response_data.insert("shares", share_info.share_count.to_string());
+                response_data.insert("threshold", share_info.threshold.to_string());
+            }
+        }
+        (StatusCode::OK, response_data)
+    }).await;
+    job_response(result)
+}
+
+// Helper function to get the file name from a path, to show in the UI
+// rather than where the file is kept.
+fn file_name_of(path: &str) -> String {
+    Path::new(path).file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+// Helper function to get the folder that uploaded files to embed are
+// saved to, in the system temporary folder, creating it if needed.
+// Kept apart so that retention only ever deletes our own files.
+fn upload_folder() -> PathBuf {
+    let folder = temp_dir().join("peekaboo-uploads");
+    if let Err(e) = create_dir_all(&folder) {
+        error!("Unable to create upload folder: {}", e);
+    }
+    folder
+}
+
+// Struct to hold a folder of its own in the upload folder for the files
+// uploaded by one request, so that uploads from different requests never
+// overwrite each other. The folder is only created when a file is saved
+// to it, and is securely deleted along with the files when it is dropped,
+// once the request is done with them, whether it worked or not.
+struct UploadFolder {
+    path: PathBuf,
+}
+
+impl UploadFolder {
+    fn new() -> Self {
+        let id: String = container::random_bytes(8).iter().map(|b| format!("{:02x}", b)).collect();
+        UploadFolder { path: upload_folder().join(id) }
+    }
+}
+
+impl Drop for UploadFolder {
+    fn drop(&mut self) {
+        if !self.path.exists() {
+            return;
+        }
+        if let Err(e) = file_tree::secure_delete_dir(&self.path) {
+            warn!("Failed to remove upload folder: {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+// Helper function to get a new file name for a saved image,
+// named from the current time.
+fn new_image_name() -> String {
+    let ts_string: String = Utc::now().to_string().chars().filter(|c| !c.is_whitespace()).collect();
+    format!("{}.png", ts_string)
+}
+
+// Helper function to get a new file path in the secrets folder
+// for a saved image, named from the current time.
+fn new_secret_path(settings: &Settings) -> String {
+    // Save in secrets folder from settings, creating it if needed.
+    let mut wrt_path = PathBuf::new();
+    wrt_path.push(&settings.secret_folder);
+    if !wrt_path.exists() {
+        create_dir_all(&wrt_path).unwrap();
+    }
+    wrt_path.push(new_image_name());
+    wrt_path.to_string_lossy().into_owned()
+}
+
+// Helper function to save an embedded or changed image as a new file in
+// the secrets folder, returning its file name and a download link to it.
+// In memory only mode the image is only kept in memory, behind the link.
+fn save_output_image(steg: &mut Steganography, settings: &Settings, links: &SessionLinks) -> std::io::Result<(String, String)> {
+    if settings.memory_only {
+        let file_name = new_image_name();
+        let bytes = Zeroizing::new(steg.image_png_bytes()?);
+        let link = links.link_data(&file_name, bytes);
+        return Ok((file_name, link));
+    }
+    let wrt_path_string = new_secret_path(settings);
+    steg.save_image(wrt_path_string.clone());
+    Ok((file_name_of(&wrt_path_string), links.link(&wrt_path_string)))
+}
+
+// Helper function to read an uploaded file into memory, for memory only
+// mode. The buffer is grown by hand, wiping the old buffer each time, so
+// that no copies of the file are left behind in memory.
+async fn read_upload(field: &mut actix_multipart::Field) -> std::io::Result<Zeroizing<Vec<u8>>> {
+    let mut data = Zeroizing::new(Vec::new());
+    while let Some(chunk) = field.try_next().await.map_err(std::io::Error::other)? {
+        if data.len() + chunk.len() > data.capacity() {
+            let mut grown = Zeroizing::new(Vec::with_capacity((data.len() + chunk.len()).max(data.capacity() * 2)));
+            grown.extend_from_slice(&data);
+            data = grown;
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(data)
+}
+
+// Helper function to read a text field of a multipart form.
+async fn read_text(field: &mut actix_multipart::Field) -> std::io::Result<String> {
+    let mut text = Vec::new();
+    while let Some(chunk) = field.try_next().await.map_err(std::io::Error::other)? {
+        text.extend_from_slice(&chunk);
+    }
+    String::from_utf8(text).map_err(std::io::Error::other)
+}
+
+// Helper function to save an uploaded file to the given path,
+// creating the folder it is in if needed. If the upload can't be
+// read in full, the part saved is removed.
+async fn save_upload(field: &mut actix_multipart::Field, file_path: &Path) -> std::io::Result<String> {
+    if let Some(folder) = file_path.parent() {
+        create_dir_all(folder)?;
+    }
+    let mut file = StdFile::create(file_path)?;
+    let written: std::io::Result<()> = async {
+        while let Some(chunk) = field.try_next().await.map_err(std::io::Error::other)? {
+            file.write_all(&chunk)?;
+        }
+        Ok(())
+    }.await;
+    if let Err(e) = written {
+        drop(file);
+        let _ = fs::remove_file(file_path);
+        return Err(e);
+    }
+    Ok(file_path.to_string_lossy().into_owned())
+}
+
+// Helper function to get a path in a folder for an uploaded image,
+// with a random prefix so that images with the same name don't
+// overwrite each other.
+fn unique_upload_path(folder: &str, filename: &str) -> PathBuf {
+    let prefix: String = container::random_bytes(4).iter().map(|b| format!("{:02x}", b)).collect();
+    Path::new(folder).join(format!("{}-{}", prefix, sanitize(filename)))
+}
+
+// Helper function to respond that a form couldn't be read.
+fn bad_form_response(e: std::io::Error) -> HttpResponse {
+    HttpResponse::BadRequest().json(HashMap::from([("error", format!("Invalid form: {}", e))]))
+}
+
+// Helper function to respond that a request needs files on disk,
+// so isn't available in memory only mode.
+fn memory_only_response() -> HttpResponse {
+    HttpResponse::BadRequest().json(HashMap::from([("error", "Not available in memory only mode")]))
+}
+
+// Helper function to get the list of extracted files for the UI,
+// with a download link to each file. Files extracted to memory are
+// moved behind their links.
+fn extracted_files(steg: &mut Steganography, links: &SessionLinks) -> Vec<HashMap<&'static str, String>> {
+    let mut files = Vec::new();
+    if steg.in_memory() {
+        for mut file in std::mem::take(&mut steg.memory_files) {
+            let extension = Path::new(&file.file_name).extension().unwrap_or_default().to_string_lossy().to_lowercase();
+            files.push(HashMap::from([
+                ("name", file.file_name.clone()),
+                ("path", links.link_extracted(&mut file)),
+                ("type", steg::get_mime_type(&extension).to_string()),
+                ("coded", "false".to_string()),
+            ]));
         }
+        return files;
     }
+    for file in &steg.embedded_files {
+        // Files in directories keep their path under the secrets folder.
+        let file_path = Path::new(&file.file_name);
+        let file_name = file_path
+            .strip_prefix(&steg.settings.secret_folder)
+            .unwrap_or_else(|_| Path::new(file_path.file_name().unwrap()))
+            .to_string_lossy()
+            .replace('\\', "/");
+        let file_path = links.link(&file.file_name);
+        let file_type = &file.file_type;
+
+        // Include coded status of extracted file.
+        // Only need to bother checking possible MIME types.
+        let mut file_coded = false;
+        if file_type == "image/png" {
+            file_coded = file.file_coded;
+        }
 
-    HttpResponse::Ok().json(response_data)
+        files.push(HashMap::from([
+            ("name", file_name),
+            ("path", file_path),
+            ("type", file_type.to_string()),
+            ("coded", file_coded.to_string()),
+        ]));
+    }
+    files
 }
 
 #[post("/extract")]
 async fn extract(
     form: web::Form<HashMap<String, String>>,
-    steg: web::Data<Arc<Mutex<Steganography>>>,
+    user: User,
+    jobs: web::Data<Jobs>,
+    links: SessionLinks,
 ) -> impl Responder {
 
     // User password received from UI.
     let password = form.get("password").cloned().unwrap_or_default(); 
 
-    // Get access to steg instance.
-    let mut steg = steg.lock().unwrap();
+    // Files to extract, as a comma separated list of file names or
+    // indexes from 1. All files are extracted if none are given.
+    let selection: Vec<String> = form.get("select")
+        .map(|select| select.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect())
+        .unwrap_or_default();
 
-    // Initialise vector of extracted files.
-    let mut response_data = HashMap::new();
+    // Option to extract as a background job.
+    let background = form.get("async").is_some_and(|option| option.eq_ignore_ascii_case("true"));
+
+    // Option to download the extracted files as a zip or tar archive,
+    // and to extract them to memory only, so they never touch disk.
+    if let Some(archive_name) = form.get("archive").filter(|name| !name.is_empty()) {
+        let Some(format) = ArchiveFormat::from_name(archive_name) else {
+            return HttpResponse::BadRequest().json(HashMap::from([("extracted", format!("Unknown archive format: {}", archive_name))]));
+        };
+        let memory = form.get("memory").is_some_and(|option| option.eq_ignore_ascii_case("true"));
+        return extract_archive(&user.steg, password, selection, format, memory).await;
+    }
 
-    // Perform extraction of current uploaded file.
-    // Check status of extaction
-    match steg.extract_data(password.clone()) {
-        // Extraction completed successfully.
-        Ok(_) => {
+    // Extract the files on the blocking thread pool.
+    respond_to_job(&user, &jobs, background, "extract", move |steg| {
+        // Initialise vector of extracted files.
+        let mut response_data = HashMap::new();
+        let mut status = StatusCode::OK;
+
+        // Perform extraction of current uploaded file.
+        // Check status of extaction
+        match steg.extract_selected(password.clone(), selection) {
             // Extraction completed successfully.
-            // Get vector of extract files to display on UI.
-            let saved_files = &steg.embedded_files;
-            let mut files = Vec::new();
-            for file in saved_files {
-                let file_name = Path::new(&file.file_name)
-                    .file_name()
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
-                    .to_string();
-                let file_path = format!("secrets/{}", file_name);
-                let file_type = &file.file_type;
-
-                // Include coded status of extracted file.
-                // Only need to bother checking possible MIME types.
-                let mut file_coded = false;
-                if file_type == "image/png" {
-                    file_coded = file.file_coded;
-                }
+            Ok(_) => {
+                // Extraction completed successfully.
+                // Get vector of extract files to display on UI.
+                let files = extracted_files(steg, &links);
+
+                // Respond with extraction status to display on UI.
+                response_data.insert("extracted", "True".to_string());
+                let test_time_ms:f64 = steg.extract_duration.as_millis() as f64 / 1000.0_f64;
+                let duration_str = format!("{:.3} sec", test_time_ms);
+                response_data.insert("time", duration_str);
 
-                files.push(HashMap::from([
-                    ("name", file_name),
-                    ("path", file_path),
-                    ("type", file_type.to_string()),
-                    ("coded", file_coded.to_string()),
-                ]));
+                // Respond with names of extracted files.
+                let files_json = serde_json::to_string(&files).unwrap();
+                response_data.insert("files", files_json.clone());
+
+                // Respond with who signed the files, and if the signature checks out.
+                insert_signature(&mut response_data, steg);
             }
+            // Extraction failed with error result.
+            Err(_e) => {
+                // Respond with failed extraction status to display on UI.
+                if matches!(_e, SteganographyError::Cancelled) {
+                    response_data.insert("cancelled", "True".to_string());
+                    status = StatusCode::CONFLICT;
+                }
+                response_data.insert("extracted", _e.to_string());
+                let test_time_ms:f64 = steg.extract_duration.as_millis() as f64 / 1000.0_f64;
+                let duration_str = format!("{:.3} sec", test_time_ms);
+                response_data.insert("time", duration_str);
+            }
+        }
+        (status, response_data)
+    }).await
+}
+
+// Helper function to extract files and respond with them as an archive.
+// Failed extractions respond with the usual json extraction status.
+async fn extract_archive(
+    steg: &SharedSteg,
+    password: String,
+    selection: Vec<String>,
+    format: ArchiveFormat,
+    memory: bool,
+) -> HttpResponse {
+    let result = run_steg_job(steg, move |steg| {
+        steg.extract_to_memory = memory;
+        let result = steg.extract_selected(password, selection)
+            .map_err(|e| e.to_string())
+            .and_then(|_| archive::extracted_entries(steg).map_err(|e| e.to_string()))
+            .and_then(|entries| archive::build_archive(format, &entries).map_err(|e| e.to_string()));
 
-            // Respond with extraction status to display on UI.
-            response_data.insert("extracted", "True".to_string());
-            let test_time_ms:f64 = steg.extract_duration.as_millis() as f64 / 1000.0 as f64;
-            let duration_str = format!("{:.3} sec", test_time_ms);
-            response_data.insert("time", duration_str);
+        // Extracted files aren't kept in memory once archived, and
+        // the next extraction goes to disk unless it asks otherwise.
+        steg.extract_to_memory = false;
+        steg.memory_files = Vec::new();
+        result
+    }).await;
 
-            // Respond with names of extracted files.
-            let files_json = serde_json::to_string(&files).unwrap();
-            response_data.insert("files", files_json.clone());
+    match result {
+        Ok(Ok(bytes)) => HttpResponse::Ok()
+            .content_type(format.content_type())
+            .insert_header(("Content-Disposition", format!("attachment; filename=\"{}\"", format.file_name())))
+            .body(bytes),
+        Ok(Err(e)) => HttpResponse::Ok().json(HashMap::from([("extracted", e)])),
+        Err(e) => job_response::<()>(Err(e)),
+    }
+}
+
+#[post("/manifest")]
+async fn manifest(
+    form: web::Form<HashMap<String, String>>,
+    user: User,
+) -> impl Responder {
+    // User password received from UI.
+    let password = form.get("password").cloned().unwrap_or_default();
+
+    // List the files on the blocking thread pool.
+    let result = run_steg_job(&user.steg, move |steg| {
+        // List the embedded files, without extracting them.
+        let mut response_data = HashMap::new();
+        match steg.list_manifest(password) {
+            Ok(listed_files) => {
+                let files: Vec<HashMap<&str, String>> = listed_files
+                    .iter()
+                    .map(|file| HashMap::from([
+                        ("index", file.file_idx.to_string()),
+                        ("name", file.file_name.clone()),
+                        ("size", file.file_len.to_string()),
+                        ("type", file.file_type.clone()),
+                        ("checksum", file.checksum.clone()),
+                        ("modified", file.meta.map(|meta| meta.modified.to_string()).unwrap_or_default()),
+                        ("mode", file.meta.map(|meta| format!("{:o}", meta.mode)).unwrap_or_default()),
+                    ]))
+                    .collect();
+                response_data.insert("listed", "True".to_string());
+                response_data.insert("files", serde_json::to_string(&files).unwrap());
+                insert_signature(&mut response_data, steg);
+            }
+            Err(e) => {
+                response_data.insert("listed", e.to_string());
+            }
         }
-        // Extraction failed with error result.
-        Err(_e) => {
-            // Respond with failed extraction status to display on UI.
-            response_data.insert("extracted", _e.to_string());
-            let test_time_ms:f64 = steg.extract_duration.as_millis() as f64 / 1000.0 as f64;
-            let duration_str = format!("{:.3} sec", test_time_ms);
-            response_data.insert("time", duration_str);
+        (StatusCode::OK, response_data)
+    }).await;
+    job_response(result)
+}
+
+// Helper function to add who signed the extracted files, and if the
+// signature checks out, to a response.
+fn insert_signature(response_data: &mut HashMap<&str, String>, steg: &Steganography) {
+    match &steg.signature {
+        Some(signature) => {
+            let status = if signature.verified { "Verified" } else { "Failed" };
+            response_data.insert("signature", status.to_string());
+            response_data.insert("signer", signature.fingerprint.clone());
+            let trusted = if signature.trusted { "True" } else { "False" };
+            response_data.insert("trusted", trusted.to_string());
+        }
+        None => {
+            response_data.insert("signature", "Unsigned".to_string());
         }
     }
-    HttpResponse::Ok().json(response_data)
 }
 
 #[post("/embed")]
-async fn embed(mut payload: Multipart, steg: web::Data<Arc<Mutex<Steganography>>>) -> impl Responder {
+async fn embed(mut payload: Multipart, user: User, jobs: web::Data<Jobs>, links: SessionLinks) -> impl Responder {
 
-    // Get application settings in scope.
-    let settings: Settings = SETTINGS.lock().unwrap().clone();
+    // Get the user's settings in scope.
+    let settings: Settings = user.settings.clone();
+
+    // Passwords and files for each volume.
+    // Fields "password" and "files" are the first volume, and
+    // "password_N" and "files_N" are for any additional deniable volumes.
+    let mut passwords: BTreeMap<usize, String> = BTreeMap::new();
+    let mut files: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+    let mut recipient_keys: Vec<[u8; recipients::KEY_LEN]> = Vec::new();
+    let mut invalid_keys = false;
+    let mut signing_key: Option<Vec<u8>> = None;
+    let mut noise_fill = settings.noise_fill;
+    let mut headerless = settings.headerless;
+    let mut key_slots = false;
+    let mut background = false;
+    let uploads = UploadFolder::new();
+
+    // Files to embed held in memory, in memory only mode.
+    let mut memory_files: BTreeMap<usize, Vec<SourceFile>> = BTreeMap::new();
+
+    while let Ok(Some(mut field)) = payload.try_next().await {
+        let content_disposition = field.content_disposition();
+        let name = content_disposition.get_name().unwrap_or_default().to_string();
+        let filename = content_disposition.get_filename().map(sanitize);
+        let (field_name, volume) = match name.split_once('_') {
+            Some((field_name, volume)) => (field_name, volume.parse().unwrap_or(0)),
+            None => (name.as_str(), 0),
+        };
+        let result = match (field_name, filename) {
+            ("password", _) => read_text(&mut field).await.map(|text| passwords.entry(volume).or_default().push_str(&text)),
+            ("files", Some(filename)) if settings.memory_only => {
+                read_upload(&mut field).await.map(|data| memory_files.entry(volume).or_default().push(SourceFile::in_memory(&filename, data)))
+            }
+            ("files", Some(filename)) => {
+                save_upload(&mut field, &uploads.path.join(filename)).await.map(|path| files.entry(volume).or_default().push(path))
+            }
+            // Public key files of recipients, in place of a password.
+            ("recipients", _) => read_text(&mut field).await.map(|text| match recipients::parse_key(&text) {
+                Some(key) => recipient_keys.push(key),
+                None => invalid_keys = true,
+            }),
+            // Signing key file to sign the embedded files with.
+            ("signing", _) => read_text(&mut field).await.map(|text| match signing::parse_signing_key(&text) {
+                Some(key) => signing_key = Some(key),
+                None => invalid_keys = true,
+            }),
+            // Option to fill unused capacity with random data.
+            ("noise", _) => read_text(&mut field).await.map(|text| noise_fill = text.eq_ignore_ascii_case("true")),
+            // Option to embed volumes without any header.
+            ("headerless", _) => read_text(&mut field).await.map(|text| headerless = text.eq_ignore_ascii_case("true")),
+            // Option to use a key slot for each password.
+            ("slots", _) => read_text(&mut field).await.map(|text| key_slots = text.eq_ignore_ascii_case("true")),
+            // Option to embed as a background job.
+            ("async", _) => read_text(&mut field).await.map(|text| background = text.eq_ignore_ascii_case("true")),
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            return bad_form_response(e);
+        }
+    }
+    let password = passwords.get(&0).cloned().unwrap_or_default();
 
+    // Only plain embedding writes a signature section, so rather than
+    // leave the files unsigned, refuse to sign anything else.
+    let plain = recipient_keys.is_empty() && !key_slots && !headerless
+        && passwords.len() <= 1 && files.len() <= 1 && memory_files.len() <= 1;
+    if signing_key.is_some() && !plain {
+        return HttpResponse::BadRequest().json(HashMap::from([
+            ("embedded", "False"),
+            ("error", "Signing is only available for files embedded with a single password, or none"),
+        ]));
+    }
+
+    // Embed the files on the blocking thread pool.
+    respond_to_job(&user, &jobs, background, "embed", move |steg| {
+        // Uploaded files are removed once done with.
+        let _uploads = uploads;
+        steg.noise_fill = noise_fill;
+        steg.headerless = headerless;
+        steg.signing_key = signing_key;
+
+        // Call the embed_files function with appropriate parameters,
+        // or embed_recipients if public keys were given,
+        // or embed_key_slots if key slots were asked for,
+        // or embed_volumes if more than one volume was given or headerless.
+        let embed_result = if invalid_keys {
+            Err(std::io::Error::other("Invalid key file"))
+        } else if settings.memory_only {
+            // Only plain embedding, signed or not, works on files in memory.
+            if !recipient_keys.is_empty() || key_slots || headerless || passwords.len() > 1 || memory_files.len() > 1 {
+                Err(std::io::Error::other("Not available in memory only mode"))
+            } else {
+                let sources: Vec<SourceFile> = memory_files.into_values().flatten().collect();
+                steg.embed_sources(!password.is_empty(), &password, &sources)
+            }
+        } else if !recipient_keys.is_empty() {
+            let files_ref: Vec<&str> = files.values().flatten().map(|s| &**s).collect();
+            steg.embed_recipients(&recipient_keys, &files_ref)
+        } else if key_slots {
+            let files_ref: Vec<&str> = files.values().flatten().map(|s| &**s).collect();
+            let passwords_ref: Vec<&str> = passwords.values().map(|s| &**s).collect();
+            steg.embed_key_slots(&passwords_ref, &files_ref)
+        } else if passwords.len() > 1 || files.len() > 1 || headerless {
+            let volumes: Vec<Volume> = files
+                .iter()
+                .map(|(volume, volume_files)| Volume {
+                    password: passwords.get(volume).map(|s| s.as_str()).unwrap_or_default(),
+                    files: volume_files.iter().map(|s| &**s).collect(),
+                })
+                .collect();
+            steg.embed_volumes(&volumes)
+        } else {
+            // Convert Vec<String> to Vec<&str> for steg function.
+            let files_ref: Vec<&str> = files.values().flatten().map(|s| &**s).collect();
+            steg.embed_files(!password.is_empty(), &password, &files_ref)
+        };
+        // Embedding succesful, so save to temporary file.
+        let embed_result = embed_result.and_then(|_| save_output_image(steg, &settings, &links));
+        match embed_result {
+            Ok((file_name, link)) => {
+                // Embedding successful, respond with embedding status.
+                let mut response_data = HashMap::new();
+                response_data.insert("embedded", "True".to_string());
+                let test_time_ms:f64 = steg.embed_duration.as_millis() as f64 / 1000.0_f64;
+                let duration_str = format!("{:.3} sec", test_time_ms);
+                response_data.insert("time", duration_str);
+                response_data.insert("thumbnail", link);
+                response_data.insert("filename", file_name);
+
+                // Respond with embedding status to display on UI.
+                (StatusCode::OK, response_data)
+            }
+            Err(e) => {
+                // Embedding failed, respond with error.
+                let mut response_data = HashMap::new();
+                response_data.insert("embedded", "False".to_string());
+                response_data.insert("error", e.to_string());
+                if e.kind() == std::io::ErrorKind::Interrupted {
+                    response_data.insert("cancelled", "True".to_string());
+                    return (StatusCode::CONFLICT, response_data);
+                }
+
+                // Respond with embedding status to display on UI.
+                (StatusCode::INTERNAL_SERVER_ERROR, response_data)
+            }
+        }
+    }).await
+}
+
+#[post("/embed_split")]
+async fn embed_split(mut payload: Multipart, user: User, links: SessionLinks) -> impl Responder {
+
+    // Get the user's settings in scope.
+    let settings: Settings = user.settings.clone();
+
+    // Sets of images are kept on disk, so not available in memory only mode.
+    if settings.memory_only {
+        return memory_only_response();
+    }
+
+    // Carrier images, in the order the parts are to be embedded,
+    // and the files to split over them.
+    let mut carriers: Vec<String> = Vec::new();
+    let mut files: Vec<String> = Vec::new();
     let mut password = String::new();
-    let mut files = Vec::new();
-    let temp_dir = temp_dir();
+    let mut noise_fill = settings.noise_fill;
+    let uploads = UploadFolder::new();
 
     while let Ok(Some(mut field)) = payload.try_next().await {
         let content_disposition = field.content_disposition();
-        if let Some(name) = content_disposition.get_name() {
-            if name == "password" {
-                while let Some(chunk) = field.try_next().await.unwrap() {
-                    password.push_str(&String::from_utf8(chunk.to_vec()).unwrap());
+        let name = content_disposition.get_name().unwrap_or_default().to_string();
+        let filename = content_disposition.get_filename().map(sanitize);
+        let result = match (name.as_str(), filename) {
+            ("password", _) => read_text(&mut field).await.map(|text| password.push_str(&text)),
+            // Carriers are kept with the user's images, under unique
+            // names as they may well have the same names.
+            ("carriers", Some(filename)) => {
+                let file_path = unique_upload_path(&settings.thumb_folder, &filename);
+                save_upload(&mut field, &file_path).await.map(|path| carriers.push(path))
+            }
+            ("files", Some(filename)) => {
+                save_upload(&mut field, &uploads.path.join(filename)).await.map(|path| files.push(path))
+            }
+            // Option to fill unused capacity with random data.
+            ("noise", _) => read_text(&mut field).await.map(|text| noise_fill = text.eq_ignore_ascii_case("true")),
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            return bad_form_response(e);
+        }
+    }
+
+    // Output file for each carrier, named from current time and part number.
+    let mut ts_string = Utc::now().to_string();
+    ts_string = ts_string.chars().filter(|c| !c.is_whitespace()).collect();
+    let mut wrt_path = PathBuf::new();
+    wrt_path.push(&settings.secret_folder);
+    if !wrt_path.exists() {
+        create_dir_all(&wrt_path).unwrap();
+    }
+    let out_files: Vec<String> = (1..=carriers.len())
+        .map(|idx| wrt_path.join(format!("{}-part{}.png", ts_string, idx)).to_string_lossy().into_owned())
+        .collect();
+
+    // Embed the parts on the blocking thread pool.
+    let result = run_steg_job(&user.steg, move |steg| {
+        // Uploaded files are removed once done with.
+        let _uploads = uploads;
+        steg.noise_fill = noise_fill;
+
+        let files_ref: Vec<&str> = files.iter().map(|s| &**s).collect();
+        let mut response_data = HashMap::new();
+        match steg.embed_split(&carriers, &out_files, !password.is_empty(), &password, &files_ref) {
+            Ok(num_parts) => {
+                // Embedding successful, respond with embedding status
+                // and the images holding each part.
+                response_data.insert("embedded", "True".to_string());
+                let test_time_ms:f64 = steg.embed_duration.as_millis() as f64 / 1000.0_f64;
+                let duration_str = format!("{:.3} sec", test_time_ms);
+                response_data.insert("time", duration_str);
+                let parts: Vec<String> = out_files[..num_parts].iter().map(|path| links.link(path)).collect();
+                let parts_json = serde_json::to_string(&parts).unwrap();
+                response_data.insert("parts", parts_json);
+                (StatusCode::OK, response_data)
+            }
+            Err(e) => {
+                // Embedding failed, respond with error.
+                response_data.insert("embedded", "False".to_string());
+                response_data.insert("error", e.to_string());
+                (StatusCode::INTERNAL_SERVER_ERROR, response_data)
+            }
+        }
+    }).await;
+    job_response(result)
+}
+
+#[post("/extract_parts")]
+async fn extract_parts(mut payload: Multipart, user: User, links: SessionLinks) -> impl Responder {
+
+    // Get the user's settings in scope.
+    let settings: Settings = user.settings.clone();
+
+    // Sets of images are kept on disk, so not available in memory only mode.
+    if settings.memory_only {
+        return memory_only_response();
+    }
+
+    // Images holding the parts of a split set, in any order.
+    let mut images: Vec<String> = Vec::new();
+    let mut password = String::new();
+
+    while let Ok(Some(mut field)) = payload.try_next().await {
+        let content_disposition = field.content_disposition();
+        let name = content_disposition.get_name().unwrap_or_default().to_string();
+        let filename = content_disposition.get_filename().map(sanitize);
+        let result = match (name.as_str(), filename) {
+            ("password", _) => read_text(&mut field).await.map(|text| password.push_str(&text)),
+            ("images", Some(filename)) => {
+                let file_path = unique_upload_path(&settings.thumb_folder, &filename);
+                save_upload(&mut field, &file_path).await.map(|path| images.push(path))
+            }
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            return bad_form_response(e);
+        }
+    }
+
+    // Extract the parts on the blocking thread pool.
+    let result = run_steg_job(&user.steg, move |steg| {
+        let mut response_data = HashMap::new();
+        match steg.extract_parts(&images, password) {
+            Ok(_) => {
+                response_data.insert("extracted", "True".to_string());
+                let files_json = serde_json::to_string(&extracted_files(steg, &links)).unwrap();
+                response_data.insert("files", files_json);
+            }
+            Err(e) => {
+                // Extraction failed, including when parts are missing.
+                response_data.insert("extracted", e.to_string());
+            }
+        }
+        let test_time_ms:f64 = steg.extract_duration.as_millis() as f64 / 1000.0_f64;
+        let duration_str = format!("{:.3} sec", test_time_ms);
+        response_data.insert("time", duration_str);
+        (StatusCode::OK, response_data)
+    }).await;
+    job_response(result)
+}
+
+#[post("/embed_shares")]
+async fn embed_shares(mut payload: Multipart, user: User, links: SessionLinks) -> impl Responder {
+
+    // Get the user's settings in scope.
+    let settings: Settings = user.settings.clone();
+
+    // Sets of images are kept on disk, so not available in memory only mode.
+    if settings.memory_only {
+        return memory_only_response();
+    }
+
+    // Carrier images, one for each share,
+    // and the files to share over them.
+    let mut carriers: Vec<String> = Vec::new();
+    let mut files: Vec<String> = Vec::new();
+    let mut password = String::new();
+    let mut threshold: u16 = 0;
+    let mut noise_fill = settings.noise_fill;
+    let uploads = UploadFolder::new();
+
+    while let Ok(Some(mut field)) = payload.try_next().await {
+        let content_disposition = field.content_disposition();
+        let name = content_disposition.get_name().unwrap_or_default().to_string();
+        let filename = content_disposition.get_filename().map(sanitize);
+        let result = match (name.as_str(), filename) {
+            ("password", _) => read_text(&mut field).await.map(|text| password.push_str(&text)),
+            // Carriers are kept with the user's images, under unique
+            // names as they may well have the same names.
+            ("carriers", Some(filename)) => {
+                let file_path = unique_upload_path(&settings.thumb_folder, &filename);
+                save_upload(&mut field, &file_path).await.map(|path| carriers.push(path))
+            }
+            ("files", Some(filename)) => {
+                save_upload(&mut field, &uploads.path.join(filename)).await.map(|path| files.push(path))
+            }
+            // Number of shares needed to extract the files.
+            ("threshold", _) => read_text(&mut field).await.map(|text| threshold = text.trim().parse().unwrap_or(0)),
+            // Option to fill unused capacity with random data.
+            ("noise", _) => read_text(&mut field).await.map(|text| noise_fill = text.eq_ignore_ascii_case("true")),
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            return bad_form_response(e);
+        }
+    }
+
+    // Output file for each carrier, named from current time and share number.
+    let mut ts_string = Utc::now().to_string();
+    ts_string = ts_string.chars().filter(|c| !c.is_whitespace()).collect();
+    let mut wrt_path = PathBuf::new();
+    wrt_path.push(&settings.secret_folder);
+    if !wrt_path.exists() {
+        create_dir_all(&wrt_path).unwrap();
+    }
+    let out_files: Vec<String> = (1..=carriers.len())
+        .map(|idx| wrt_path.join(format!("{}-share{}.png", ts_string, idx)).to_string_lossy().into_owned())
+        .collect();
+
+    // Embed the shares on the blocking thread pool.
+    let result = run_steg_job(&user.steg, move |steg| {
+        // Uploaded files are removed once done with.
+        let _uploads = uploads;
+        steg.noise_fill = noise_fill;
+
+        let files_ref: Vec<&str> = files.iter().map(|s| &**s).collect();
+        let mut response_data = HashMap::new();
+        match steg.embed_shares(&carriers, &out_files, threshold, !password.is_empty(), &password, &files_ref) {
+            Ok(_) => {
+                // Embedding successful, respond with embedding status
+                // and the images holding each share.
+                response_data.insert("embedded", "True".to_string());
+                let test_time_ms:f64 = steg.embed_duration.as_millis() as f64 / 1000.0_f64;
+                let duration_str = format!("{:.3} sec", test_time_ms);
+                response_data.insert("time", duration_str);
+                let shares: Vec<String> = out_files.iter().map(|path| links.link(path)).collect();
+                let shares_json = serde_json::to_string(&shares).unwrap();
+                response_data.insert("shares", shares_json);
+                (StatusCode::OK, response_data)
+            }
+            Err(e) => {
+                // Embedding failed, respond with error.
+                response_data.insert("embedded", "False".to_string());
+                response_data.insert("error", e.to_string());
+                (StatusCode::INTERNAL_SERVER_ERROR, response_data)
+            }
+        }
+    }).await;
+    job_response(result)
+}
+
+#[post("/extract_shares")]
+async fn extract_shares(mut payload: Multipart, user: User, links: SessionLinks) -> impl Responder {
+
+    // Get the user's settings in scope.
+    let settings: Settings = user.settings.clone();
+
+    // Sets of images are kept on disk, so not available in memory only mode.
+    if settings.memory_only {
+        return memory_only_response();
+    }
+
+    // Images holding the shares of a threshold set, in any order.
+    let mut images: Vec<String> = Vec::new();
+    let mut password = String::new();
+
+    while let Ok(Some(mut field)) = payload.try_next().await {
+        let content_disposition = field.content_disposition();
+        let name = content_disposition.get_name().unwrap_or_default().to_string();
+        let filename = content_disposition.get_filename().map(sanitize);
+        let result = match (name.as_str(), filename) {
+            ("password", _) => read_text(&mut field).await.map(|text| password.push_str(&text)),
+            ("images", Some(filename)) => {
+                let file_path = unique_upload_path(&settings.thumb_folder, &filename);
+                save_upload(&mut field, &file_path).await.map(|path| images.push(path))
+            }
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            return bad_form_response(e);
+        }
+    }
+
+    // Extract the shares on the blocking thread pool.
+    let result = run_steg_job(&user.steg, move |steg| {
+        let mut response_data = HashMap::new();
+        match steg.extract_shares(&images, password) {
+            Ok(_) => {
+                response_data.insert("extracted", "True".to_string());
+                let files_json = serde_json::to_string(&extracted_files(steg, &links)).unwrap();
+                response_data.insert("files", files_json);
+            }
+            Err(e) => {
+                // Extraction failed, including when more shares are needed.
+                response_data.insert("extracted", e.to_string());
+            }
+        }
+        let test_time_ms:f64 = steg.extract_duration.as_millis() as f64 / 1000.0_f64;
+        let duration_str = format!("{:.3} sec", test_time_ms);
+        response_data.insert("time", duration_str);
+        (StatusCode::OK, response_data)
+    }).await;
+    job_response(result)
+}
+
+#[get("/slots")]
+async fn list_slots(user: User) -> impl Responder {
+    // List the key slots on the blocking thread pool.
+    let result = run_steg_job(&user.steg, move |steg| {
+        // List which key slots are in use in the loaded image.
+        let mut response_data = HashMap::new();
+        if steg.pic_key_slots {
+            let slots_json = serde_json::to_string(&steg.list_key_slots()).unwrap();
+            response_data.insert("slots", slots_json);
+        } else {
+            response_data.insert("error", "Image does not use key slots".to_string());
+        }
+        (StatusCode::OK, response_data)
+    }).await;
+    job_response(result)
+}
+
+#[post("/slots/add")]
+async fn add_slot(
+    form: web::Form<HashMap<String, String>>,
+    user: User,
+    links: SessionLinks,
+) -> impl Responder {
+    // Get the user's settings in scope.
+    let settings: Settings = user.settings.clone();
+
+    // Existing password, and the new password to add.
+    let password = form.get("password").cloned().unwrap_or_default();
+    let new_password = form.get("new_password").cloned().unwrap_or_default();
+
+    // Add the key slot on the blocking thread pool.
+    let result = run_steg_job(&user.steg, move |steg| {
+        // Add the key slot, and save the changed image.
+        let mut response_data = HashMap::new();
+        match steg.add_key_slot(&password, &new_password) {
+            Ok(slot) => match save_output_image(steg, &settings, &links) {
+                Ok((file_name, link)) => {
+                    response_data.insert("slot", slot.to_string());
+                    response_data.insert("filename", file_name);
+                    response_data.insert("link", link);
                 }
-            } else if name == "files" {
-                if let Some(filename) = content_disposition.get_filename() {
-                    let sanitized_filename = sanitize(filename);
-                    let file_path = temp_dir.join(&sanitized_filename);
-                    let mut file = StdFile::create(&file_path).unwrap();
-                    while let Some(chunk) = field.try_next().await.unwrap() {
-                        file.write_all(&chunk).unwrap();
-                    }
-                    files.push(file_path.to_str().unwrap().to_string());
+                Err(e) => {
+                    response_data.insert("error", e.to_string());
                 }
+            },
+            Err(e) => {
+                response_data.insert("error", e.to_string());
             }
         }
-    }
+        (StatusCode::OK, response_data)
+    }).await;
+    job_response(result)
+}
+
+#[post("/slots/remove")]
+async fn remove_slot(
+    form: web::Form<HashMap<String, String>>,
+    user: User,
+    links: SessionLinks,
+) -> impl Responder {
+    // Get the user's settings in scope.
+    let settings: Settings = user.settings.clone();
+
+    // Password in use, and the slot to remove.
+    let password = form.get("password").cloned().unwrap_or_default();
+    let slot = form.get("slot").and_then(|slot| slot.parse().ok()).unwrap_or(u64::MAX);
+
+    // Remove the key slot on the blocking thread pool.
+    let result = run_steg_job(&user.steg, move |steg| {
+        // Remove the key slot, and save the changed image.
+        let mut response_data = HashMap::new();
+        match steg.remove_key_slot(&password, slot) {
+            Ok(_) => match save_output_image(steg, &settings, &links) {
+                Ok((file_name, link)) => {
+                    response_data.insert("slot", slot.to_string());
+                    response_data.insert("filename", file_name);
+                    response_data.insert("link", link);
+                }
+                Err(e) => {
+                    response_data.insert("error", e.to_string());
+                }
+            },
+            Err(e) => {
+                response_data.insert("error", e.to_string());
+            }
+        }
+        (StatusCode::OK, response_data)
+    }).await;
+    job_response(result)
+}
 
-    // Convert Vec<String> to Vec<&str> for steg function.
-    let files_ref: Vec<&str> = files.iter().map(|s| &**s).collect();
+#[post("/rekey")]
+async fn rekey(
+    form: web::Form<HashMap<String, String>>,
+    user: User,
+    links: SessionLinks,
+) -> impl Responder {
+    // Get the user's settings in scope.
+    let settings: Settings = user.settings.clone();
 
-    // Get access to steg instance.
-    let mut steg = steg.lock().unwrap();
-    // Call the embed_files function with appropriate parameters.
-    match steg.embed_files(!password.is_empty(), &password, &files_ref) {
-        Ok(_) => {
-            // Embedding succesful, so save to temporary file.
-            // Create temporary file name from current time.
-            let mut ts_string = Utc::now().to_string();
-            ts_string = ts_string.chars().filter(|c| !c.is_whitespace()).collect();
- 
-            // Save in secrets folder from settings.
-            // Should actually check the folder exists, and create if not.
-            let mut wrt_path = PathBuf::new();       
-            wrt_path.push(&settings.secret_folder);
-            if !wrt_path.exists() {
-                create_dir_all(&wrt_path).unwrap();
+    // Old password, and the new password, blank to remove protection.
+    let old_password = form.get("old_password").cloned().unwrap_or_default();
+    let new_password = form.get("new_password").cloned().unwrap_or_default();
+
+    // Rekey the image on the blocking thread pool.
+    let result = run_steg_job(&user.steg, move |steg| {
+        // Change the password, and save the changed image.
+        let mut response_data = HashMap::new();
+        match steg.rekey(&old_password, &new_password) {
+            Ok(_) => match save_output_image(steg, &settings, &links) {
+                Ok((file_name, link)) => {
+                    response_data.insert("rekeyed", "True".to_string());
+                    response_data.insert("filename", file_name);
+                    response_data.insert("link", link);
+                }
+                Err(e) => {
+                    response_data.insert("rekeyed", "False".to_string());
+                    response_data.insert("error", e.to_string());
+                }
+            },
+            Err(e) => {
+                response_data.insert("rekeyed", "False".to_string());
+                response_data.insert("error", e.to_string());
             }
-            wrt_path.push(format!("{}.png",ts_string));
-            let wrt_path_string = wrt_path.to_string_lossy().into_owned();
-            let wrt_path_string_clone = wrt_path_string.clone();
+        }
+        (StatusCode::OK, response_data)
+    }).await;
+    job_response(result)
+}
+
+#[post("/files")]
+async fn list_files(
+    form: web::Form<HashMap<String, String>>,
+    user: User,
+) -> impl Responder {
+    // Password for the embedded files.
+    let password = form.get("password").cloned().unwrap_or_default();
+
+    // List the files on the blocking thread pool.
+    let result = run_steg_job(&user.steg, move |steg| {
+        // Respond with the embedded files that can be edited.
+        let mut response_data = HashMap::new();
+        match steg.list_embedded_files(&password) {
+            Ok(table) => {
+                response_data.insert("files", file_table_json(&table));
+            }
+            Err(e) => {
+                response_data.insert("error", e.to_string());
+            }
+        }
+        (StatusCode::OK, response_data)
+    }).await;
+    job_response(result)
+}
+
+#[post("/files/add")]
+async fn add_files(payload: Multipart, user: User, links: SessionLinks) -> impl Responder {
+    // Files to add are read from disk, so not available in memory only mode.
+    if SETTINGS.lock().unwrap().memory_only {
+        return memory_only_response();
+    }
+    let uploads = UploadFolder::new();
+    let (password, _, files) = match read_edit_form(payload, &uploads).await {
+        Ok(form) => form,
+        Err(e) => return bad_form_response(e),
+    };
+
+    // Add the files on the blocking thread pool.
+    let result = run_steg_job(&user.steg, move |steg| {
+        // Uploaded files are removed once done with.
+        let _uploads = uploads;
+        let files_ref: Vec<&str> = files.iter().map(|s| &**s).collect();
+        let result = steg.add_embedded_files(&password, &files_ref);
+        edit_response(steg, result, &links)
+    }).await;
+    job_response(result)
+}
+
+#[post("/files/remove")]
+async fn remove_file(
+    form: web::Form<HashMap<String, String>>,
+    user: User,
+    links: SessionLinks,
+) -> impl Responder {
+    // Password for the embedded files, and the file to remove.
+    let password = form.get("password").cloned().unwrap_or_default();
+    let file_name = form.get("file").cloned().unwrap_or_default();
 
-            // Save the temporary output file.
-            steg.save_image(wrt_path_string_clone.clone());
+    // Remove the file on the blocking thread pool.
+    let result = run_steg_job(&user.steg, move |steg| {
+        let result = steg.remove_embedded_file(&password, &file_name);
+        edit_response(steg, result, &links)
+    }).await;
+    job_response(result)
+}
+
+#[post("/files/replace")]
+async fn replace_file(payload: Multipart, user: User, links: SessionLinks) -> impl Responder {
+    // Files to add are read from disk, so not available in memory only mode.
+    if SETTINGS.lock().unwrap().memory_only {
+        return memory_only_response();
+    }
+    let uploads = UploadFolder::new();
+    let (password, file_name, files) = match read_edit_form(payload, &uploads).await {
+        Ok(form) => form,
+        Err(e) => return bad_form_response(e),
+    };
+
+    // Replace the file on the blocking thread pool.
+    let result = run_steg_job(&user.steg, move |steg| {
+        // Uploaded files are removed once done with.
+        let _uploads = uploads;
+        let result = match files.first() {
+            Some(new_file) => steg.replace_embedded_file(&password, &file_name, new_file),
+            None => Err(std::io::Error::other("No replacement file given")),
+        };
+        edit_response(steg, result, &links)
+    }).await;
+    job_response(result)
+}
+
+// Helper function to read the form for adding or replacing embedded files.
+// Fields are "password", "file" (the embedded file to replace), and "files".
+// Returns the password, the file name, and the uploaded files,
+// which are saved to the upload folder given, or an error if the
+// form can't be read or a file can't be saved.
+async fn read_edit_form(mut payload: Multipart, uploads: &UploadFolder) -> std::io::Result<(String, String, Vec<String>)> {
+    let mut password = String::new();
+    let mut file_name = String::new();
+    let mut files: Vec<String> = Vec::new();
+
+    while let Ok(Some(mut field)) = payload.try_next().await {
+        let content_disposition = field.content_disposition();
+        let name = content_disposition.get_name().unwrap_or_default().to_string();
+        let filename = content_disposition.get_filename().map(sanitize);
+        match (name.as_str(), filename) {
+            ("password", _) => password.push_str(&read_text(&mut field).await?),
+            ("file", _) => file_name.push_str(&read_text(&mut field).await?),
+            ("files", Some(filename)) => files.push(save_upload(&mut field, &uploads.path.join(filename)).await?),
+            _ => {}
+        }
+    }
+    Ok((password, file_name, files))
+}
 
-            // Embedding successful, respond with embedding status.
-            let mut response_data = HashMap::new();
-            response_data.insert("embedded", "True".to_string());
-            let test_time_ms:f64 = steg.embed_duration.as_millis() as f64 / 1000.0 as f64;
-            let duration_str = format!("{:.3} sec", test_time_ms);
-            response_data.insert("time", duration_str);
-            response_data.insert("thumbnail", wrt_path_string_clone.clone());
-            response_data.insert("filename", wrt_path_string_clone.clone());
+// Helper function to respond to a change to the embedded files.
+// If successful the changed image is saved, and the new list of files returned.
+fn edit_response(steg: &mut Steganography, result: std::io::Result<()>, links: &SessionLinks) -> JobResponse {
+    // Get the user's settings in scope.
+    let settings: Settings = steg.settings.clone();
 
-            // Respond with embedding status to display on UI.
-            HttpResponse::Ok().json(response_data)
+    let mut response_data = HashMap::new();
+    let result = result
+        .and_then(|_| steg.read_file_table())
+        .and_then(|table| save_output_image(steg, &settings, links).map(|saved| (table, saved)));
+    match result {
+        Ok((table, (file_name, link))) => {
+            response_data.insert("edited", "True".to_string());
+            response_data.insert("filename", file_name);
+            response_data.insert("link", link);
+            response_data.insert("files", file_table_json(&table));
         }
         Err(e) => {
-            // Embedding failed, respond with error.
-            let mut response_data = HashMap::new();
-            response_data.insert("embedded", "False".to_string());
+            response_data.insert("edited", "False".to_string());
             response_data.insert("error", e.to_string());
+        }
+    }
+    (StatusCode::OK, response_data)
+}
 
-            // Respond with embedding status to display on UI.
-            HttpResponse::InternalServerError().json(response_data)
+// Helper function to get the embedded files in the file table as json for the UI.
+fn file_table_json(table: &[FileSection]) -> String {
+    let files: Vec<HashMap<&str, String>> = table
+        .iter()
+        .map(|section| HashMap::from([
+            ("name", section.file_name.clone()),
+            ("size", section.file_len.to_string()),
+        ]))
+        .collect();
+    serde_json::to_string(&files).unwrap()
+}
+
+#[get("/jobs/{id}")]
+async fn job_status(path: web::Path<String>, jobs: web::Data<Jobs>, user: User) -> impl Responder {
+    // Respond with the progress of the job, and its result if finished.
+    let id = path.into_inner();
+    match jobs.get(&user.name, &id) {
+        Some(job) => HttpResponse::Ok().json(job.status(&id)),
+        None => HttpResponse::NotFound().json(HashMap::from([("error", "Job not found")])),
+    }
+}
+
+#[delete("/jobs/{id}")]
+async fn cancel_job(path: web::Path<String>, jobs: web::Data<Jobs>, user: User) -> impl Responder {
+    // Ask the job to stop at the next chunk. The job finishes
+    // with a cancelled result once it has stopped.
+    let id = path.into_inner();
+    match jobs.get(&user.name, &id) {
+        Some(job) if job.cancel() => {
+            info!("Cancelling job: {}", id);
+            HttpResponse::Accepted().json(HashMap::from([("job", id), ("cancelled", "True".to_string())]))
         }
+        Some(_) => HttpResponse::Conflict().json(HashMap::from([("error", "Job already finished")])),
+        None => HttpResponse::NotFound().json(HashMap::from([("error", "Job not found")])),
     }
 }
 
+#[get("/jobs/{id}/events")]
+async fn job_events(path: web::Path<String>, jobs: web::Data<Jobs>, user: User) -> impl Responder {
+    let id = path.into_inner();
+    let job = match jobs.get(&user.name, &id) {
+        Some(job) => job,
+        None => return HttpResponse::NotFound().json(HashMap::from([("error", "Job not found")])),
+    };
+
+    // Stream the progress of the job as server-sent events, every
+    // (progress_interval_ms) milliseconds, ending with a "done" event
+    // holding the result.
+    let interval = std::time::Duration::from_millis(SETTINGS.lock().unwrap().progress_interval_ms);
+    let events = futures::stream::unfold((job, true, false), move |(job, first, finished)| {
+        let id = id.clone();
+        async move {
+            if finished {
+                return None;
+            }
+            if !first {
+                tokio::time::sleep(interval).await;
+            }
+            let done = job.is_finished();
+            let event = format!("event: {}\ndata: {}\n\n", if done { "done" } else { "progress" }, serde_json::to_string(&job.status(&id)).unwrap());
+            Some((Ok::<_, actix_web::Error>(web::Bytes::from(event)), (job, false, done)))
+        }
+    });
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(events)
+}
+
 async fn help(settings: web::Data<Settings>) -> impl Responder {
     // Help endpoint function
     // Read the help file.
@@ -270,6 +1399,13 @@ async fn help(settings: web::Data<Settings>) -> impl Responder {
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    // Run a command line command if one is given,
+    // instead of the web service.
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        return cli::run(&args);
+    }
+
     // Create files if they don't already exist.
     fs::create_dir_all("./logs")?;
     fs::create_dir_all("./images")?;
@@ -283,21 +1419,52 @@ async fn main() -> std::io::Result<()> {
     // Do initial program version logging, mainly as a test.
     info!("Application started: {} v({})", settings.program_name, settings.program_ver);
 
-    // Instantiate a steganography struct.
-    // Call init method to initialise struct.
-    let img_steg = Arc::new(Mutex::new(Steganography::init()));
+    // Users, each with a steganography instance of their own.
+    let accounts = web::Data::new(Accounts::new(&settings)?);
+
+    // Background jobs, shared by all workers.
+    let jobs = web::Data::new(Jobs::new(settings.job_keep_secs));
+
+    // Delete stored files once past the retention limits.
+    actix_web::rt::spawn(retention::run(settings.clone()));
+
+    // Download links for extracted files and embedded images.
+    let downloads = web::Data::new(Downloads::new(settings.link_keep_secs));
+    actix_web::rt::spawn(downloads::run_expiry(downloads.clone()));
 
     // Create and start web service.
     HttpServer::new(move || {
         App::new()
-            .app_data(web::Data::new(img_steg.clone()))
+            .app_data(accounts.clone())
+            .app_data(jobs.clone())
+            .app_data(downloads.clone())
             .app_data(web::Data::new(settings.clone()))
-            .service(fsx::Files::new("/secrets", "./secrets").show_files_listing())
+            .wrap(actix_web::middleware::from_fn(accounts::require_user))
+            .wrap(actix_web::middleware::from_fn(downloads::ensure_session))
+            .configure(accounts::config)
+            .service(downloads::download)
             .service(intro)
             .service(upload)
             .service(extract)
+            .service(manifest)
             .service(embed)
-            .service(actix_files::Files::new("/static", "./static").show_files_listing())
+            .service(embed_split)
+            .service(extract_parts)
+            .service(embed_shares)
+            .service(extract_shares)
+            .service(list_slots)
+            .service(add_slot)
+            .service(remove_slot)
+            .service(rekey)
+            .service(list_files)
+            .service(add_files)
+            .service(remove_file)
+            .service(replace_file)
+            .service(job_status)
+            .service(cancel_job)
+            .service(job_events)
+            .configure(api::config)
+            .service(actix_files::Files::new("/static", "./static"))
             .route("/help", web::get().to(help))
     })
     .bind("127.0.0.1:8080")?
@@ -0,0 +1,184 @@
+// Retention of stored files.
+//
+// Uploaded images in the thumbnails folder, embedded images and extracted
+// files in the secrets folder, and uploaded files to embed in the upload
+// folder, are only kept for a while. A background task sweeps the folders
+// every (retention_interval_secs) seconds:
+//
+// Age : files older than (retention_max_age_secs) are deleted.
+// Size : if the files left take up more than (retention_max_bytes) in
+//        total, the oldest are deleted until they fit. Files younger than
+//        one sweep interval are never deleted for size, as they may still
+//        be in use by a request.
+//
+// Either limit is turned off by setting it to 0. Extracted files keep
+// their original modification time, so the age of a file is taken from
+// when it was last changed on disk in any way, including being created.
+//
+// Files in the secrets folder are securely deleted, as they may be
+// extracted secrets, as are any files left in the upload folder, which
+// are files to embed. Directories left empty are removed as well, but
+// never the folders themselves, or the users' own folders in them.
+
+use actix_web::web;
+use log::{info, warn};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::settings::Settings;
+use crate::steg::file_tree;
+use crate::upload_folder;
+
+// Struct to hold a stored file that may be deleted.
+struct StoredFile {
+    path: PathBuf,
+    size: u64,
+    stored: SystemTime,
+    secret: bool,
+}
+
+// Helper function to run the retention sweep every
+// (retention_interval_secs) seconds, on the blocking thread pool.
+pub async fn run(settings:Settings) {
+    if settings.retention_max_age_secs == 0 && settings.retention_max_bytes == 0 {
+        info!("Retention limits turned off.");
+        return;
+    }
+    let mut interval = tokio::time::interval(Duration::from_secs(settings.retention_interval_secs.max(1)));
+    loop {
+        interval.tick().await;
+        let settings = settings.clone();
+        if let Err(e) = web::block(move || sweep(&settings)).await {
+            warn!("Retention sweep failed: {}", e);
+        }
+    }
+}
+
+// Helper function to delete the stored files that are past the
+// retention limits. Returns the number of files deleted.
+pub fn sweep(settings:&Settings) -> usize {
+    // Users' own folders are a level down when authentication is on.
+    let user_depth = if settings.auth_enabled { 2 } else { 1 };
+    let folders = [
+        (PathBuf::from(&settings.thumb_folder), false, user_depth),
+        (PathBuf::from(&settings.secret_folder), true, user_depth),
+        (upload_folder(), true, 1),
+    ];
+    let mut files = Vec::new();
+    for (folder, secret, _) in &folders {
+        if let Err(e) = collect_files(folder, *secret, &mut files) {
+            warn!("Unable to read folder for retention: {}: {}", folder.display(), e);
+        }
+    }
+
+    // Oldest first.
+    files.sort_by_key(|file| file.stored);
+    let now = SystemTime::now();
+    let age = |file:&StoredFile| now.duration_since(file.stored).unwrap_or_default();
+    let max_age = Duration::from_secs(settings.retention_max_age_secs);
+    let min_age = Duration::from_secs(settings.retention_interval_secs);
+    let mut total:u64 = files.iter().map(|file| file.size).sum();
+    let mut deleted = 0;
+
+    for file in &files {
+        let too_old = !max_age.is_zero() && age(file) > max_age;
+        let too_big = settings.retention_max_bytes > 0 && total > settings.retention_max_bytes && age(file) > min_age;
+        if !too_old && !too_big {
+            continue;
+        }
+        let result = if file.secret {
+            file_tree::secure_delete(&file.path)
+        }
+        else {
+            fs::remove_file(&file.path)
+        };
+        match result {
+            Ok(()) => {
+                total -= file.size;
+                deleted += 1;
+            }
+            Err(e) => warn!("Unable to delete file for retention: {}: {}", file.path.display(), e),
+        }
+    }
+
+    for (folder, _, keep_depth) in &folders {
+        remove_empty_dirs(folder, *keep_depth);
+    }
+    if deleted > 0 {
+        info!("Retention sweep deleted {} files, {} bytes left.", deleted, total);
+    }
+    deleted
+}
+
+// Helper function to get the number of files stored in a folder,
+// and its subfolders, and the bytes they take up.
+pub fn folder_usage(folder:&Path) -> (u64, u64) {
+    let mut files = Vec::new();
+    if let Err(e) = collect_files(folder, false, &mut files) {
+        warn!("Unable to read folder for usage: {}: {}", folder.display(), e);
+    }
+    (files.len() as u64, files.iter().map(|file| file.size).sum())
+}
+
+// Helper function to find the stored files in a folder, and its
+// subfolders. A folder that doesn't exist has no files.
+fn collect_files(folder:&Path, secret:bool, files:&mut Vec<StoredFile>) -> io::Result<()> {
+    let entries = match fs::read_dir(folder) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            collect_files(&entry.path(), secret, files)?;
+        }
+        else {
+            files.push(StoredFile {
+                path: entry.path(),
+                size: metadata.len(),
+                stored: stored_time(&metadata),
+                secret,
+            });
+        }
+    }
+    Ok(())
+}
+
+// Helper function to get when a file was last changed on disk in any way.
+fn stored_time(metadata:&fs::Metadata) -> SystemTime {
+    let mut stored = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+    if let Ok(created) = metadata.created() {
+        stored = stored.max(created);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let changed = SystemTime::UNIX_EPOCH + Duration::from_secs(metadata.ctime().max(0) as u64);
+        stored = stored.max(changed);
+    }
+    stored
+}
+
+// Helper function to remove empty directories in a folder, keeping
+// those less than (keep_depth) levels down, counting the folder itself.
+// Returns true if the folder is left empty.
+fn remove_empty_dirs(folder:&Path, keep_depth:usize) -> bool {
+    let Ok(entries) = fs::read_dir(folder) else {
+        return false;
+    };
+    let mut empty = true;
+    for entry in entries.flatten() {
+        let is_dir = entry.file_type().is_ok_and(|file_type| file_type.is_dir());
+        if !is_dir || !remove_empty_dirs(&entry.path(), keep_depth.saturating_sub(1)) {
+            empty = false;
+        }
+    }
+    if empty && keep_depth == 0 {
+        return fs::remove_dir(folder).is_ok();
+    }
+    empty
+}
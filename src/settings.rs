@@ -1,5 +1,7 @@
 use serde::{Deserialize};
 
+use crate::accounts::ApiToken;
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct Settings {
     pub program_name: String,
@@ -7,11 +9,34 @@ pub struct Settings {
     pub program_devs: Vec<String>,
     pub program_web: String,
     pub byte_chunk: u32,
+    pub max_jobs: u32,
+    pub job_keep_secs: u64,
+    pub progress_interval_ms: u64,
+    pub memory_only: bool,
+    pub auth_enabled: bool,
+    pub users_file: String,
+    pub login_keep_secs: u64,
+    pub api_tokens: Vec<ApiToken>,
+    pub link_keep_secs: u64,
+    pub retention_interval_secs: u64,
+    pub retention_max_age_secs: u64,
+    pub retention_max_bytes: u64,
+    pub parallel_chunk: u32,
     pub secret_folder: String,
     pub thumb_folder: String,
     pub num_files_chars: u8,
     pub len_filename_chars: u8,
     pub file_len_chars: u8,
+    pub file_time_chars: u8,
+    pub file_mode_chars: u8,
     pub pw_protected_chars: u8,
     pub pw_chars: u8,
+    pub header_copies: u8,
+    pub noise_fill: bool,
+    pub volume_slots: u8,
+    pub volume_bit_planes: u8,
+    pub headerless: bool,
+    pub kdf_iterations: u32,
+    pub key_slots: u8,
+    pub trusted_keys: Vec<String>,
 }
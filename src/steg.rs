@@ -4,6 +4,8 @@
 // specifically in PNG format images.
 // Supported formats are rgb and rgba colour formats, although only
 // the rgb colour bytes are used to encode data into.
+// Data is read and written directly on the image colour samples,
+// see the bit_stream module.
 //
 // A pic coded image contains a particular byte string embedded in the image.
 // Here 'contains' implies embedded in the image colour bytes.
@@ -11,17 +13,71 @@
 //
 // Pic coded signature : specific, but arbitray number of bytes.
 // Password enabled : 1 byte, 'Y' or 'N'.
-// If password enabled : 32 byte hash of password.
+// Password hash : 32 byte hash of password, zeros if no password.
 // Number of files embedded : (num_files_chars) digit integer, leading zeros.
 // For each file section the following applies:
 //
+// File sync marker : specific, but arbitrary number of bytes.
+// File index : (num_files_chars) digit integer, leading zeros.
 // File name length : (len_filename_chars) digit integer, leading zeros.
 // File name : file name string in file name length bytes.
 // File length in bytes : (file_len_chars) digit integer, leading zeros.
+// File metadata : modification time and permissions, see the file_tree module.
 // File contents : file bytes in file length bytes.
-
+//
+// The file name is the path of the file relative to any directory that
+// was embedded, see the file_tree module. File sections embedded before
+// file metadata was recorded have a different sync marker, and no
+// file metadata.
+//
+// The header (signature, password and number of files) is a fixed length,
+// and is repeated (header_copies) times at pseudorandom locations in the
+// first bit plane of the image. If the header at the start of the image is
+// damaged the copies are majority voted to recover it.
+// The file sections skip over the header copies, and the sync marker
+// allows the remaining files to be found if a file section is damaged.
+//
+// If the password flag is 'V' the image holds deniable volumes instead
+// of file sections, see the volumes module. Volumes can also be embedded
+// headerless, with no signature at all.
+//
+// Files too large for one image can be split over several images,
+// in which case each image holds a part section, see the parts module.
+// Files can also be shared over several images so that only some of
+// them are needed, see the shares module.
+//
+// Files can be signed, in which case a signature section comes straight
+// after the header, see the signing module.
+// Files in an image can be added, removed, and replaced without
+// re-embedding the rest, see the edit module.
+// Files can be listed without extracting them, or only chosen files
+// extracted, see the listing module.
+//
+// If the password flag is 'K' the files are encrypted for recipients'
+// public keys instead of a password, see the recipients module.
+// If the password flag is 'S' the files are encrypted with key slots
+// for several passwords, see the key_slots module.
+//
+// Images coded before the header copies and sync markers were added
+// can still be extracted, see the legacy module.
+
+pub mod bench;
+pub mod bit_stream;
+pub mod container;
+pub mod edit;
+pub mod file_tree;
 pub mod image_read;
 pub mod image_write;
+pub mod key_slots;
+pub mod legacy;
+pub mod listing;
+pub mod parts;
+pub mod progress;
+pub mod recipients;
+pub mod rekey;
+pub mod shares;
+pub mod signing;
+pub mod volumes;
 
 extern crate image;
 extern crate ring;
@@ -35,25 +91,71 @@ use std::fs::{self, File};
 use std::io::{self, Read, Write};
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::{Instant, Duration};
+use zeroize::{Zeroize, Zeroizing};
 
 use crate::settings::Settings;
+use crate::steg::container::ContainerFile;
+use crate::steg::file_tree::{FileMeta, SourceFile};
+use crate::steg::listing::ListedFile;
+use crate::steg::parts::PartInfo;
+use crate::steg::progress::Progress;
+use crate::steg::shares::ShareInfo;
+use crate::steg::signing::{ManifestEntry, SignatureInfo, SIGNATURE_SECTION_LEN};
 use crate::SETTINGS;
 
 // Define program code here so not exposed in settings file.
-const PROG_CODE : &str = "PICCODER";
+pub const PROG_CODE : &str = "PICCODER";
+
+// Marker written at the start of every embedded file section.
+// Used to resynchronise reading if a file section is damaged.
+const SYNC_CODE : &str = "PKSYNC";
+
+// Marker written at the start of file sections that include file metadata.
+// Must be the same length as the plain sync marker.
+const META_SYNC_CODE : &str = "PKSYNM";
 
 // Error result enum.
 #[derive(Debug)]
 pub enum SteganographyError {
     IncorrectPassword,
+    IncorrectKey,
+    NoKeySlots,
+    NoFreeKeySlot,
+    InvalidKeySlot,
+    LastKeySlot,
+    RekeyNotSupported,
+    MissingParts { missing: Vec<u16>, count: u16 },
+    MixedSets,
+    NoParts,
+    NotEnoughShares { needed: u16 },
+    NoShares,
+    InvalidShares,
+    Cancelled,
 }
 
 // Display of Steganography specific errors.
 impl fmt::Display for SteganographyError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match *self {
+        match self {
             SteganographyError::IncorrectPassword => write!(f, "Incorrect password provided"),
+            SteganographyError::IncorrectKey => write!(f, "Private key does not match a recipient"),
+            SteganographyError::NoKeySlots => write!(f, "Image does not use key slots"),
+            SteganographyError::NoFreeKeySlot => write!(f, "No free key slots"),
+            SteganographyError::InvalidKeySlot => write!(f, "Key slot is not in use"),
+            SteganographyError::LastKeySlot => write!(f, "Can't remove the last key slot in use"),
+            SteganographyError::RekeyNotSupported => write!(f, "Password can't be changed for this image"),
+            SteganographyError::MissingParts { missing, count } => {
+                let missing: Vec<String> = missing.iter().map(|idx| idx.to_string()).collect();
+                write!(f, "Missing part(s) {} of {}", missing.join(", "), count)
+            }
+            SteganographyError::MixedSets => write!(f, "Images are from different sets"),
+            SteganographyError::NoParts => write!(f, "No images are part of a split set"),
+            SteganographyError::NotEnoughShares { needed } => write!(f, "Not enough shares, {} more needed", needed),
+            SteganographyError::NoShares => write!(f, "No images are shares of a threshold set"),
+            SteganographyError::InvalidShares => write!(f, "Shares do not recover the files"),
+            SteganographyError::Cancelled => write!(f, "Extraction cancelled"),
         }
     }
 }
@@ -81,14 +183,25 @@ pub struct Steganography {
     pub pic_coded: bool,
     pub user_permit: bool,
     pub pic_has_pw: bool,
+    pub pic_volumes: bool,
+    pub pic_recipients: bool,
+    pub pic_key_slots: bool,
+    pub pic_unknown: bool,
+    pub pic_legacy: bool,
+    pub pic_part: Option<PartInfo>,
+    pub pic_share: Option<ShareInfo>,
     pub pic_code_name_len: u8,
     pub pic_width: u32,
     pub pic_height: u32,
     pub pic_col_planes: u8,
+    pub header_bytes: Vec<u8>,
+    pub header_copy_locs: Vec<u64>,
+    pub skip_reserved: bool,
     pub row: u32,
     pub col: u32,
     pub plane: usize,
     pub bit: u8,
+    pub stream_pos: u64,
     pub bytes_read: u32,
     pub code_bytes: Vec<u8>,
     pub overhead_per_file: u16,
@@ -98,6 +211,18 @@ pub struct Steganography {
     pub embed_duration: Duration,
     pub embedded_files: Vec<EmbeddedFile>,
     pub retry_extract: bool,
+    pub noise_fill: bool,
+    pub headerless: bool,
+    pub signing_key: Option<Vec<u8>>,
+    pub signature: Option<SignatureInfo>,
+    pub file_digests: Vec<ManifestEntry>,
+    pub manifest_only: bool,
+    pub extract_selection: Vec<String>,
+    pub files_seen: u16,
+    pub listed_files: Vec<ListedFile>,
+    pub progress: Arc<Progress>,
+    pub extract_to_memory: bool,
+    pub memory_files: Vec<ContainerFile>,
 }
 
 // Initialise all struct variables.
@@ -108,6 +233,8 @@ impl Steganography {
 
         // Lock the global SETTINGS to obtain access to the Settings object.
         let settings = SETTINGS.lock().unwrap().clone();
+        let noise_fill = settings.noise_fill;
+        let headerless = settings.headerless;
 
         Steganography {
             settings,
@@ -118,14 +245,25 @@ impl Steganography {
             pic_coded: false,
             user_permit: false,
             pic_has_pw: false,
+            pic_volumes: false,
+            pic_recipients: false,
+            pic_key_slots: false,
+            pic_unknown: false,
+            pic_legacy: false,
+            pic_part: None,
+            pic_share: None,
             pic_code_name_len: 0,
             pic_width: 0,
             pic_height: 0,
             pic_col_planes: 0,
+            header_bytes: Vec::new(),
+            header_copy_locs: Vec::new(),
+            skip_reserved: false,
             row: 0,
             col: 0,
             plane: 0,
             bit: 0,
+            stream_pos: 0,
             bytes_read: 0,
             code_bytes: Vec::with_capacity(0),
             overhead_per_file: 0,
@@ -135,6 +273,18 @@ impl Steganography {
             embed_duration: Duration::new(0, 0),
             embedded_files: Vec::new(),
             retry_extract: false,
+            noise_fill,
+            headerless,
+            signing_key: None,
+            signature: None,
+            file_digests: Vec::new(),
+            manifest_only: false,
+            extract_selection: Vec::new(),
+            files_seen: 0,
+            listed_files: Vec::new(),
+            progress: Arc::new(Progress::new()),
+            extract_to_memory: false,
+            memory_files: Vec::new(),
         }
     }
 }
@@ -149,12 +299,22 @@ impl Steganography {
         self.pic_coded = false;
         self.user_permit = false;
         self.pic_has_pw = false;
+        self.pic_volumes = false;
+        self.pic_recipients = false;
+        self.pic_key_slots = false;
+        self.pic_unknown = false;
+        self.pic_legacy = false;
+        self.pic_part = None;
+        self.pic_share = None;
         self.pic_code_name_len = 0;
         self.pic_width = 0;
         self.pic_height = 0;
         self.pic_col_planes = 0;
+        self.header_bytes = Vec::new();
+        self.header_copy_locs = Vec::new();
         self.embed_capacity = 0;
         self.retry_extract = false;
+        self.signature = None;
     }
 }
 
@@ -167,6 +327,8 @@ impl Steganography {
         self.col = 0;
         self.plane = 0;
         self.bit = 0;
+        self.stream_pos = 0;
+        self.skip_reserved = false;
         self.bytes_read = 0;
     }
 }
@@ -176,7 +338,36 @@ impl Steganography {
     pub fn load_new_file(&mut self, in_file:String) {
         // Initialise timer for function.
         let load_start = Instant::now();
+        let img_result = image::open(&in_file);
+        self.load_image(in_file, img_result, load_start);
+    }
+}
 
+// Method to load a brand new image for analysis from memory,
+// such as an image uploaded in memory only mode.
+// The name is only used for reporting.
+impl Steganography {
+    pub fn load_image_bytes(&mut self, name:String, bytes:&[u8]) {
+        // Initialise timer for function.
+        let load_start = Instant::now();
+        let img_result = image::load_from_memory(bytes);
+        self.load_image(name, img_result, load_start);
+    }
+}
+
+// Method to load a brand new image for analysis, read from disk or held in memory.
+impl Steganography {
+    pub fn load_source(&mut self, source:&SourceFile) {
+        match &source.memory {
+            Some((data, _)) => self.load_image_bytes(source.file_name.clone(), data),
+            None => self.load_new_file(source.path.clone()),
+        }
+    }
+}
+
+// Method to analyse a newly opened image.
+impl Steganography {
+    fn load_image(&mut self, in_file:String, img_result:image::ImageResult<DynamicImage>, load_start:Instant) {
         // Do image intialisatioins to clean up after any
         // successful or failed image loading.
         // That is, parameters for loaded and imbedded image.
@@ -193,14 +384,14 @@ impl Steganography {
         let img_path_string = img_path.to_string_lossy().into_owned();
         self.image_file = img_path_string;
 
-        let img_result = image::open(&img_path);
         // Handle exceptions, specific file not found, and generic.
-        let _img = match img_result {
+        match img_result {
             Ok(_img) => {
                 // Set flag to indicate we have an image to process.
+                // The image is kept without copying it, so there is
+                // only the one copy to wipe in memory only mode.
                 self.img_to_proc = true;
-                self.image = Some(_img.clone());
-                _img
+                self.image = Some(_img);
             }
             Err(err) => {
                 // Set flag indicating that there was an issue opening the file.
@@ -211,22 +402,18 @@ impl Steganography {
                     image::ImageError::IoError(io_err) if io_err.kind() == std::io::ErrorKind::NotFound => {
 
                         warn!("Warning file not found: {}", in_file.clone());
-                        // Return a placeholder image.
-                        image::DynamicImage::new_rgb8(1, 1)
                     }
                     // Generic exception.
                     _ => {
                         error!("Error openning image file: {}", in_file.clone());
-                        // Return a placeholder image.
-                        image::DynamicImage::new_rgb8(1, 1)
                     }
                 }
             }
-        };
+        }
 
         // If we have an image file open, then read the parameters.
         // Need to check if 3 colour planes as well.
-        if cont_ckh == true {
+        if cont_ckh {
             if let Some(image) = &self.image {
                 // Get image width and height
                 (self.pic_width, self.pic_height) = image.dimensions();
@@ -263,37 +450,27 @@ impl Steganography {
         // Basically how many bits get used when embeddng files
         // in an image.
         // Here capacity is in bytes.
-        if cont_ckh == true {
-            let _embed_bytes: u32 = self.pic_width * self.pic_height * self.pic_col_planes as u32;
-            self.embed_capacity = _embed_bytes as u64;
-
-            info!("Absolute host file capacity (bytes): {}", self.embed_capacity);
-
-            // There is a fixed amount of capacity that must be reserved.
-            // Allocation for pic code preamble.
-            // Allocation (pw_protected_chars) byte for is password protected
-            // Allocation (pw_chars) bytes for password
-            // Allocation (num_files_chars) bytes for number of files
-            self.embed_capacity = self.embed_capacity - PROG_CODE.len() as u64 - 36;
-            self.embed_capacity -= PROG_CODE.len() as u64;
-            self.embed_capacity -= self.settings.pw_protected_chars as u64;
-            self.embed_capacity -= self.settings.pw_chars as u64;
-            self.embed_capacity -= self.settings.num_files_chars as u64;
-            info!("Embedding capacity (bytes): {}", self.embed_capacity);
+        if cont_ckh {
+            self.set_layout(false);
 
             // There is also an overhead per file to cover the file name and size etc.
             // Need to account for this when embedding.
+            // File sync marker and file index.
             // File name length (len_filename_chars) leading zeros. Assume worse case.
             // File name : file name string in file name length bytes.
             // File length in bytes : leading zeros.
-            self.overhead_per_file = self.settings.len_filename_chars as u16;
+            // File metadata : modification time and permissions.
+            self.overhead_per_file = META_SYNC_CODE.len() as u16;
+            self.overhead_per_file += self.settings.num_files_chars as u16;
+            self.overhead_per_file += self.settings.len_filename_chars as u16;
             self.overhead_per_file += u16::pow(10, self.settings.len_filename_chars as u32);
             self.overhead_per_file += self.settings.file_len_chars as u16;
+            self.overhead_per_file += file_tree::meta_len(&self.settings) as u16;
         }
 
         // Check if the file is already pic coded.
         self.check_for_code();
-        if self.pic_coded == true {
+        if self.pic_coded {
             info!("Image file contains preamble code.");
 
             // Now that we know that the image is pic coded,
@@ -303,7 +480,7 @@ impl Steganography {
 
             // If password protected can't go further, until the user
             // gives a valid password.
-            if self.pic_has_pw == false {
+            if !self.pic_has_pw {
                 // If embedded image is not password protected
                 // we can continue.
                 info!("Files embedded WITHOUT password.")
@@ -311,6 +488,21 @@ impl Steganography {
             else {
                 info!("Files embedded WITH password.")
             }
+
+            // Images coded with the original layout have no
+            // header copies, parts or shares.
+            if !self.pic_volumes && !self.pic_recipients && !self.pic_key_slots {
+                self.check_for_legacy();
+            }
+
+            // See if the image holds part of a split set,
+            // or a share of a threshold set.
+            if !self.pic_volumes && !self.pic_recipients && !self.pic_key_slots && !self.pic_legacy {
+                self.check_for_part();
+                if self.pic_part.is_none() {
+                    self.check_for_share();
+                }
+            }
         }
 
         // Determine delta time for function.
@@ -319,6 +511,228 @@ impl Steganography {
     }
 }
 
+// Method to set up the header layout and embedding capacity.
+// Normally there is a header at the start of the image and the
+// header copies, but headerless images only have the volume salt
+// in the header area, and no copies.
+impl Steganography {
+    pub fn set_layout(&mut self, headerless:bool) {
+        self.embed_capacity = self.stream_len();
+
+        info!("Absolute host file capacity (bytes): {}", self.embed_capacity);
+
+        // There is a fixed amount of capacity that must be reserved.
+        // Allocation for the header at the start of the image,
+        // and for each of the header copies.
+        if headerless {
+            self.header_copy_locs = Vec::new();
+        }
+        else {
+            self.header_copy_locs = self.header_copy_locations();
+        }
+        let header_bytes = self.header_len() as u64 * (1 + self.header_copy_locs.len() as u64);
+        self.embed_capacity = self.embed_capacity.saturating_sub(header_bytes);
+        info!("Embedding capacity (bytes): {}", self.embed_capacity);
+    }
+}
+
+// Method to get the total number of bytes that can be
+// written to the image colour planes.
+impl Steganography {
+    pub fn stream_len(&self) -> u64 {
+        // Only the rgb colour planes are written to,
+        // and all 8 bits of each colour byte can be used.
+        self.pic_width as u64 * self.pic_height as u64 * 3
+    }
+}
+
+// Method to get the length of the fixed header.
+// The header is the signature, password details, and number of files.
+impl Steganography {
+    pub fn header_len(&self) -> u32 {
+        PROG_CODE.len() as u32
+            + self.settings.pw_protected_chars as u32
+            + self.settings.pw_chars as u32
+            + self.settings.num_files_chars as u32
+    }
+}
+
+// Method to convert a byte position in the embedding stream into
+// the row, column, colour plane, and bit where that byte starts.
+// Bits run along each row, then down the image, then through each
+// colour plane, and finally on to the next bit of the colour bytes.
+impl Steganography {
+    pub fn stream_location(&self, stream_pos:u64) -> (u32, u32, usize, u8) {
+        let bit_pos = stream_pos * 8;
+        let width = self.pic_width as u64;
+        let pixels = width * self.pic_height as u64;
+
+        let col = (bit_pos % width) as u32;
+        let row = ((bit_pos / width) % self.pic_height as u64) as u32;
+        let plane = ((bit_pos / pixels) % 3) as usize;
+        let bit = (bit_pos / (pixels * 3)) as u8;
+        (row, col, plane, bit)
+    }
+}
+
+// Method to move the read/write position to a byte position
+// in the embedding stream.
+impl Steganography {
+    pub fn seek_stream(&mut self, stream_pos:u64) {
+        (self.row, self.col, self.plane, self.bit) = self.stream_location(stream_pos);
+        self.stream_pos = stream_pos;
+    }
+}
+
+// Method to move the read/write position to a byte position
+// in the file data following the header.
+// Position takes into account the header copies that are skipped.
+impl Steganography {
+    pub fn seek_payload(&mut self, payload_pos:u64) {
+        let mut stream_pos = self.header_len() as u64 + payload_pos;
+        for loc in &self.header_copy_locs {
+            if *loc <= stream_pos {
+                stream_pos += self.header_len() as u64;
+            }
+        }
+        self.skip_reserved = true;
+        self.seek_stream(stream_pos);
+    }
+}
+
+// Method to get the current byte position in the file data
+// following the header, the reverse of seek_payload.
+impl Steganography {
+    pub fn payload_pos(&self) -> u64 {
+        let mut payload_pos = self.stream_pos.saturating_sub(self.header_len() as u64);
+        for loc in &self.header_copy_locs {
+            if *loc < self.stream_pos {
+                payload_pos = payload_pos.saturating_sub(self.header_len() as u64);
+            }
+        }
+        payload_pos
+    }
+}
+
+// Method to check if a stream position is at the start of, or in,
+// a reserved header copy region.
+// If it is, the stream position at the end of the region is returned.
+impl Steganography {
+    pub fn reserved_end(&self, stream_pos:u64) -> Option<u64> {
+        let header_len = self.header_len() as u64;
+        self.header_copy_locs
+            .iter()
+            .find(|loc| stream_pos >= **loc && stream_pos < **loc + header_len)
+            .map(|loc| *loc + header_len)
+    }
+}
+
+// Method to determine where the header copies are located.
+// Copies are at pseudorandom locations spread through the first
+// bit plane of the image, so that damage to one area of the image
+// is unlikely to destroy all of them.
+// The locations only depend on the image dimensions, so they can be
+// found again when the image is loaded.
+impl Steganography {
+    pub fn header_copy_locations(&self) -> Vec<u64> {
+        let header_len = self.header_len() as u64;
+        let num_copies = self.settings.header_copies as u64;
+        let mut locations = Vec::new();
+
+        // Copies go between the end of the primary header and the
+        // end of the first bit plane of all colours.
+        // Region is divided into a band for each copy, and
+        // each copy is placed somewhere in its band.
+        let first_plane_bytes = self.stream_len() / 8;
+        let region_start = header_len * 2;
+        if num_copies == 0 || first_plane_bytes <= region_start {
+            return locations;
+        }
+        let band_len = (first_plane_bytes - region_start) / num_copies;
+        if band_len < header_len {
+            warn!("Image too small for header copies.");
+            return locations;
+        }
+
+        // Simple xorshift generator seeded from the image size.
+        let mut seed:u64 = ((self.pic_width as u64) << 32) | self.pic_height as u64;
+        seed ^= 0x9E37_79B9_7F4A_7C15;
+        for band in 0..num_copies {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            let offset = seed % (band_len - header_len + 1);
+            locations.push(region_start + band * band_len + offset);
+        }
+        debug!("Header copy locations: {:?}", locations);
+        locations
+    }
+}
+
+// Method to read the header from the image.
+// The header at the start of the image is used if it is intact,
+// otherwise the header copies are majority voted to recover it.
+impl Steganography {
+    pub fn read_header(&mut self) {
+        let header_len = self.header_len();
+        self.skip_reserved = false;
+        self.seek_stream(0);
+        self.read_data_from_image(header_len);
+        let primary = self.code_bytes.clone();
+        if self.header_valid(&primary) {
+            self.header_bytes = primary;
+            return;
+        }
+
+        // Primary header no good, so try the copies.
+        if self.header_copy_locs.is_empty() {
+            self.header_bytes = primary;
+            return;
+        }
+        info!("Header not found at start of image, checking header copies.");
+        let mut copies = vec![primary];
+        for loc in self.header_copy_locs.clone() {
+            self.seek_stream(loc);
+            self.read_data_from_image(header_len);
+            copies.push(self.code_bytes.clone());
+        }
+        self.header_bytes = majority_vote(&copies);
+        if self.header_valid(&self.header_bytes) {
+            warn!("Header recovered from header copies.");
+        }
+    }
+}
+
+// Method to check that header bytes have a valid
+// signature and password (or volume) flag.
+impl Steganography {
+    pub fn header_valid(&self, header:&[u8]) -> bool {
+        let code_len = PROG_CODE.len();
+        if header.len() < code_len + self.settings.pw_protected_chars as usize {
+            return false;
+        }
+        header[..code_len] == *PROG_CODE.as_bytes()
+            && matches!(header[code_len], b'Y' | b'N' | b'V' | b'K' | b'S')
+    }
+}
+
+// Helper function to majority vote copies of the same bytes.
+// Each bit is set if it is set in more than half the copies.
+fn majority_vote(copies:&[Vec<u8>]) -> Vec<u8> {
+    let len = copies.iter().map(|c| c.len()).min().unwrap_or(0);
+    let mut voted = vec![0u8; len];
+    for (idx, byte) in voted.iter_mut().enumerate() {
+        for bit in 0..8 {
+            let mask = 1u8 << bit;
+            let votes = copies.iter().filter(|c| c[idx] & mask != 0).count();
+            if votes * 2 > copies.len() {
+                *byte |= mask;
+            }
+        }
+    }
+    voted
+}
+
 // Method to check if image has been previously encoded,
 // that is, it contains the preamble code.
 impl Steganography {
@@ -332,36 +746,18 @@ impl Steganography {
         }
 
         // File large enough to hold preamble code.
-        // Extract data from image and match with code.
-        // Read number of bytes for the pic code.
-        let bytes_to_read:u32 = PROG_CODE.len().try_into().unwrap();
-        self.read_data_from_image(bytes_to_read);
-        if self.bytes_read != bytes_to_read {
-            error!("Expected bytes: {}, bytes read: {}", bytes_to_read, self.bytes_read);
-            info!("Image file is not pic coded.");  
-            self.pic_coded = false;
-            return;
+        // Extract the header from the image and match with code.
+        self.read_header();
+        if self.header_valid(&self.header_bytes) {
+            self.pic_coded = true;
+            info!("Image is pic coded.");
         }
         else {
-            // Compare the byte array read with the pic coded array (string).
-            let string_result = String::from_utf8((&*self.code_bytes).to_vec());
-            match string_result {
-                Ok(string) => {
-                    // String read so need to see if it matches the code.
-                    if string == PROG_CODE {
-                        self.pic_coded = true;
-                        info!("Image is pic coded.");
-                    }
-                    else {
-                        self.pic_coded = false;
-                        info!("Image is not pic coded.");
-                    }
-                }
-                _ => {
-                    self.pic_coded = false;
-                    info!("Image is not pic coded.");
-                }
-            }
+            // Without a header the image may still hold a headerless volume,
+            // which can only be found with the password.
+            self.pic_coded = false;
+            self.pic_unknown = true;
+            info!("Image is not pic coded, or is headerless.");
         }
     }
 }
@@ -369,32 +765,19 @@ impl Steganography {
 // Method to check if image has a password.
 impl Steganography {
     pub fn check_for_password(&mut self) {
-
-        // Read number of bytes for whether or not there is a password.
-        let bytes_to_read:u32 = self.settings.pw_protected_chars as u32;
-        self.read_data_from_image(bytes_to_read);
-        if self.bytes_read != bytes_to_read {
-            error!("Expected bytes: {}, bytes read: {}", bytes_to_read, self.bytes_read);
-            info!("Image does not include a password.");  
-            self.user_permit = false;
-            return;
+        // Check for Y(es) or N(o) re password, V(olumes), K(eys), or key S(lots).
+        // Follows the preamble code in the header.
+        // Volumes and key slots always need a password, and recipients a private key.
+        self.pic_volumes = self.header_bytes.get(PROG_CODE.len()) == Some(&b'V');
+        self.pic_recipients = self.header_bytes.get(PROG_CODE.len()) == Some(&b'K');
+        self.pic_key_slots = self.header_bytes.get(PROG_CODE.len()) == Some(&b'S');
+        if self.header_bytes.get(PROG_CODE.len()) == Some(&b'Y') || self.pic_volumes || self.pic_recipients || self.pic_key_slots {
+            self.pic_has_pw = true;
+            info!("Image includes a password.");
         }
         else {
-            // Check for Y(es) or N(o) re password.
-            let string_result = String::from_utf8((&*self.code_bytes).to_vec());
-            match string_result {
-                Ok(string) => {
-                    // String read so need to see if it is Y or N.
-                    if string == "Y" {
-                        self.pic_has_pw = true;
-                        info!("Image includes a password.");
-                    }
-                }
-                _ => {
-                    self.pic_has_pw = false;
-                    info!("Image does not include a password.");
-                }
-            }
+            self.pic_has_pw = false;
+            info!("Image does not include a password.");
         }
     }
 }
@@ -409,24 +792,78 @@ impl Steganography {
 
         // Initialise embedded files prior to starting.
         self.embedded_files = Vec::new();
+        self.memory_files = Vec::new();
+        self.signature = None;
+        self.reset_listing();
+
+        // Volumes are located and checked by the password,
+        // so are extracted separately.
+        if self.pic_volumes || self.pic_unknown {
+            let result = self.extract_volume(&pw);
+            self.retry_extract = result.is_err();
+
+            // Determine delta time for function.
+            self.extract_duration = extract_start.elapsed();
+            info!("Time for file(s) extraction: {:?}", self.extract_duration);
+            return result;
+        }
+
+        // Files for recipients need a private key, given as hex
+        // digits in place of the password.
+        if self.pic_recipients {
+            let result = match recipients::parse_key(&pw) {
+                Some(private_key) => self.extract_recipient(&private_key),
+                None => Err(SteganographyError::IncorrectKey),
+            };
+            self.retry_extract = result.is_err();
+
+            // Determine delta time for function.
+            self.extract_duration = extract_start.elapsed();
+            info!("Time for file(s) extraction: {:?}", self.extract_duration);
+            return result;
+        }
 
-        // If retrying extraction then have to reset read position.
-        // Here retries occur if wrong password entered.
-        if self.retry_extract == true {
-            // Reset to start of file
-            self.init_embed_params();
+        // Key slots are checked against the password as they are opened.
+        if self.pic_key_slots {
+            let result = self.extract_key_slots(&pw);
+            self.retry_extract = result.is_err();
 
-            // Need to point to password location.
-            // Offset by previous file lengths.
-            let pw_offset:u32 = PROG_CODE.len() as u32 + self.settings.pw_protected_chars as u32;
-            self.read_data_from_image(pw_offset);
+            // Determine delta time for function.
+            self.extract_duration = extract_start.elapsed();
+            info!("Time for file(s) extraction: {:?}", self.extract_duration);
+            return result;
+        }
+
+        // A part of a split set can only be extracted on its own
+        // if it is the only part, otherwise report the other parts.
+        if let Some(part_info) = self.pic_part.clone() {
+            if part_info.part_count == 1 {
+                let result = self.extract_loaded_part(pw);
+                self.retry_extract = matches!(result, Err(SteganographyError::IncorrectPassword));
+                return result;
+            }
+            let missing = (1..=part_info.part_count).filter(|idx| *idx != part_info.part_idx).collect();
+            self.extract_duration = extract_start.elapsed();
+            return Err(SteganographyError::MissingParts { missing, count: part_info.part_count });
+        }
+
+        // Likewise a share of a threshold set can only be extracted
+        // on its own if only one share is needed.
+        if let Some(share_info) = self.pic_share.clone() {
+            if share_info.threshold == 1 {
+                let result = self.extract_loaded_share(pw);
+                self.retry_extract = matches!(result, Err(SteganographyError::IncorrectPassword));
+                return result;
+            }
+            self.extract_duration = extract_start.elapsed();
+            return Err(SteganographyError::NotEnoughShares { needed: share_info.threshold - 1 });
         }
 
         // If password required then check it.
-        if self.pic_has_pw == true {
+        if self.pic_has_pw {
             // Password required, so check password provided.
             self.check_valid_password(pw);
-            if self.user_permit == true {
+            if self.user_permit {
                 info!("Correct password provided.");
 
                 self.retry_extract = false;
@@ -445,227 +882,314 @@ impl Steganography {
         }
         // Either password not required or correct password entered.
         // Either way we can proceed with extracting data.
-        self.get_embedded_data();
+        let result = match self.pic_legacy {
+            true => self.get_legacy_data(),
+            false => self.get_embedded_data(),
+        };
 
         // Determine delta time for function.
         self.extract_duration = extract_start.elapsed();
         info!("Time for file(s) extraction: {:?}", self.extract_duration);
 
-        Ok(())
+        result
     }
 }
 
 // Method to check user's password entry.
 impl Steganography {
     pub fn check_valid_password(&mut self, password: String) {
-        // Before checking the password we have to get the
-        // hashed password stored in the image.
+        // The hashed password stored in the image follows the
+        // password flag in the header.
         // The password is a SHA-256 so always 32 bytes long.
-        let bytes_to_read:u32 = self.settings.pw_chars as u32;
-        self.read_data_from_image(bytes_to_read);
-        if self.bytes_read != bytes_to_read {
-            error!("Expected bytes: {}, bytes read: {}", bytes_to_read, self.bytes_read);
+        let hash_start = PROG_CODE.len() + self.settings.pw_protected_chars as usize;
+        let hash_end = hash_start + self.settings.pw_chars as usize;
+        if self.header_bytes.len() < hash_end {
+            error!("Header too short for password: {}", self.header_bytes.len());
             self.user_permit = false;
             return;
         }
-        else {
-            // Check password against hash of user entry.
-            match digest::digest(&digest::SHA256, password.as_bytes()).as_ref() == &self.code_bytes[..] {
-                true => {
-                    self.user_permit = true;
-                    info!("User entered password matches.");
-                }
-                false => {
-                    self.user_permit = false;
-                    info!("User entered password does not match.");
-                }
+
+        // Check password against hash of user entry.
+        match digest::digest(&digest::SHA256, password.as_bytes()).as_ref() == &self.header_bytes[hash_start..hash_end] {
+            true => {
+                self.user_permit = true;
+                info!("User entered password matches.");
+            }
+            false => {
+                self.user_permit = false;
+                info!("User entered password does not match.");
             }
         }
     }
 }
 
+// Method to get the number of files from the header.
+impl Steganography {
+    pub fn header_num_files(&self) -> Option<u16> {
+        let num_start = self.header_len() as usize - self.settings.num_files_chars as usize;
+        let num_bytes = self.header_bytes.get(num_start..self.header_len() as usize)?;
+        String::from_utf8(num_bytes.to_vec()).ok()?.parse().ok()
+    }
+}
+
 // Method to get embedded data from the image.
+// If the extraction is cancelled, the files extracted so far are removed.
 impl Steganography {
-    pub fn get_embedded_data(&mut self) {
+    pub fn get_embedded_data(&mut self) -> Result<(), SteganographyError> {
 
         // First get the number of files embedded.
-        let bytes_to_read:u32 = self.settings.len_filename_chars as u32;
-        self.read_data_from_image(bytes_to_read);
-        if self.bytes_read != bytes_to_read {
-            error!("Expected bytes: {}, bytes read: {}", bytes_to_read, self.bytes_read);
-            return;
-        }
-        else {
-            let _string_result = String::from_utf8((&*self.code_bytes).to_vec());
-            match _string_result {
-                Ok(string) => {
-                    let num_files:u8 = string.parse().unwrap();
-                    info!("Number of embedded files: {}", num_files);
-
-                    // Let's process each embedded file, one by one.
-                    for _idx in 1..= num_files {
-
-                        // First get the length of the file name.
-                        let bytes_to_read:u32 = self.settings.num_files_chars as u32;
-                        self.read_data_from_image(bytes_to_read);
-                        if self.bytes_read != bytes_to_read {
-                            error!("Expected bytes: {}, bytes read: {}", bytes_to_read, self.bytes_read);
-                            return;
+        let num_files = match self.header_num_files() {
+            Some(num_files) => num_files,
+            None => {
+                warn!("Invalid number of files length.");
+                return Ok(());
+            }
+        };
+        info!("Number of embedded files: {}", num_files);
+
+        // File sections start straight after the header,
+        // or after the signature section if the files are signed.
+        self.seek_payload(0);
+        let signature = self.read_signature();
+        self.file_digests = Vec::new();
+
+        // Position of the last file section found.
+        // If a file section is damaged we search for the next
+        // sync marker from here.
+        let mut section_pos:u64 = self.payload_pos();
+        let mut files_found:u16 = 0;
+
+        // Let's process each embedded file, one by one.
+        while files_found < num_files {
+            // Make sure we are at the start of a file section.
+            // If not search for the next one.
+            let has_meta = match self.read_sync_marker() {
+                Some(has_meta) => has_meta,
+                None => {
+                    warn!("File section sync marker not found, searching for next file.");
+                    match self.find_sync_marker(section_pos + 1) {
+                        Some(pos) => {
+                            self.seek_payload(pos);
+                            self.read_sync_marker().unwrap_or(false)
                         }
-                        else {
-                            let _string_result = String::from_utf8((&*self.code_bytes).to_vec());
-                            match _string_result {
-                                Ok(string) => {
-                                    let file_name_len:u8 = string.parse().unwrap();
-                                    info!("File name length: {}", file_name_len);
-
-                                    // Now that we have the length of the file name we can extract it.
-                                    let bytes_to_read:u32 = file_name_len as u32;
-                                    self.read_data_from_image(bytes_to_read);
-                                    if self.bytes_read != bytes_to_read {
-                                        error!("Expected bytes: {}, bytes read: {}", bytes_to_read, self.bytes_read);
-                                        return;
-                                    }
-                                    else {
-                                        let _string_result = String::from_utf8((&*self.code_bytes).to_vec());
-                                        match _string_result {
-                                            Ok(string) => {
-                                                let file_name:String = string;
-                                                info!("Embedded file name: {}", file_name);
-
-                                                // Now we need to get the length of the file.
-                                                let bytes_to_read:u32 = self.settings.file_len_chars as u32;
-                                                self.read_data_from_image(bytes_to_read);
-                                                if self.bytes_read != bytes_to_read {
-                                                    error!("Expected bytes: {}, bytes read: {}", bytes_to_read, self.bytes_read);
-                                                    return;
-                                                }
-                                                else {
-                                                    let _string_result = String::from_utf8((&*self.code_bytes).to_vec());
-                                                    match _string_result {
-                                                        Ok(string) => {
-                                                            let file_len:u32 = string.parse().unwrap();
-                                                            info!("File length: {}", file_len);
-
-                                                            // Now we have all the file details, we can
-                                                            // read the data from the image and construct
-                                                            // the file.
-                                                            let _ = self.extract_file(file_len, file_name);
-                                                        }
-                                                        _ => {
-                                                            warn!("Invalid file length.");
-                                                        }
-                                                    }
-                                                }
-                                            }
-                                            _ => {
-                                                warn!("Invalid file name.");
-                                            }
-                                        }
-                                    }                       
-                                }
-                                _ => {
-                                    warn!("Invalid file name length.");
-                                }
-                            }
+                        None => {
+                            warn!("No further file sections found.");
+                            break;
                         }
                     }
-                    
-                    // Go through extracted files and check if embedded.
-                    // First, collect the file names of embedded PNGs for analysis.
-                    let png_file_names: Vec<String> = self.embedded_files
-                        .iter()
-                        .filter(|file| file.file_type == "image/png")
-                        .map(|file| file.file_name.clone())
-                        .collect();
-
-                    // Create a HashMap to store the coded status of each file by its file name.
-                    // This is so we can update the class veriable for embedded_files.
-                    let mut file_coded_map: HashMap<String, bool> = HashMap::new();
-
-                    // Perform analysis on collected file names.
-                    for file_name in png_file_names {
-
-                        // Clone the file name before passing to avoid moving it
-                        self.load_new_file(file_name.clone());
-
-                        // Update hash map of embeded status of files.
-                        let coded_status = self.pic_coded;
-                        file_coded_map.insert(file_name, coded_status);
-                    }
-
-                    // Now that the analysis is done, mutably iterate over embedded_files to set file_coded
-                    // status in the class variable returned to main.
-                    for file in &mut self.embedded_files {
-                        // Check if the file was in the analysis result and set the file_coded attribute.
-                        if let Some(&coded_status) = file_coded_map.get(&file.file_name) {
-                            file.file_coded = coded_status; // Set the coded status for the file.
-                            debug!("Updated file_coded status for: {:?} to {:?}", file.file_name, file.file_coded);
+                }
+            };
+            section_pos = self.payload_pos() - SYNC_CODE.len() as u64;
+
+            // Now that we have the file details, we can read the data
+            // from the image and construct the file.
+            match self.read_file_details(has_meta) {
+                Some((file_idx, file_name, file_len, meta)) => {
+                    info!("Embedded file {}: {}, length: {}", file_idx, file_name, file_len);
+                    match self.extract_file(file_len, file_name, meta) {
+                        Err(err) if err.kind() == io::ErrorKind::Interrupted => {
+                            info!("Extraction cancelled.");
+                            self.remove_extracted_files();
+                            self.memory_files = Vec::new();
+                            return Err(SteganographyError::Cancelled);
                         }
+                        Err(err) => warn!("Failed to extract file: {}", err),
+                        Ok(()) => (),
                     }
+                    files_found = file_idx;
                 }
-                _ => {
-                    warn!("Invalid number of files length.");
+                None => {
+                    warn!("Invalid file section.");
+                    // Carry on looking from after this damaged section.
+                    self.seek_payload(section_pos + 1);
                 }
             }
         }
+
+        // Check the signature against the files extracted.
+        if let Some((public_key, signature_bytes)) = signature {
+            let entries = std::mem::take(&mut self.file_digests);
+            self.verify_signature(public_key, &signature_bytes, &entries);
+        }
+
+        // Go through extracted files and check if embedded.
+        self.analyse_extracted_files();
+        Ok(())
+    }
+}
+
+// Method to check which of the extracted files are
+// themselves pic coded images.
+impl Steganography {
+    pub fn analyse_extracted_files(&mut self) {
+        // First, collect the file names of embedded PNGs for analysis.
+        let png_file_names: Vec<String> = self.embedded_files
+            .iter()
+            .filter(|file| file.file_type == "image/png")
+            .map(|file| file.file_name.clone())
+            .collect();
+
+        // Create a HashMap to store the coded status of each file by its file name.
+        // This is so we can update the class veriable for embedded_files.
+        let mut file_coded_map: HashMap<String, bool> = HashMap::new();
+
+        // Perform analysis on collected file names.
+        for file_name in png_file_names {
+
+            // Clone the file name before passing to avoid moving it
+            self.load_new_file(file_name.clone());
+
+            // Update hash map of embeded status of files.
+            let coded_status = self.pic_coded;
+            file_coded_map.insert(file_name, coded_status);
+        }
+
+        // Now that the analysis is done, mutably iterate over embedded_files to set file_coded
+        // status in the class variable returned to main.
+        for file in &mut self.embedded_files {
+            // Check if the file was in the analysis result and set the file_coded attribute.
+            if let Some(&coded_status) = file_coded_map.get(&file.file_name) {
+                file.file_coded = coded_status; // Set the coded status for the file.
+                debug!("Updated file_coded status for: {:?} to {:?}", file.file_name, file.file_coded);
+            }
+        }
+    }
+}
+
+// Method to read the sync marker at the current position,
+// and check that it is correct.
+// Returns whether the file section has file metadata,
+// or None if there is no sync marker.
+impl Steganography {
+    pub fn read_sync_marker(&mut self) -> Option<bool> {
+        let bytes_to_read:u32 = SYNC_CODE.len() as u32;
+        if self.payload_pos() + bytes_to_read as u64 > self.embed_capacity {
+            return None;
+        }
+        self.read_data_from_image(bytes_to_read);
+        if self.code_bytes == META_SYNC_CODE.as_bytes() {
+            Some(true)
+        }
+        else if self.code_bytes == SYNC_CODE.as_bytes() {
+            Some(false)
+        }
+        else {
+            None
+        }
+    }
+}
+
+// Method to search for the next sync marker in the file data,
+// starting from a position in the file data.
+// Returns the position of the sync marker if found.
+impl Steganography {
+    pub fn find_sync_marker(&mut self, start_pos:u64) -> Option<u64> {
+        let sync_bytes = SYNC_CODE.as_bytes();
+        let meta_sync_bytes = META_SYNC_CODE.as_bytes();
+        let mut search_pos = start_pos;
+
+        // Read chunks from the image and search each one.
+        // Chunks overlap so that a marker split over two chunks is found.
+        while search_pos + sync_bytes.len() as u64 <= self.embed_capacity {
+            let remaining = self.embed_capacity - search_pos;
+            let bytes_to_read = remaining.min(self.settings.byte_chunk as u64) as u32;
+            self.seek_payload(search_pos);
+            self.read_data_from_image(bytes_to_read);
+            if let Some(idx) = self.code_bytes.windows(sync_bytes.len()).position(|w| w == sync_bytes || w == meta_sync_bytes) {
+                return Some(search_pos + idx as u64);
+            }
+            if bytes_to_read as usize <= sync_bytes.len() {
+                break;
+            }
+            search_pos += (bytes_to_read as usize - sync_bytes.len() + 1) as u64;
+        }
+        None
+    }
+}
+
+// Method to read the details of a file section following the sync marker.
+// That is the file index, file name, file length, and file metadata
+// if the section has it.
+// Returns None if any of the details are not valid.
+impl Steganography {
+    pub fn read_file_details(&mut self, has_meta:bool) -> Option<(u16, String, u32, Option<FileMeta>)> {
+        // First get the index of the file.
+        let file_idx:u16 = self.read_number(self.settings.num_files_chars as u32)?;
+
+        // Then the length of the file name.
+        let file_name_len:u32 = self.read_number(self.settings.len_filename_chars as u32)?;
+        if file_name_len == 0 || self.payload_pos() + file_name_len as u64 > self.embed_capacity {
+            return None;
+        }
+
+        // Now that we have the length of the file name we can extract it.
+        // Don't accept anything that isn't a safe relative path.
+        self.read_data_from_image(file_name_len);
+        let file_name = String::from_utf8(self.code_bytes.clone()).ok()?;
+        file_tree::safe_relative_path(&file_name)?;
+
+        // Now we need to get the length of the file.
+        let file_len:u32 = self.read_number(self.settings.file_len_chars as u32)?;
+
+        // Then the file metadata, if there is any.
+        let mut meta = None;
+        if has_meta {
+            let meta_len = file_tree::meta_len(&self.settings);
+            if self.payload_pos() + meta_len > self.embed_capacity {
+                return None;
+            }
+            self.read_data_from_image(meta_len as u32);
+            meta = Some(file_tree::parse_meta(&self.settings, &self.code_bytes)?);
+        }
+        if self.payload_pos() + file_len as u64 > self.embed_capacity {
+            return None;
+        }
+        Some((file_idx, file_name, file_len, meta))
+    }
+}
+
+// Method to read a number, stored as digits with leading zeros,
+// from the current position in the file data.
+impl Steganography {
+    pub fn read_number<T: std::str::FromStr>(&mut self, num_chars:u32) -> Option<T> {
+        if self.payload_pos() + num_chars as u64 > self.embed_capacity {
+            return None;
+        }
+        self.read_data_from_image(num_chars);
+        if self.bytes_read != num_chars || !self.code_bytes.iter().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        String::from_utf8(self.code_bytes.clone()).ok()?.parse().ok()
     }
 }
 
 // Method to extract a file from the image,
-// and save it to file.
+// and save it to file, or keep it in memory if extracting to memory.
+// The file is only saved if it is selected, and not just being listed,
+// but is always read so that its digest can be checked.
 impl Steganography {
-    pub fn extract_file(&mut self, file_size:u32, file_name:String) -> io::Result<()> {
+    pub fn extract_file(&mut self, file_size:u32, file_name:String, meta:Option<FileMeta>) -> io::Result<()> {
         info!("Extracting file of size: {}.", file_size);
+        let selected = self.select_file(&file_name);
 
         // Now the file data in the image needs to be written to a
         // file.
         // Will do this by reading chunks of data from the image at a time,
         // and appending chunks to the file.
         // When the file is complete save the file.
-
-        // Check if folder for storing embedded files exists.
-        // If it doesn't exist, create it.
-        fs::create_dir_all(&self.settings.secret_folder)?;
-
-        // We need to remove the path from the filename,
-        // as we are not interested in the original path.
-        let path = Path::new(&file_name);
-        let raw_filename = path.file_name().unwrap();
-
-        // Get file path for the file to be written.
-        // All files will be written to a specific folder.
-        let mut wrt_path = PathBuf::new();       
-        wrt_path.push(&self.settings.secret_folder);
-        wrt_path.push(raw_filename);
-        let mut wrt_path_string = wrt_path.to_string_lossy().into_owned();
-
-        // Check if we are going to overwrite an existing file.
-        // If so we will add a suffix to the end of the file name
-        // to make it unique.
-        let mut suffix = 1;
-        let original_filename = wrt_path_string.clone();
-        while Path::new(&wrt_path_string).exists() {
-            // Construct next suffix.
-            let extension = match original_filename.rfind('.') {
-                Some(idx) => &original_filename[idx..],
-                None => "",
-            };
-            // Construct base file path.
-            let base_filename = if let Some(idx) = original_filename.rfind('.') {
-                &original_filename[..idx]
-            } else {
-                &original_filename
-            };
-            // Construct complete file name.
-            wrt_path_string = format!("{}-{:03}{}", base_filename, suffix, extension);
-            // Increment suffix if this file name exists.
-            suffix += 1;
+        let mut file_digest = digest::Context::new(&digest::SHA256);
+        let mut file = None;
+        let mut memory_data = None;
+        self.progress.start_file(&file_name, Some(file_size as u64));
+        if selected && !self.manifest_only && self.in_memory() {
+            memory_data = Some(Vec::with_capacity(file_size as usize));
+        }
+        else if selected && !self.manifest_only {
+            // Open the file for writing.
+            let wrt_path_string = self.secret_file_path(&file_name)?;
+            info!("Opening file for writing: {}", wrt_path_string.clone());
+            file = Some((File::create(&wrt_path_string)?, wrt_path_string));
         }
-
-        // Open the file for writing.
-        info!("Opening file for writing: {}", wrt_path_string.clone());
-        let mut file = File::create(&wrt_path_string)?;
 
         // Keep track of bytes left to write.
         let mut bytes_remaining:u32 = file_size;
@@ -676,6 +1200,16 @@ impl Steganography {
 
         // Keep reading data from image until file read in full.
         while bytes_remaining > 0 {
+            // Stop if the extraction has been cancelled, removing
+            // the partly written file.
+            if self.progress.is_cancelled() {
+                if let Some((file, wrt_path_string)) = file {
+                    drop(file);
+                    file_tree::secure_delete(Path::new(&wrt_path_string))?;
+                }
+                return Err(io::Error::new(io::ErrorKind::Interrupted, "Extraction cancelled"));
+            }
+
             // Check if we have read a full or part chunk.
             if bytes_remaining < self.settings.byte_chunk {
                 bytes_to_read = bytes_remaining;
@@ -684,49 +1218,209 @@ impl Steganography {
             // Read a chunk of bytes from the image.
             self.read_data_from_image(bytes_to_read);
             if self.bytes_read != bytes_to_read {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
+                return Err(io::Error::other(
                     format!("Incorrect number of bytes read: {}", self.bytes_read),
                 ));
             } else {
                 // Write bytes read to the file.
-                file.write_all(&self.code_bytes)?;
+                if let Some((file, _)) = &mut file {
+                    file.write_all(&self.code_bytes)?;
+                }
+                if let Some(data) = &mut memory_data {
+                    data.extend_from_slice(&self.code_bytes);
+                }
+                file_digest.update(&self.code_bytes);
 
                 // Update the number of bytes remaining to read.
-                bytes_remaining = bytes_remaining - self.bytes_read as u32;
+                bytes_remaining -= self.bytes_read;
+                self.progress.advance(self.bytes_read as u64);
             }
         }
 
         // File writing completed, so save and close the file.
         // No need to manually close as the file will be closed when it goes out of scope.
-        debug!("Data written to file successfully.");
+        debug!("Data read from image successfully.");
+
+        // Keep the file digest in case the files are signed.
+        let file_digest = file_digest.finish().as_ref().to_vec();
+        if selected {
+            self.add_listed_file(&file_name, file_size as u64, &file_digest, meta);
+        }
+        if let Some(data) = memory_data {
+            self.memory_files.push(ContainerFile { file_name: file_name.clone(), data, meta });
+        }
+        self.file_digests.push(ManifestEntry {
+            file_name,
+            file_len: file_size as u64,
+            digest: file_digest,
+        });
+
+        // Restore the file metadata, and add to the list of files extracted.
+        if let Some((file, wrt_path_string)) = file {
+            drop(file);
+            if let Some(meta) = meta {
+                file_tree::restore_meta(&wrt_path_string, &meta)?;
+            }
+            self.add_extracted_file(wrt_path_string);
+        }
+        Ok(())
+    }
+}
+
+// Method to save file data that has already been read from the image,
+// such as a file unpacked from a container, or keep it in memory if
+// extracting to memory.
+// The file is only saved if it is selected, and not just being listed.
+impl Steganography {
+    pub fn save_extracted_file(&mut self, file:&ContainerFile) -> io::Result<()> {
+        if !self.select_file(&file.file_name) {
+            return Ok(());
+        }
+        self.progress.start_file(&file.file_name, Some(file.data.len() as u64));
+        self.progress.advance(file.data.len() as u64);
+        let file_digest = digest::digest(&digest::SHA256, &file.data);
+        self.add_listed_file(&file.file_name, file.data.len() as u64, file_digest.as_ref(), file.meta);
+        if self.manifest_only {
+            return Ok(());
+        }
+        if self.in_memory() {
+            self.memory_files.push(file.clone());
+            return Ok(());
+        }
+        let wrt_path_string = self.secret_file_path(&file.file_name)?;
+        info!("Writing extracted file: {}", wrt_path_string);
+        fs::write(&wrt_path_string, &file.data)?;
+        if let Some(meta) = &file.meta {
+            file_tree::restore_meta(&wrt_path_string, meta)?;
+        }
+        self.add_extracted_file(wrt_path_string);
+        Ok(())
+    }
+}
+
+// Method to get the path to write an extracted file to.
+// All files are written to the secrets folder, keeping any directories
+// in the file name, with a suffix added if a file of the same name
+// already exists.
+impl Steganography {
+    pub fn secret_file_path(&self, file_name:&str) -> io::Result<String> {
+        // The file name must be a relative path that stays
+        // inside the secrets folder.
+        let relative_path = file_tree::safe_relative_path(file_name)
+            .ok_or_else(|| io::Error::other(format!("Invalid file name: {}", file_name)))?;
+
+        // Get file path for the file to be written.
+        // All files will be written to a specific folder.
+        let mut wrt_path = PathBuf::new();
+        wrt_path.push(&self.settings.secret_folder);
+        wrt_path.push(relative_path);
+        let mut wrt_path_string = wrt_path.to_string_lossy().into_owned();
+
+        // Check if folder for storing embedded files exists,
+        // including any directories for this file.
+        // If it doesn't exist, create it.
+        if let Some(parent) = wrt_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Check if we are going to overwrite an existing file.
+        // If so we will add a suffix to the end of the file name
+        // to make it unique.
+        // Only a dot in the file name itself starts the extension.
+        let mut suffix = 1;
+        let original_filename = wrt_path_string.clone();
+        let name_start = original_filename.rfind(std::path::MAIN_SEPARATOR).map(|idx| idx + 1).unwrap_or(0);
+        let extension_start = original_filename[name_start..].rfind('.').map(|idx| name_start + idx);
+        while Path::new(&wrt_path_string).exists() {
+            // Construct next suffix.
+            let extension = match extension_start {
+                Some(idx) => &original_filename[idx..],
+                None => "",
+            };
+            // Construct base file path.
+            let base_filename = match extension_start {
+                Some(idx) => &original_filename[..idx],
+                None => &original_filename,
+            };
+            // Construct complete file name.
+            wrt_path_string = format!("{}-{:03}{}", base_filename, suffix, extension);
+            // Increment suffix if this file name exists.
+            suffix += 1;
+        }
+        Ok(wrt_path_string)
+    }
+}
 
+// Method to add a file that has been written to the
+// list of extracted files.
+impl Steganography {
+    pub fn add_extracted_file(&mut self, wrt_path_string:String) {
         // From file extention fix the file type.
         // Use in front end UI for displaying file thumbnails.
         let file_extension = Path::new(&wrt_path_string)
             .extension()
             .and_then(|ext| ext.to_str())
             .unwrap_or("");
-        let mime_type = get_mime_type(file_extension);
+        let mime_type = get_mime_type(file_extension).to_string();
         debug!("Data file of mime type: {:?}" , mime_type);
 
         // Push the filename onto the vector array so that we have a list of all
         // files written.
         let file_details = EmbeddedFile {
-            file_name : String::from(&wrt_path_string),
-            file_type: String::from(mime_type),
+            file_name : wrt_path_string,
+            file_type: mime_type,
             file_extracted : true,
             file_coded : false,
             file_analysed : true,
         };
         self.embedded_files.push(file_details);
-        Ok(())
+    }
+}
+
+// Method to check if files are extracted to memory rather than the secrets
+// folder, either because it was asked for or in memory only mode.
+impl Steganography {
+    pub fn in_memory(&self) -> bool {
+        self.extract_to_memory || self.settings.memory_only
+    }
+}
+
+// Method to wipe the loaded image, and any files extracted to memory, once
+// done with in memory only mode, so nothing is left behind in memory.
+impl Steganography {
+    pub fn wipe_memory(&mut self) {
+        wipe_image(self.image.take());
+        self.img_to_proc = false;
+        self.memory_files = Vec::new();
+        self.code_bytes.zeroize();
+    }
+}
+
+// Helper function to wipe the pixels of an image before it is dropped.
+pub fn wipe_image(image:Option<DynamicImage>) {
+    if let Some(mut image) = image {
+        if let Some((samples, _)) = bit_stream::image_samples_mut(&mut image) {
+            samples.zeroize();
+        }
+    }
+}
+
+// Method to securely remove the files extracted so far from the secrets
+// folder, such as when the extraction is cancelled part way through.
+impl Steganography {
+    pub fn remove_extracted_files(&mut self) {
+        for file in self.embedded_files.drain(..) {
+            info!("Removing extracted file: {}", file.file_name);
+            if let Err(err) = file_tree::secure_delete(Path::new(&file.file_name)) {
+                warn!("Failed to remove extracted file: {}", err);
+            }
+        }
     }
 }
 
 // Helper function to map file extensions to MIME types.
 // Used by front end when displaying thumbnails of extracted images.
-fn get_mime_type(extension: &str) -> &str {
+pub fn get_mime_type(extension: &str) -> &str {
     match extension {
         "txt" => "text/plain",
         "html" => "text/html",
@@ -743,38 +1437,87 @@ fn get_mime_type(extension: &str) -> &str {
 }
 
 // Method to embed one or more files into a loaded image.
+// Directories are embedded with all the files in them.
 impl Steganography {
     pub fn embed_files(&mut self, pw:bool, pw_str:&str, files_to_embed:&[&str]) -> io::Result<()> {
+        // Expand any directories into the files in them.
+        let sources = if self.img_to_proc { file_tree::expand_files(files_to_embed)? } else { Vec::new() };
+        self.embed_sources(pw, pw_str, &sources)
+    }
+}
+
+// Method to embed files, read from disk or held in memory, into a loaded image.
+// If the embedding is cancelled, the loaded image is left as it was.
+impl Steganography {
+    pub fn embed_sources(&mut self, pw:bool, pw_str:&str, sources:&[SourceFile]) -> io::Result<()> {
         // Initialise timer for function.
         let embed_start = Instant::now();
 
         // Don't need to initialise image parameters as we require
         // a loaded image to embed files into.
-        if self.img_to_proc == true {
+        if self.img_to_proc {
+            // Make sure the header copies are in place, in case the
+            // image was last checked for a headerless volume.
+            self.set_layout(false);
+
             // We have an image to embed into so all good.
             // It doesn't matter if the image is already pic coded as we
             // will just overwrite the previous embedding.
             // We should also alaready know the embedding width, height,
             // and embedding capacity of the image.
 
+            // The number of files, and the length of each file name,
+            // must fit in the digits used for them.
+            let max_files = 10_usize.pow(self.settings.num_files_chars as u32) - 1;
+            if sources.len() > max_files {
+                return Err(io::Error::other(format!("No more than {} files can be embedded", max_files)));
+            }
+            let max_name_len = 10_usize.pow(self.settings.len_filename_chars as u32) - 1;
+            if let Some(source) = sources.iter().find(|source| source.file_name.len() > max_name_len) {
+                return Err(io::Error::other(format!("File name too long: {}", source.file_name)));
+            }
+
             // First check to see if there is space for the file(s) requested.
             let mut bytes_to_embed = 0;
-            for file in files_to_embed {
+            for source in sources {
                 // Need to get sum of file lengths to embed.
-                let metadata = fs::metadata(file)?;
-                let file_size = metadata.len();
-                bytes_to_embed = bytes_to_embed + file_size;
-                info!("File: {} Size: {} bytes", file, file_size);
+                let file_size = source.size()?;
+                bytes_to_embed += file_size;
+                info!("File: {} Size: {} bytes", source.file_name, file_size);
             }
+            // Progress is reported against the file data alone.
+            let file_bytes = bytes_to_embed;
+
             // Need to compare bytes to embed with image capacity.
-            // Ignoring size of file names as not likely to be significant.
+            // Include the overhead for each file section.
+            bytes_to_embed += sources.len() as u64 * self.overhead_per_file as u64;
+            if self.signing_key.is_some() {
+                bytes_to_embed += SIGNATURE_SECTION_LEN;
+            }
             if bytes_to_embed > self.embed_capacity {
                 // Exceeded embedding capacity so can't imbed.
-                warn!("Exceeded image emdedding: {}", self.embed_capacity)
+                warn!("Exceeded image emdedding: {}", self.embed_capacity);
+                self.embed_duration = embed_start.elapsed();
+                return Err(io::Error::new(
+                    io::ErrorKind::StorageFull,
+                    format!("Files need {} bytes, but the image can only hold {} bytes", bytes_to_embed, self.embed_capacity),
+                ));
             }
             else {
                 // Within the embedding capacity of the image, so proceed.
                 info!("Total data to embed: {} bytes", bytes_to_embed);
+                self.progress.set_total(file_bytes);
+
+                // Sign the files first if required, so nothing is
+                // written if the signing key is invalid.
+                let signature_section = match &self.signing_key {
+                    Some(pkcs8) => Some(self.signature_section(pkcs8, sources)?),
+                    None => None,
+                };
+
+                // Embed into a copy of the loaded image, keeping the
+                // original to put back if the embedding is cancelled.
+                let carrier = self.image.clone();
 
                 // First step is to write the preamble to the file.
                 self.embed_preamble();
@@ -783,21 +1526,43 @@ impl Steganography {
                 self.embed_password(pw, pw_str);
 
                 // Next need to embed the number of files we are embedding.
-                let num_files:u16 = files_to_embed.len() as u16;
+                let num_files:u16 = sources.len() as u16;
                 self.embed_num_of_files(num_files);
 
+                // Header is complete, so copy it to the header copy locations.
+                self.embed_header_copies();
+
+                // Signature section goes before the file sections.
+                if let Some(signature_section) = signature_section {
+                    self.write_chunks(&signature_section);
+                }
+
                 // Next need to embed files themselves, one at a time.
-                for file in files_to_embed {
+                for (file_idx, source) in sources.iter().enumerate() {
                     // Need to embed the file.
                     // This also means embeddng the name of the file,
                     // and the length of the file.
-                    if let Err(err) = self.embed_file(file) {
-                        eprintln!("Error: {}", err); 
-                    }
-                    else {
-                        info!("Successfully embedded file: {}", file);
+                    match self.embed_file(source, file_idx as u16 + 1) {
+                        Err(err) if err.kind() == io::ErrorKind::Interrupted => {
+                            info!("Embedding cancelled.");
+                            self.image = carrier;
+                            self.embed_duration = embed_start.elapsed();
+                            return Err(err);
+                        }
+                        Err(err) => eprintln!("Error: {}", err),
+                        Ok(()) => info!("Successfully embedded file: {}", source.file_name),
                     }
                 }
+
+                // Hide where the files end if required.
+                if self.noise_fill {
+                    self.embed_noise();
+                }
+
+                // The copy kept in case of cancelling is no longer needed.
+                if self.in_memory() {
+                    wipe_image(carrier);
+                }
             }
 
             // Determine delta time for function.
@@ -812,7 +1577,7 @@ impl Steganography {
             // Determine delta time for function.
             self.embed_duration = embed_start.elapsed();
             info!("Time to NOT embed any file: {:?}", self.embed_duration);
- 
+
             Ok(())
         }
     }
@@ -848,10 +1613,13 @@ impl Steganography {
 
         // Send pasword as applicable as bytes vector for embedding.
         // All writes to the image is done in chunks.
-        if _pw == false {
+        if !_pw {
+            // No password, so the hash is left as zeros.
             let have_pw_str = String::from("N");
             let have_pw_bytes = have_pw_str.as_bytes();
-            for chunk in have_pw_bytes.chunks(self.settings.byte_chunk.try_into().unwrap()) {
+            let no_password_bytes = vec![0u8; self.settings.pw_chars as usize];
+            let pw_bytes:Vec<u8> = [have_pw_bytes, &no_password_bytes].concat();
+            for chunk in pw_bytes.chunks(self.settings.byte_chunk.try_into().unwrap()) {
                 let bytes_written:u32 = self.write_data_to_image(chunk);
                 if bytes_written != chunk.len() as u32{
                     error!("Incorrect number of bytes written: {}", bytes_written)
@@ -901,32 +1669,77 @@ impl Steganography {
     }
 }
 
+// Method to copy the header to each of the header copy locations.
+// Called once the header has been written to the start of the image,
+// and leaves the position at the start of the file sections.
+impl Steganography {
+    pub fn embed_header_copies(&mut self) {
+        info!("Embedding {} header copies.", self.header_copy_locs.len());
+
+        // Read back the header that has just been written.
+        let header_len = self.header_len();
+        self.skip_reserved = false;
+        self.seek_stream(0);
+        self.read_data_from_image(header_len);
+        let header_bytes = self.code_bytes.clone();
+
+        // Write the header to each copy location.
+        for loc in self.header_copy_locs.clone() {
+            self.seek_stream(loc);
+            let bytes_written:u32 = self.write_data_to_image(&header_bytes);
+            if bytes_written != header_len {
+                error!("Incorrect number of bytes written: {}", bytes_written)
+            }
+        }
+
+        // File sections follow the header, skipping the copies.
+        self.seek_payload(0);
+    }
+}
+
+// Method to rewrite the header, and each of its copies, in an already
+// pic coded image, such as when the password or number of files changes.
+impl Steganography {
+    pub fn write_header(&mut self, header:&[u8]) {
+        info!("Rewriting header and {} header copies.", self.header_copy_locs.len());
+        self.skip_reserved = false;
+        for loc in [0].into_iter().chain(self.header_copy_locs.clone()) {
+            self.seek_stream(loc);
+            self.write_chunks(header);
+        }
+        self.header_bytes = header.to_vec();
+    }
+}
+
 // Method to embed the contents of a file into the image.
 impl Steganography {
-    pub fn embed_file(&mut self, file_path:&str) -> io::Result<()> {
-        info!("Embedding file: {}", file_path);
+    pub fn embed_file(&mut self, source:&SourceFile, file_idx:u16) -> io::Result<()> {
+        info!("Embedding file: {}", source.file_name);
 
         // Need to get the filename to give the file,
         // and the length of this filename, as both are embedded.
         // File name, and filename length.
-        let _file_path = Path::new(file_path);
-        // Extract file name.
-        let _file_name = _file_path.file_name().unwrap();
-        let _file_name_bytes = _file_name.as_encoded_bytes();
+        // The file name is the path relative to any directory embedded.
+        let _file_name_bytes = source.file_name.as_bytes();
         // Determine filename length.
         // And format to 3 digits, with leading 0s.
-        let _file_name_len = _file_name.len() as u8;
+        let _file_name_len = _file_name_bytes.len();
         let _file_name_len_str:String = format!("{:0>width$}", _file_name_len, width=self.settings.len_filename_chars as usize);
         let _file_name_len_bytes = _file_name_len_str.as_bytes();
         // Determine file length in bytes.
         // Format to 10 digits, with leading 0s.
-        let _metadata = fs::metadata(file_path)?;
-        let _file_size = _metadata.len();
+        let _file_size = source.size()?;
         let _file_size_str:String = format!("{:0>width$}", _file_size, width=self.settings.file_len_chars as usize);
         let _file_size_bytes = _file_size_str.as_bytes();
+        // File modification time and permissions.
+        let _file_meta_str = file_tree::format_meta(&self.settings, &source.meta()?);
+        let _file_meta_bytes = _file_meta_str.as_bytes();
+        // File section starts with the sync marker and file index.
+        let _file_idx_str:String = format!("{:0>width$}", file_idx, width=self.settings.num_files_chars as usize);
+        let _sync_bytes = [META_SYNC_CODE.as_bytes(), _file_idx_str.as_bytes()].concat();
 
         // Concatenate file details for embedding.
-        let file_detail_bytes:Vec<u8> = [_file_name_len_bytes, _file_name_bytes, _file_size_bytes].concat();
+        let file_detail_bytes:Vec<u8> = [&_sync_bytes, _file_name_len_bytes, _file_name_bytes, _file_size_bytes, _file_meta_bytes].concat();
         // Embed into image.
         for chunk in file_detail_bytes.chunks(self.settings.byte_chunk.try_into().unwrap()) {
             let bytes_written:u32 = self.write_data_to_image(chunk);
@@ -940,13 +1753,19 @@ impl Steganography {
         // and writing the data to the image, until the file is done.
 
         // Open the file for reading.
-        let mut file = File::open(file_path)?;
+        let mut file = source.open()?;
+        self.progress.start_file(&source.file_name, None);
 
         // Define a buffer to use for the chunks of read data.
-        let mut buffer = vec![0u8; self.settings.byte_chunk as usize];
+        let mut buffer = Zeroizing::new(vec![0u8; self.settings.byte_chunk as usize]);
 
         // Loop until there are no bytes in the file to write.
         loop {
+            // Stop if the embedding has been cancelled.
+            if self.progress.is_cancelled() {
+                return Err(io::Error::new(io::ErrorKind::Interrupted, "Embedding cancelled"));
+            }
+
             // Read a chunk of data from the file.
             let bytes_read = file.read(&mut buffer)?;
 
@@ -960,11 +1779,11 @@ impl Steganography {
 
             // Check that the correct number of bytes were written.
             if bytes_written != bytes_read as u32 {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
+                return Err(io::Error::other(
                     format!("Incorrect number of bytes written: {}", bytes_written),
                 ));
             }
+            self.progress.advance(bytes_written as u64);
         }
         // Return ok result.
         info!("File data written to image successfully.");
@@ -972,6 +1791,43 @@ impl Steganography {
     }
 }
 
+// Method to fill the rest of the bit planes used for embedding
+// with random data.
+// Without this the bits after the embedded files keep the original
+// image bits, so the end of the embedded files can be seen when
+// looking at the bit plane.
+// Only the bit planes already written to are filled, so that no
+// more of the image is changed than needed.
+impl Steganography {
+    pub fn embed_noise(&mut self) {
+        // Find the end of the bit plane we are currently writing to.
+        // If at the very start of a bit plane then nothing is written to it yet.
+        let plane_bits = self.pic_width as u64 * self.pic_height as u64 * 3;
+        let stream_bits = self.stream_pos * 8;
+        let end_bits = stream_bits.div_ceil(plane_bits) * plane_bits;
+        let end_pos = (end_bits / 8).min(self.stream_len());
+        if end_pos <= self.stream_pos {
+            return;
+        }
+
+        // Number of bytes of file data left up to the end position.
+        // Header copies in the way are skipped over when writing.
+        let current_pos = self.payload_pos();
+        self.seek_stream(end_pos);
+        let fill_len = self.payload_pos().saturating_sub(current_pos);
+        self.seek_payload(current_pos);
+        info!("Filling {} bytes with random data.", fill_len);
+
+        // Write the random data a chunk at a time.
+        let mut bytes_remaining = fill_len;
+        while bytes_remaining > 0 {
+            let bytes_to_write = bytes_remaining.min(self.settings.byte_chunk as u64);
+            self.write_chunks(&container::random_bytes(bytes_to_write as usize));
+            bytes_remaining -= bytes_to_write;
+        }
+    }
+}
+
 // Method to save image with name.
 // Will overwrite the existing image if no file specified.
 impl Steganography {
@@ -979,13 +1835,13 @@ impl Steganography {
 
         // Check if file path string provided.
         // If not then overwrite the loaded image file instead.
-        if save_file.len() == 0 {
+        if save_file.is_empty() {
             save_file = self.image_file.clone();
             info!("Overwritting original image.")
         }
         // Create path to image file .
         let mut img_path = PathBuf::new();
-        img_path.push(&save_file.clone());
+        img_path.push(&save_file);
         let img_path_string = img_path.to_string_lossy().into_owned();
         info!("Writing to image: {}", img_path_string);
 
@@ -997,3 +1853,14 @@ impl Steganography {
         }
     }
 }
+
+// Method to get the image as PNG file bytes, such as to send it
+// straight back without saving it to a file.
+impl Steganography {
+    pub fn image_png_bytes(&self) -> io::Result<Vec<u8>> {
+        let image = self.image.as_ref().ok_or_else(|| io::Error::other("No image loaded"))?;
+        let mut png_bytes = Vec::new();
+        image.write_to(&mut png_bytes, image::ImageOutputFormat::Png).map_err(io::Error::other)?;
+        Ok(png_bytes)
+    }
+}
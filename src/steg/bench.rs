@@ -0,0 +1,164 @@
+// Bit stream benchmark.
+//
+// Times embedding and extracting data with the bit stream methods, on one
+// thread and split across threads, against the original pixel at a time
+// methods, which went through get_pixel and put_pixel for every bit of the
+// data. The original methods are kept here only for comparison. All are
+// run on the same random image and data, and the results checked to be
+// identical.
+
+use crate::steg::container;
+use crate::steg::Steganography;
+
+use image::{DynamicImage, GenericImage, GenericImageView, ImageBuffer, Pixel};
+use std::io;
+use std::time::{Duration, Instant};
+
+// Helper function to run the benchmark on a random rgb image of the given
+// size, embedding and extracting the given number of bytes of random data.
+pub fn run_benchmark(width:u32, height:u32, data_len:usize) -> io::Result<()> {
+    let samples = container::random_bytes(width as usize * height as usize * 3);
+    let buffer = ImageBuffer::from_raw(width, height, samples)
+        .ok_or_else(|| io::Error::other("Invalid image size"))?;
+    let carrier = DynamicImage::ImageRgb8(buffer);
+
+    // Data can't be more than the image can hold.
+    let stream_len = width as usize * height as usize * 3;
+    let data = container::random_bytes(data_len.min(stream_len));
+    println!("Image: {} x {} pixels, data: {} bytes", width, height, data.len());
+
+    // Original pixel at a time methods.
+    let mut legacy_image = Some(carrier.clone());
+    let start = Instant::now();
+    legacy_write(&mut legacy_image, width, height, &data);
+    let legacy_write_time = start.elapsed();
+    let start = Instant::now();
+    let legacy_data = legacy_read(&legacy_image, width, height, data.len());
+    let legacy_read_time = start.elapsed();
+
+    // Bit stream methods, on one thread and then split across threads.
+    // Nothing is split if only one thread is available.
+    let parallel_chunk = Steganography::init().settings.parallel_chunk.max(1);
+    let single = time_stream(&carrier, &data, 0);
+    let threaded = time_stream(&carrier, &data, parallel_chunk);
+
+    // All methods must give the same image, and read back the data.
+    for (image, stream_data, _, _) in [&single, &threaded] {
+        let same_image = match (&legacy_image, image) {
+            (Some(legacy), Some(stream)) => legacy.as_bytes() == stream.as_bytes(),
+            _ => false,
+        };
+        if !same_image || legacy_data != data || *stream_data != data {
+            return Err(io::Error::other("Bit stream results don't match the original methods"));
+        }
+    }
+
+    println!("{:<8} {:>12} {:>12} {:>12} {:>9} {:>9}", "", "pixel", "bit stream", "threaded", "speedup", "threaded");
+    print_timing("Embed", legacy_write_time, single.2, threaded.2);
+    print_timing("Extract", legacy_read_time, single.3, threaded.3);
+    Ok(())
+}
+
+// Helper function to time writing, then reading back, the data with the
+// bit stream methods, through the usual chunked writes and reads.
+// Returns the image written to, the data read back, and the timings.
+fn time_stream(carrier:&DynamicImage, data:&[u8], parallel_chunk:u32) -> (Option<DynamicImage>, Vec<u8>, Duration, Duration) {
+    let mut steg = Steganography::init();
+    steg.settings.parallel_chunk = parallel_chunk;
+    (steg.pic_width, steg.pic_height) = carrier.dimensions();
+    steg.image = Some(carrier.clone());
+    steg.pic_col_planes = 3;
+    steg.header_copy_locs = Vec::new();
+    steg.skip_reserved = false;
+
+    steg.seek_stream(0);
+    let start = Instant::now();
+    steg.write_chunks(data);
+    let write_time = start.elapsed();
+
+    steg.seek_stream(0);
+    let start = Instant::now();
+    let stream_data = steg.read_chunks(data.len() as u64);
+    let read_time = start.elapsed();
+    (steg.image.take(), stream_data, write_time, read_time)
+}
+
+// Helper function to print the timings for one operation.
+// Speedups are against the original methods.
+fn print_timing(name:&str, legacy:Duration, single:Duration, threaded:Duration) {
+    let speedup = |time:Duration| legacy.as_secs_f64() / time.as_secs_f64().max(f64::EPSILON);
+    println!(
+        "{:<8} {:>12.3?} {:>12.3?} {:>12.3?} {:>8.1}x {:>8.1}x",
+        name, legacy, single, threaded, speedup(single), speedup(threaded),
+    );
+}
+
+// Helper function with the original method of writing bytes to the image,
+// from the start of the stream, a pixel at a time.
+fn legacy_write(image:&mut Option<DynamicImage>, width:u32, height:u32, bytes:&[u8]) {
+    let (mut row_cnt, mut col_cnt, mut col_plane, mut bit_write) = (0u32, 0u32, 0usize, 0u8);
+    let mut col_mask:u8 = 1 << bit_write;
+    for byte_data in bytes {
+        let mut mask:u8 = 128;
+        for _idx in 1..9 {
+            let mapped_bit:u8 = (if (byte_data & mask) == 0 { 0 } else { 1 }) << bit_write;
+            if let Some(mut img) = image.take() {
+                let col_part = img.get_pixel(col_cnt, row_cnt).to_rgb();
+                let mut rgb = [col_part[0], col_part[1], col_part[2]];
+                rgb[col_plane] = (rgb[col_plane] & !col_mask) + mapped_bit;
+                img.put_pixel(col_cnt, row_cnt, image::Rgba([rgb[0], rgb[1], rgb[2], 255]));
+                *image = Some(img);
+            }
+            mask >>= 1;
+            col_cnt += 1;
+            if col_cnt == width {
+                col_cnt = 0;
+                row_cnt += 1;
+                if row_cnt == height {
+                    row_cnt = 0;
+                    col_plane += 1;
+                    if col_plane == 3 {
+                        col_plane = 0;
+                        bit_write += 1;
+                        col_mask = col_mask.wrapping_shl(1);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Helper function with the original method of reading bytes from the image,
+// from the start of the stream, a pixel at a time.
+fn legacy_read(image:&Option<DynamicImage>, width:u32, height:u32, bytes_to_read:usize) -> Vec<u8> {
+    let (mut row_cnt, mut col_cnt, mut col_plane, mut bits_read) = (0u32, 0u32, 0usize, 0u8);
+    let mut mask:u8 = 1 << bits_read;
+    let mut bytes = Vec::with_capacity(bytes_to_read);
+    while bytes.len() < bytes_to_read {
+        let mut code_data:u8 = 0;
+        for _idx in 1..9 {
+            let mut col_part:u8 = 0;
+            if let Some(image) = image {
+                col_part = image.get_pixel(col_cnt, row_cnt).to_rgb()[col_plane];
+            }
+            code_data <<= 1;
+            code_data |= (col_part & mask) >> bits_read;
+            col_cnt += 1;
+            if col_cnt == width {
+                col_cnt = 0;
+                row_cnt += 1;
+                if row_cnt == height {
+                    row_cnt = 0;
+                    col_plane += 1;
+                    if col_plane == 3 {
+                        col_plane = 0;
+                        bits_read += 1;
+                        mask = mask.wrapping_shl(1);
+                    }
+                }
+            }
+        }
+        bytes.push(code_data);
+    }
+    bytes
+}
@@ -0,0 +1,362 @@
+// Bit stream methods.
+//
+// The embedding stream is read and written directly on the raw colour
+// samples of the image, rather than a pixel at a time. Bits of the stream
+// run along the pixels for one colour plane and bit, so consecutive bits
+// are a pixel's worth of samples apart in the sample slice. Only when the
+// end of the image is reached does the stream move on to the next colour
+// plane, or the next bit.
+//
+// Bits are packed into, and unpacked from, 64 bit words, so eight bytes
+// of the stream are handled at a time.
+//
+// Each bit of the stream maps to a fixed sample and bit, so large reads
+// and writes are split across threads. Reads are split into pieces of
+// the data, each read from its own position in the stream. Writes are
+// split into bands of pixels, each thread writing the bits that land
+// in its band, so no two threads ever change the same sample.
+
+use crate::steg::Steganography;
+
+use image::DynamicImage;
+use rayon::prelude::*;
+
+// Struct to hold the position in the image samples that the next
+// bit of the stream is read from, or written to.
+#[derive(Clone, Copy, Debug)]
+pub struct BitCursor {
+    pub pixel: usize,
+    pub plane: usize,
+    pub bit: u8,
+    pub pixels: usize,
+    pub channels: usize,
+}
+
+impl BitCursor {
+    // Move on to the next colour plane, or the next bit of the
+    // first colour plane, once the end of the image is reached.
+    fn wrap(&mut self) {
+        self.pixel = 0;
+        self.plane += 1;
+        if self.plane == 3 {
+            self.plane = 0;
+            self.bit += 1;
+        }
+    }
+
+    // Index in the sample slice for the current pixel and colour plane.
+    fn sample_index(&self) -> usize {
+        self.pixel * self.channels + self.plane
+    }
+
+    // Position of the cursor in bits from the start of the stream.
+    fn stream_bit(&self) -> u64 {
+        (self.bit as u64 * 3 + self.plane as u64) * self.pixels as u64 + self.pixel as u64
+    }
+
+    // Number of whole bytes left in the stream from the cursor.
+    fn bytes_left(&self) -> u64 {
+        (self.pixels as u64 * 24).saturating_sub(self.stream_bit()) / 8
+    }
+
+    // Cursor for a number of bits further on in the stream.
+    fn advanced(&self, bits:u64) -> BitCursor {
+        let pixels = self.pixels.max(1) as u64;
+        let stream_bit = self.stream_bit() + bits;
+        let level = stream_bit / pixels;
+        BitCursor {
+            pixel: (stream_bit % pixels) as usize,
+            plane: (level % 3) as usize,
+            bit: (level / 3).min(8) as u8,
+            ..*self
+        }
+    }
+}
+
+// Method to get a cursor for the current read/write position.
+impl Steganography {
+    pub fn bit_cursor(&self, channels:usize) -> BitCursor {
+        BitCursor {
+            pixel: self.row as usize * self.pic_width as usize + self.col as usize,
+            plane: self.plane,
+            bit: self.bit,
+            pixels: self.pic_width as usize * self.pic_height as usize,
+            channels,
+        }
+    }
+}
+
+// Method to save the position of a cursor as the current read/write position.
+impl Steganography {
+    pub fn save_bit_cursor(&mut self, cursor:&BitCursor, stream_pos:u64) {
+        let width = self.pic_width.max(1) as usize;
+        self.row = (cursor.pixel / width) as u32;
+        self.col = (cursor.pixel % width) as u32;
+        self.plane = cursor.plane;
+        self.bit = cursor.bit;
+        self.stream_pos = stream_pos;
+    }
+}
+
+// Method to get the number of bytes from a stream position to the
+// start of the next reserved header copy region.
+// There is no limit when reserved regions aren't being skipped.
+impl Steganography {
+    pub fn bytes_to_reserved(&self, stream_pos:u64) -> u64 {
+        if !self.skip_reserved {
+            return u64::MAX;
+        }
+        self.header_copy_locs
+            .iter()
+            .filter(|loc| **loc > stream_pos)
+            .map(|loc| *loc - stream_pos)
+            .min()
+            .unwrap_or(u64::MAX)
+    }
+}
+
+// Helper function to get the colour samples of an image, and the
+// number of samples per pixel. Only 8 bit rgb and rgba images
+// are supported.
+pub fn image_samples(image:&DynamicImage) -> Option<(&[u8], usize)> {
+    let flat = match image {
+        DynamicImage::ImageRgb8(buffer) => buffer.as_flat_samples(),
+        DynamicImage::ImageRgba8(buffer) => buffer.as_flat_samples(),
+        _ => return None,
+    };
+    Some((flat.samples, flat.layout.channels as usize))
+}
+
+// Helper function to get the colour samples of an image for writing,
+// and the number of samples per pixel.
+pub fn image_samples_mut(image:&mut DynamicImage) -> Option<(&mut [u8], usize)> {
+    let flat = match image {
+        DynamicImage::ImageRgb8(buffer) => buffer.as_flat_samples_mut(),
+        DynamicImage::ImageRgba8(buffer) => buffer.as_flat_samples_mut(),
+        _ => return None,
+    };
+    let channels = flat.layout.channels as usize;
+    Some((flat.samples, channels))
+}
+
+// Helper function to read bytes from the image samples, starting at the cursor.
+// Bits are read MSB first. Once past the last bit of the colour bytes
+// there is nothing more to read, so any remaining bits are zero.
+pub fn read_bits(samples:&[u8], cursor:&mut BitCursor, bytes:&mut [u8]) {
+    for word_bytes in bytes.chunks_mut(8) {
+        let num_bits = word_bytes.len() * 8;
+        let mut word:u64 = 0;
+        let mut bits_done = 0;
+        while bits_done < num_bits {
+            if cursor.bit > 7 {
+                word = word.checked_shl((num_bits - bits_done) as u32).unwrap_or(0);
+                break;
+            }
+
+            // Read as far as the word, or the end of the image, allows.
+            let run = (num_bits - bits_done).min(cursor.pixels - cursor.pixel);
+            let shift = cursor.bit;
+            for sample in samples[cursor.sample_index()..].iter().step_by(cursor.channels).take(run) {
+                word = (word << 1) | ((sample >> shift) & 1) as u64;
+            }
+            bits_done += run;
+            cursor.pixel += run;
+            if cursor.pixel == cursor.pixels {
+                cursor.wrap();
+            }
+        }
+        word_bytes.copy_from_slice(&word.to_be_bytes()[8 - word_bytes.len()..]);
+    }
+}
+
+// Helper function to write bytes to the image samples, starting at the cursor.
+// Bits are written MSB first. Once past the last bit of the colour bytes
+// there is nowhere left to write, so any remaining bits are dropped.
+// Returns the number of whole bytes written.
+pub fn write_bits(samples:&mut [u8], cursor:&mut BitCursor, bytes:&[u8]) -> usize {
+    let written = bytes.len().min(cursor.bytes_left() as usize);
+    for word_bytes in bytes.chunks(8) {
+        let mut padded = [0u8; 8];
+        padded[8 - word_bytes.len()..].copy_from_slice(word_bytes);
+        let word = u64::from_be_bytes(padded);
+        let mut bits_left = word_bytes.len() * 8;
+        while bits_left > 0 && cursor.bit < 8 {
+            // Write as far as the word, or the end of the image, allows.
+            let run = bits_left.min(cursor.pixels - cursor.pixel);
+            let shift = cursor.bit;
+            let clear_mask = !(1u8 << shift);
+            let start = cursor.sample_index();
+            for sample in samples[start..].iter_mut().step_by(cursor.channels).take(run) {
+                bits_left -= 1;
+                let data_bit = ((word >> bits_left) & 1) as u8;
+                *sample = (*sample & clear_mask) | (data_bit << shift);
+            }
+            cursor.pixel += run;
+            if cursor.pixel == cursor.pixels {
+                cursor.wrap();
+            }
+        }
+    }
+    written
+}
+
+// Helper function to read bytes from the image samples, starting at the
+// cursor, with the data split into pieces read on separate threads.
+pub fn read_bits_parallel(samples:&[u8], cursor:&mut BitCursor, bytes:&mut [u8], piece_len:usize) {
+    let start = *cursor;
+    bytes.par_chunks_mut(piece_len).enumerate().for_each(|(idx, piece)| {
+        let mut piece_cursor = start.advanced((idx * piece_len) as u64 * 8);
+        read_bits(samples, &mut piece_cursor, piece);
+    });
+    *cursor = start.advanced(bytes.len() as u64 * 8);
+}
+
+// Helper function to write bytes to the image samples, starting at the
+// cursor, with the image split into bands of pixels written on separate
+// threads. Each band gets the bits of every colour plane and bit that
+// fall in its pixels. Returns the number of whole bytes written.
+pub fn write_bits_parallel(samples:&mut [u8], cursor:&mut BitCursor, bytes:&[u8], piece_len:usize) -> usize {
+    if bytes.is_empty() {
+        return 0;
+    }
+    let start = *cursor;
+    let written = bytes.len().min(start.bytes_left() as usize);
+    let stream_start = start.stream_bit();
+    let stream_end = stream_start + bytes.len() as u64 * 8;
+    let pixels = start.pixels as u64;
+    let channels = start.channels;
+    let band_pixels = piece_len * 8;
+
+    samples.par_chunks_mut(band_pixels * channels).enumerate().for_each(|(band, band_samples)| {
+        let band_start = (band * band_pixels) as u64;
+        let band_len = (band_samples.len() / channels) as u64;
+
+        // Go through each colour plane and bit that the data reaches.
+        for level in stream_start / pixels..=(stream_end - 1) / pixels {
+            // Past the last bit of the colour bytes there is nowhere to write.
+            if level >= 24 {
+                break;
+            }
+            let level_start = level * pixels + band_start;
+            let lo = stream_start.max(level_start);
+            let hi = stream_end.min(level_start + band_len);
+            if lo >= hi {
+                continue;
+            }
+
+            let plane = (level % 3) as usize;
+            let shift = (level / 3) as u8;
+            let clear_mask = !(1u8 << shift);
+            let first = (lo - level_start) as usize * channels + plane;
+            let samples_iter = band_samples[first..].iter_mut().step_by(channels);
+            for (data_idx, sample) in (lo - stream_start..hi - stream_start).zip(samples_iter) {
+                let data_bit = (bytes[(data_idx / 8) as usize] >> (7 - data_idx % 8)) & 1;
+                *sample = (*sample & clear_mask) | (data_bit << shift);
+            }
+        }
+    });
+    *cursor = start.advanced(bytes.len() as u64 * 8);
+    written
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Image with an odd number of pixels, so that colour planes
+    // and bits wrap part way through bytes of the stream.
+    const PIXELS:usize = 37;
+
+    // Helper function to get repeatable sample or data bytes.
+    fn test_bytes(len:usize, seed:u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    // Helper function to get a cursor a number of bits into the stream.
+    fn cursor_at(stream_bit:u64, channels:usize) -> BitCursor {
+        BitCursor { pixel: 0, plane: 0, bit: 0, pixels: PIXELS, channels }.advanced(stream_bit)
+    }
+
+    // Offsets to start at: the start, odd offsets, just before a colour
+    // plane wraps, and just before the stream moves on to the next bit.
+    const OFFSETS:[u64; 6] = [0, 3, 13, PIXELS as u64 - 5, PIXELS as u64 * 3 - 11, PIXELS as u64 * 8 + 7];
+
+    #[test]
+    fn write_then_read_round_trips() {
+        for channels in [3, 4] {
+            for offset in OFFSETS {
+                for len in [1, 7, 8, 9, 30] {
+                    let mut samples = test_bytes(PIXELS * channels, 1);
+                    let data = test_bytes(len, offset as u32 + 2);
+
+                    let mut cursor = cursor_at(offset, channels);
+                    assert_eq!(write_bits(&mut samples, &mut cursor, &data), len);
+                    assert_eq!(cursor.stream_bit(), offset + len as u64 * 8);
+
+                    let mut cursor = cursor_at(offset, channels);
+                    let mut read = vec![0u8; len];
+                    read_bits(&samples, &mut cursor, &mut read);
+                    assert_eq!(read, data, "channels {}, offset {}, len {}", channels, offset, len);
+                    assert_eq!(cursor.stream_bit(), offset + len as u64 * 8);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn parallel_matches_single() {
+        for channels in [3, 4] {
+            for offset in OFFSETS {
+                let data = test_bytes(40, offset as u32 + 3);
+
+                let mut single = test_bytes(PIXELS * channels, 4);
+                let mut cursor = cursor_at(offset, channels);
+                write_bits(&mut single, &mut cursor, &data);
+
+                let mut parallel = test_bytes(PIXELS * channels, 4);
+                let mut parallel_cursor = cursor_at(offset, channels);
+                assert_eq!(write_bits_parallel(&mut parallel, &mut parallel_cursor, &data, 3), data.len());
+                assert_eq!(parallel, single, "channels {}, offset {}", channels, offset);
+                assert_eq!(parallel_cursor.stream_bit(), cursor.stream_bit());
+
+                let mut cursor = cursor_at(offset, channels);
+                let mut read = vec![0u8; data.len()];
+                read_bits_parallel(&parallel, &mut cursor, &mut read, 3);
+                assert_eq!(read, data, "channels {}, offset {}", channels, offset);
+            }
+        }
+    }
+
+    #[test]
+    fn alpha_is_left_untouched() {
+        let mut samples = test_bytes(PIXELS * 4, 5);
+        let alpha: Vec<u8> = samples.iter().skip(3).step_by(4).copied().collect();
+        let data = test_bytes(PIXELS * 3, 6);
+        write_bits(&mut samples, &mut cursor_at(0, 4), &data);
+        write_bits_parallel(&mut samples, &mut cursor_at(5, 4), &data, 4);
+        assert_eq!(samples.iter().skip(3).step_by(4).copied().collect::<Vec<u8>>(), alpha);
+    }
+
+    #[test]
+    fn writing_past_the_end_reports_bytes_written() {
+        // Room for 5 whole bytes, and 3 bits of the next.
+        let offset = PIXELS as u64 * 24 - 43;
+        let data = test_bytes(8, 7);
+
+        let mut single = test_bytes(PIXELS * 3, 8);
+        assert_eq!(write_bits(&mut single, &mut cursor_at(offset, 3), &data), 5);
+        let mut parallel = test_bytes(PIXELS * 3, 8);
+        assert_eq!(write_bits_parallel(&mut parallel, &mut cursor_at(offset, 3), &data, 2), 5);
+        assert_eq!(parallel, single);
+
+        let mut read = vec![0u8; 5];
+        read_bits(&single, &mut cursor_at(offset, 3), &mut read);
+        assert_eq!(read, data[..5]);
+    }
+}
@@ -0,0 +1,264 @@
+// Encrypted container methods.
+//
+// A container holds a set of files packed into a single byte vector,
+// which is then encrypted so it can be embedded as one block.
+// The packed format uses the same fields as the file sections in
+// a pic coded image, without the sync markers:
+//
+// Metadata flag : 1 byte, 'M'.
+// Number of files : (num_files_chars) digit integer, leading zeros.
+// For each file the following applies:
+//
+// File name length : (len_filename_chars) digit integer, leading zeros.
+// File name : file name string in file name length bytes.
+// File length in bytes : (file_len_chars) digit integer, leading zeros.
+// File metadata : modification time and permissions, see the file_tree module.
+// File contents : file bytes in file length bytes.
+//
+// Containers packed before file metadata was recorded have no metadata
+// flag, and no metadata for each file. These can still be unpacked.
+//
+// Containers are encrypted with ChaCha20-Poly1305, using a key derived
+// from the password with PBKDF2. Anything after the packed files is
+// ignored, so containers can be padded out with random bytes.
+
+use log::info;
+use ring::aead::{self, Aad, LessSafeKey, Nonce, UnboundKey};
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::io;
+use std::num::NonZeroU32;
+use zeroize::Zeroize;
+
+use crate::settings::Settings;
+use crate::steg::file_tree::{self, FileMeta, SourceFile};
+
+// Flag at the start of a packed container that has file metadata.
+const META_FLAG : u8 = b'M';
+
+// Length of the authentication tag added to an encrypted container.
+pub const TAG_LEN : usize = 16;
+
+// Struct to hold a file packed into, or unpacked from, a container.
+#[derive(Clone)]
+pub struct ContainerFile {
+    pub file_name: String,
+    pub data: Vec<u8>,
+    pub meta: Option<FileMeta>,
+}
+
+// File data is wiped once done with, so it doesn't linger in memory.
+impl Drop for ContainerFile {
+    fn drop(&mut self) {
+        self.data.zeroize();
+    }
+}
+
+// Struct to hold the keys derived from a container password.
+// The slot selects where in the image the container is located.
+pub struct ContainerKey {
+    pub key: [u8; 32],
+    pub slot: u64,
+}
+
+// Helper function to check that the number of files, and the length
+// of each file name and file, fit in the digits used for them.
+fn check_field_widths(settings:&Settings, sources:&[SourceFile]) -> io::Result<()> {
+    let max_files = 10_usize.pow(settings.num_files_chars as u32) - 1;
+    if sources.len() > max_files {
+        return Err(io::Error::other(format!("No more than {} files can be embedded", max_files)));
+    }
+    let max_name_len = 10_usize.pow(settings.len_filename_chars as u32) - 1;
+    if let Some(source) = sources.iter().find(|source| source.file_name.len() > max_name_len) {
+        return Err(io::Error::other(format!("File name too long: {}", source.file_name)));
+    }
+    let max_file_len = 10_u64.saturating_pow(settings.file_len_chars as u32) - 1;
+    for source in sources {
+        if source.size()? > max_file_len {
+            return Err(io::Error::other(format!("File too big: {}", source.file_name)));
+        }
+    }
+    Ok(())
+}
+
+// Helper function to get the packed size of a set of files
+// without reading them. Directories are expanded into their files.
+pub fn packed_len(settings:&Settings, files:&[&str]) -> io::Result<u64> {
+    let sources = file_tree::expand_files(files)?;
+    check_field_widths(settings, &sources)?;
+    let mut packed_len = 1 + settings.num_files_chars as u64;
+    for source in sources {
+        packed_len += settings.len_filename_chars as u64;
+        packed_len += source.file_name.len() as u64;
+        packed_len += settings.file_len_chars as u64;
+        packed_len += file_tree::meta_len(settings);
+        packed_len += source.size()?;
+    }
+    Ok(packed_len)
+}
+
+// Helper function to pack a set of files into a container.
+// Directories are expanded into their files, which are named
+// by their path relative to the directory.
+pub fn pack_files(settings:&Settings, files:&[&str]) -> io::Result<Vec<u8>> {
+    let sources = file_tree::expand_files(files)?;
+    check_field_widths(settings, &sources)?;
+    let mut packed = vec![META_FLAG];
+    packed.extend(format!("{:0>width$}", sources.len(), width=settings.num_files_chars as usize).as_bytes());
+    for source in sources {
+        let data = source.read()?;
+        let meta = source.meta()?;
+        info!("Packing file: {} Size: {} bytes", source.file_name, data.len());
+
+        packed.extend(format!("{:0>width$}", source.file_name.len(), width=settings.len_filename_chars as usize).as_bytes());
+        packed.extend(source.file_name.as_bytes());
+        packed.extend(format!("{:0>width$}", data.len(), width=settings.file_len_chars as usize).as_bytes());
+        packed.extend(file_tree::format_meta(settings, &meta).as_bytes());
+        packed.extend_from_slice(&data);
+    }
+    Ok(packed)
+}
+
+// Helper function to unpack the files from a container.
+// Returns None if the container is not valid.
+pub fn unpack_files(settings:&Settings, packed:&[u8]) -> Option<Vec<ContainerFile>> {
+    let has_meta = packed.first() == Some(&META_FLAG);
+    let mut pos:usize = if has_meta { 1 } else { 0 };
+    let num_files:usize = read_number(packed, &mut pos, settings.num_files_chars as usize)?;
+    let mut files = Vec::with_capacity(num_files);
+    for _idx in 0..num_files {
+        let file_name_len:usize = read_number(packed, &mut pos, settings.len_filename_chars as usize)?;
+        let file_name = String::from_utf8(packed.get(pos..pos + file_name_len)?.to_vec()).ok()?;
+        pos += file_name_len;
+        let file_len:usize = read_number(packed, &mut pos, settings.file_len_chars as usize)?;
+        let meta = if has_meta {
+            let meta_len = file_tree::meta_len(settings) as usize;
+            let meta = file_tree::parse_meta(settings, packed.get(pos..pos + meta_len)?)?;
+            pos += meta_len;
+            Some(meta)
+        }
+        else {
+            None
+        };
+        let data = packed.get(pos..pos + file_len)?.to_vec();
+        pos += file_len;
+        files.push(ContainerFile { file_name, data, meta });
+    }
+    Some(files)
+}
+
+// Helper function to read a number, stored as digits with
+// leading zeros, from packed container bytes.
+fn read_number<T: std::str::FromStr>(packed:&[u8], pos:&mut usize, num_chars:usize) -> Option<T> {
+    let digits = packed.get(*pos..*pos + num_chars)?;
+    if !digits.iter().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    *pos += num_chars;
+    std::str::from_utf8(digits).ok()?.parse().ok()
+}
+
+// Helper function to derive the container key and slot from a password.
+// The salt is stored in the image so the same key is derived on extraction.
+pub fn derive_key(settings:&Settings, password:&str, salt:&[u8], num_slots:u64) -> ContainerKey {
+    let iterations = NonZeroU32::new(settings.kdf_iterations).unwrap_or(NonZeroU32::MIN);
+    let mut derived = [0u8; 40];
+    pbkdf2::derive(pbkdf2::PBKDF2_HMAC_SHA256, iterations, salt, password.as_bytes(), &mut derived);
+
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&derived[..32]);
+    let slot = u64::from_be_bytes(derived[32..40].try_into().unwrap()) % num_slots.max(1);
+    ContainerKey { key, slot }
+}
+
+// Helper function to encrypt a container.
+// Each key is only ever used for one container, as the salt is
+// regenerated for every embedding, so a fixed nonce is used.
+pub fn seal(key:&[u8; 32], mut data:Vec<u8>) -> Vec<u8> {
+    let sealing_key = LessSafeKey::new(UnboundKey::new(&aead::CHACHA20_POLY1305, key).unwrap());
+    let nonce = Nonce::assume_unique_for_key([0u8; aead::NONCE_LEN]);
+    sealing_key.seal_in_place_append_tag(nonce, Aad::empty(), &mut data).unwrap();
+    data
+}
+
+// Helper function to decrypt a container.
+// Returns None if the key is wrong or the container has been modified.
+pub fn open(key:&[u8; 32], mut data:Vec<u8>) -> Option<Vec<u8>> {
+    let opening_key = LessSafeKey::new(UnboundKey::new(&aead::CHACHA20_POLY1305, key).unwrap());
+    let nonce = Nonce::assume_unique_for_key([0u8; aead::NONCE_LEN]);
+    let plain_len = opening_key.open_in_place(nonce, Aad::empty(), &mut data).ok()?.len();
+    data.truncate(plain_len);
+    Some(data)
+}
+
+// Helper function to get a vector of cryptographically random bytes.
+pub fn random_bytes(len:usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; len];
+    SystemRandom::new().fill(&mut bytes).expect("Failed to generate random bytes");
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    // Helper function to get the settings the service starts with.
+    fn test_settings() -> Settings {
+        serde_yaml::from_str(include_str!("../../settings.yml")).unwrap()
+    }
+
+    // Helper function to get a new empty folder for a test's files.
+    fn test_folder(name:&str) -> PathBuf {
+        let id:String = random_bytes(8).iter().map(|b| format!("{:02x}", b)).collect();
+        let folder = std::env::temp_dir().join(format!("peekaboo-test-{}-{}", name, id));
+        fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    #[test]
+    fn too_many_files_are_refused() {
+        let settings = test_settings();
+        let max_files = 10_usize.pow(settings.num_files_chars as u32) - 1;
+        let folder = test_folder("files");
+        for idx in 0..max_files {
+            fs::write(folder.join(format!("{:04}.txt", idx)), idx.to_string()).unwrap();
+        }
+        let files = [folder.to_str().unwrap()];
+
+        // As many files as the digits allow round trip.
+        let packed = pack_files(&settings, &files).unwrap();
+        assert_eq!(packed_len(&settings, &files).unwrap(), packed.len() as u64);
+        let unpacked = unpack_files(&settings, &packed).unwrap();
+        assert_eq!(unpacked.len(), max_files);
+        assert_eq!(unpacked[7].data, b"7");
+
+        // One more is refused, rather than packed with too many digits.
+        fs::write(folder.join("extra.txt"), "extra").unwrap();
+        assert!(packed_len(&settings, &files).is_err());
+        assert!(pack_files(&settings, &files).is_err());
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn too_long_file_names_are_refused() {
+        let settings = test_settings();
+        let max_name_len = 10_usize.pow(settings.len_filename_chars as u32) - 1;
+
+        // Files in directories are named by their path in the directory,
+        // so nested directories give names longer than any one name.
+        let folder = test_folder("names");
+        let mut path = folder.join("top");
+        while path.strip_prefix(&folder).unwrap().as_os_str().len() <= max_name_len {
+            path.push("d".repeat(200));
+        }
+        fs::create_dir_all(&path).unwrap();
+        fs::write(path.join("file.txt"), "data").unwrap();
+        let files = [folder.join("top")];
+        let files:Vec<&str> = files.iter().map(|path| path.to_str().unwrap()).collect();
+        assert!(packed_len(&settings, &files).is_err());
+        assert!(pack_files(&settings, &files).is_err());
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
@@ -0,0 +1,314 @@
+// File editing methods.
+//
+// Files in a pic coded image can be changed without embedding all of
+// them again. New files can be added into the remaining capacity, and
+// a named file removed or replaced. The file sections are contiguous,
+// so only the file sections from the first one changed onwards are
+// rewritten, along with the number of files in the header and its copies.
+// Any bytes freed at the end of the file sections are overwritten with
+// random data, so removed files can't be recovered.
+// Files keep their metadata when they are moved, and added files,
+// including directories, are recorded as when embedding.
+//
+// Only images with plain file sections can be edited. Volumes, recipient
+// and key slot images, parts and shares hold their files in other forms,
+// and changing signed files would invalidate the signature.
+
+use crate::steg::container::{self, ContainerFile};
+use crate::steg::file_tree::{self, FileMeta};
+use crate::steg::{Steganography, SteganographyError, META_SYNC_CODE, SYNC_CODE};
+
+use log::info;
+use std::io;
+use std::time::Instant;
+
+// Struct to hold the details of a file section in the image,
+// as found in the file table.
+#[derive(Clone, Debug)]
+pub struct FileSection {
+    pub file_idx: u16,
+    pub file_name: String,
+    pub file_len: u64,
+    pub section_pos: u64,
+    pub data_pos: u64,
+    pub meta: Option<FileMeta>,
+}
+
+// Method to read the table of file sections in the loaded image,
+// without reading the file contents.
+// Unlike extraction, every file section must be intact.
+impl Steganography {
+    pub fn read_file_table(&mut self) -> io::Result<Vec<FileSection>> {
+        let num_files = self.header_num_files()
+            .ok_or_else(|| io::Error::other("Invalid number of files"))?;
+
+        // File sections start after the signature section, if there is one.
+        self.seek_payload(0);
+        self.read_signature();
+
+        let mut table = Vec::with_capacity(num_files as usize);
+        for expected_idx in 1..=num_files {
+            let section_pos = self.payload_pos();
+            let has_meta = self.read_sync_marker()
+                .ok_or_else(|| io::Error::other(format!("File section {} is damaged", expected_idx)))?;
+            match self.read_file_details(has_meta) {
+                Some((file_idx, file_name, file_len, meta)) if file_idx == expected_idx => {
+                    let data_pos = self.payload_pos();
+                    table.push(FileSection {
+                        file_idx,
+                        file_name,
+                        file_len: file_len as u64,
+                        section_pos,
+                        data_pos,
+                        meta,
+                    });
+                    self.seek_payload(data_pos + file_len as u64);
+                }
+                _ => return Err(io::Error::other(format!("File section {} is damaged", expected_idx))),
+            }
+        }
+        info!("File table has {} file(s).", table.len());
+        Ok(table)
+    }
+}
+
+// Method to check if the loaded image holds plain file sections,
+// so that its files can be edited.
+impl Steganography {
+    pub fn is_editable(&self) -> bool {
+        self.pic_coded && !self.pic_volumes && !self.pic_recipients && !self.pic_key_slots && !self.pic_legacy
+            && self.pic_part.is_none() && self.pic_share.is_none()
+    }
+}
+
+// Method to check that the files in the loaded image can be edited,
+// and that the password, if there is one, is correct.
+impl Steganography {
+    pub fn check_editable(&mut self, pw:&str) -> io::Result<()> {
+        if !self.is_editable() {
+            info!("Files in image can't be edited.");
+            return Err(io::Error::other("Files in this image can't be edited"));
+        }
+        if self.pic_has_pw {
+            self.check_valid_password(pw.to_string());
+            if !self.user_permit {
+                return Err(io::Error::other(SteganographyError::IncorrectPassword));
+            }
+        }
+        self.seek_payload(0);
+        if self.read_signature().is_some() {
+            info!("Signed files can't be edited.");
+            return Err(io::Error::other("Signed files can't be edited"));
+        }
+        Ok(())
+    }
+}
+
+// Method to find a file section in the file table by file name.
+// Returns the position of the file in the table.
+impl Steganography {
+    pub fn find_file_section(&self, table:&[FileSection], file_name:&str) -> io::Result<usize> {
+        table
+            .iter()
+            .position(|section| section.file_name == file_name)
+            .ok_or_else(|| io::Error::other(format!("Embedded file not found: {}", file_name)))
+    }
+}
+
+// Method to list the files embedded in the loaded image,
+// after checking they can be edited.
+impl Steganography {
+    pub fn list_embedded_files(&mut self, pw:&str) -> io::Result<Vec<FileSection>> {
+        self.check_editable(pw)?;
+        self.read_file_table()
+    }
+}
+
+// Method to add files to those already embedded in the loaded image.
+// The new files are written after the existing files.
+// Directories are added with all the files in them.
+// The image needs saving afterwards.
+impl Steganography {
+    pub fn add_embedded_files(&mut self, pw:&str, files:&[&str]) -> io::Result<()> {
+        // Initialise timer for function.
+        let embed_start = Instant::now();
+
+        self.check_editable(pw)?;
+        let table = self.read_file_table()?;
+        let new_files = read_new_files(files)?;
+        info!("Adding {} file(s) to {} embedded file(s).", new_files.len(), table.len());
+        let first = table.len();
+        self.rewrite_file_sections(&table, first, new_files)?;
+
+        // Determine delta time for function.
+        self.embed_duration = embed_start.elapsed();
+        info!("Time to add file(s): {:?}", self.embed_duration);
+        Ok(())
+    }
+}
+
+// Method to remove a named file from those embedded in the loaded image.
+// The files after it are moved down to take its place.
+// The image needs saving afterwards.
+impl Steganography {
+    pub fn remove_embedded_file(&mut self, pw:&str, file_name:&str) -> io::Result<()> {
+        // Initialise timer for function.
+        let embed_start = Instant::now();
+
+        self.check_editable(pw)?;
+        let table = self.read_file_table()?;
+        let first = self.find_file_section(&table, file_name)?;
+        info!("Removing embedded file: {}", file_name);
+        let kept_files = self.read_file_sections(&table[first + 1..]);
+        self.rewrite_file_sections(&table, first, kept_files)?;
+
+        // Determine delta time for function.
+        self.embed_duration = embed_start.elapsed();
+        info!("Time to remove file: {:?}", self.embed_duration);
+        Ok(())
+    }
+}
+
+// Method to replace a named file embedded in the loaded image with
+// another file. The new file takes the place of the old one, in the
+// same directory, with the files after it moved as needed.
+// The image needs saving afterwards.
+impl Steganography {
+    pub fn replace_embedded_file(&mut self, pw:&str, file_name:&str, new_file:&str) -> io::Result<()> {
+        // Initialise timer for function.
+        let embed_start = Instant::now();
+
+        self.check_editable(pw)?;
+        let table = self.read_file_table()?;
+        let first = self.find_file_section(&table, file_name)?;
+        info!("Replacing embedded file: {} with: {}", file_name, new_file);
+        let mut new_files = read_new_files(&[new_file])?;
+        if let (Some((dir, _)), [replacement]) = (file_name.rsplit_once('/'), new_files.as_mut_slice()) {
+            replacement.file_name = format!("{}/{}", dir, replacement.file_name);
+        }
+        new_files.extend(self.read_file_sections(&table[first + 1..]));
+        self.rewrite_file_sections(&table, first, new_files)?;
+
+        // Determine delta time for function.
+        self.embed_duration = embed_start.elapsed();
+        info!("Time to replace file: {:?}", self.embed_duration);
+        Ok(())
+    }
+}
+
+// Method to read the names and contents of file sections,
+// ready for them to be rewritten.
+impl Steganography {
+    pub fn read_file_sections(&mut self, sections:&[FileSection]) -> Vec<ContainerFile> {
+        let mut files = Vec::with_capacity(sections.len());
+        for section in sections {
+            self.seek_payload(section.data_pos);
+            files.push(ContainerFile {
+                file_name: section.file_name.clone(),
+                data: self.read_chunks(section.file_len),
+                meta: section.meta,
+            });
+        }
+        files
+    }
+}
+
+// Method to rewrite the file sections from a position in the file table
+// onwards, with the files given, and update the number of files in the
+// header. Files before that position are left untouched.
+impl Steganography {
+    pub fn rewrite_file_sections(&mut self, table:&[FileSection], first:usize, files:Vec<ContainerFile>) -> io::Result<()> {
+        // Check that the number of files can be held in the header.
+        let num_files = first + files.len();
+        let max_files = 10_usize.pow(self.settings.num_files_chars as u32) - 1;
+        if num_files > max_files {
+            return Err(io::Error::other(format!("No more than {} files can be embedded", max_files)));
+        }
+        let max_name_len = 10_usize.pow(self.settings.len_filename_chars as u32) - 1;
+        let invalid_file = files
+            .iter()
+            .find(|file| file.file_name.len() > max_name_len || file_tree::safe_relative_path(&file.file_name).is_none());
+        if let Some(file) = invalid_file {
+            return Err(io::Error::other(format!("Invalid file name: {}", file.file_name)));
+        }
+
+        // Rewriting starts at the first file changed, or after the
+        // last file if files are only being added.
+        let table_end = table.last().map(|section| section.data_pos + section.file_len).unwrap_or(0);
+        let start_pos = table.get(first).map(|section| section.section_pos).unwrap_or(table_end);
+
+        // Check that the files will fit in the image.
+        let section_overhead = SYNC_CODE.len() as u64
+            + self.settings.num_files_chars as u64
+            + self.settings.len_filename_chars as u64
+            + self.settings.file_len_chars as u64;
+        let meta_len = file_tree::meta_len(&self.settings);
+        let bytes_to_embed:u64 = files
+            .iter()
+            .map(|file| {
+                section_overhead + file.file_name.len() as u64 + file.data.len() as u64
+                    + if file.meta.is_some() { meta_len } else { 0 }
+            })
+            .sum();
+        if start_pos + bytes_to_embed > self.embed_capacity {
+            return Err(io::Error::other(format!("Files exceed capacity of {} bytes", self.embed_capacity.saturating_sub(start_pos))));
+        }
+
+        // Write the file sections, numbered on from the files kept.
+        self.seek_payload(start_pos);
+        for (idx, file) in files.iter().enumerate() {
+            self.embed_file_section(file, (first + idx + 1) as u16);
+        }
+
+        // Overwrite anything left of the old file sections.
+        let new_end = self.payload_pos();
+        if new_end < table_end {
+            info!("Clearing {} bytes of old file data.", table_end - new_end);
+            self.write_chunks(&container::random_bytes((table_end - new_end) as usize));
+        }
+        if self.noise_fill {
+            self.embed_noise();
+        }
+
+        // Update the number of files in the header.
+        let num_start = self.header_len() as usize - self.settings.num_files_chars as usize;
+        let mut header = self.header_bytes.clone();
+        header[num_start..].copy_from_slice(format!("{:0>width$}", num_files, width=self.settings.num_files_chars as usize).as_bytes());
+        self.write_header(&header);
+        info!("Image now has {} embedded file(s).", num_files);
+        Ok(())
+    }
+}
+
+// Method to embed a file section for file data held in memory.
+// Files without metadata are written as they were originally embedded.
+impl Steganography {
+    pub fn embed_file_section(&mut self, file:&ContainerFile, file_idx:u16) {
+        let (sync_code, meta) = match &file.meta {
+            Some(meta) => (META_SYNC_CODE, file_tree::format_meta(&self.settings, meta)),
+            None => (SYNC_CODE, String::new()),
+        };
+        let file_details = format!(
+            "{}{:0>idx_width$}{:0>name_width$}{}{:0>len_width$}{}",
+            sync_code, file_idx, file.file_name.len(), file.file_name, file.data.len(), meta,
+            idx_width=self.settings.num_files_chars as usize,
+            name_width=self.settings.len_filename_chars as usize,
+            len_width=self.settings.file_len_chars as usize,
+        );
+        self.write_chunks(file_details.as_bytes());
+        self.write_chunks(&file.data);
+    }
+}
+
+// Helper function to read the files to be embedded, expanding any
+// directories, with the name and metadata to embed each one with.
+fn read_new_files(files:&[&str]) -> io::Result<Vec<ContainerFile>> {
+    file_tree::expand_files(files)?
+        .into_iter()
+        .map(|source| Ok(ContainerFile {
+            data: source.read()?.to_vec(),
+            meta: Some(source.meta()?),
+            file_name: source.file_name,
+        }))
+        .collect()
+}
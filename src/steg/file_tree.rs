@@ -0,0 +1,261 @@
+// Directory and file metadata helpers.
+//
+// Directories given to be embedded are embedded recursively, with each
+// file named by its path relative to the directory's parent, using '/'
+// between path components. So embedding "photos" holding "2024/a.png"
+// gives the file name "photos/2024/a.png".
+//
+// The modification time and Unix permissions of each file are recorded
+// along with its name, as:
+//
+// File modification time : (file_time_chars) digit integer, leading zeros,
+//                          seconds since the Unix epoch.
+// File permissions : (file_mode_chars) octal digits, leading zeros.
+//
+// On extraction the directories are recreated under the secrets folder,
+// and the metadata restored, although only the read, write and execute
+// permissions. File names are checked so that a file can never be written
+// outside the secrets folder.
+//
+// Extracted files are deleted securely, overwritten with zeros and flushed
+// to disk before being unlinked, so their contents don't linger on disk.
+
+use crate::settings::Settings;
+
+use log::{info, warn};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use zeroize::Zeroizing;
+
+// Struct to hold a file to be embedded, with the path to read it
+// from and the name to embed it as. Files uploaded in memory only mode
+// are held in memory instead, along with the metadata to record for them.
+#[derive(Clone, Debug)]
+pub struct SourceFile {
+    pub path: String,
+    pub file_name: String,
+    pub memory: Option<(Arc<Zeroizing<Vec<u8>>>, FileMeta)>,
+}
+
+impl SourceFile {
+    // Make a file to be embedded from data held in memory,
+    // recorded as modified now and readable by anyone.
+    pub fn in_memory(file_name:&str, data:Zeroizing<Vec<u8>>) -> Self {
+        let modified = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+        SourceFile {
+            path: String::new(),
+            file_name: file_name.to_string(),
+            memory: Some((Arc::new(data), FileMeta { modified, mode: 0o644 })),
+        }
+    }
+
+    // Get the size of the file.
+    pub fn size(&self) -> io::Result<u64> {
+        match &self.memory {
+            Some((data, _)) => Ok(data.len() as u64),
+            None => Ok(fs::metadata(&self.path)?.len()),
+        }
+    }
+
+    // Get the metadata to record for the file.
+    pub fn meta(&self) -> io::Result<FileMeta> {
+        match &self.memory {
+            Some((_, meta)) => Ok(*meta),
+            None => read_meta(&self.path),
+        }
+    }
+
+    // Read the whole file.
+    pub fn read(&self) -> io::Result<Zeroizing<Vec<u8>>> {
+        match &self.memory {
+            Some((data, _)) => Ok(Zeroizing::new(data.to_vec())),
+            None => Ok(Zeroizing::new(fs::read(&self.path)?)),
+        }
+    }
+
+    // Open the file for reading a chunk at a time.
+    pub fn open(&self) -> io::Result<Box<dyn Read + '_>> {
+        match &self.memory {
+            Some((data, _)) => Ok(Box::new(data.as_slice())),
+            None => Ok(Box::new(File::open(&self.path)?)),
+        }
+    }
+}
+
+// Struct to hold the metadata recorded for an embedded file.
+#[derive(Clone, Copy, Debug)]
+pub struct FileMeta {
+    pub modified: u64,
+    pub mode: u32,
+}
+
+// Method to get the permissions to restore from the recorded mode.
+// Only the read, write and execute bits are kept, never the setuid,
+// setgid or sticky bits, as the image can't be trusted with those.
+impl FileMeta {
+    pub fn permissions(&self) -> u32 {
+        self.mode & 0o777
+    }
+}
+
+// Helper function to expand a list of files and directories into the
+// files to embed. Directories are walked recursively, in name order.
+// Symbolic links to directories are not followed.
+pub fn expand_files(files:&[&str]) -> io::Result<Vec<SourceFile>> {
+    let mut sources = Vec::new();
+    for file in files {
+        let path = Path::new(file);
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| io::Error::other(format!("Invalid file name: {}", file)))?
+            .to_string_lossy()
+            .into_owned();
+        if fs::symlink_metadata(path)?.is_dir() {
+            expand_dir(path, &file_name, &mut sources)?;
+        }
+        else {
+            sources.push(SourceFile { path: file.to_string(), file_name, memory: None });
+        }
+    }
+    Ok(sources)
+}
+
+// Helper function to add the files in a directory, and its
+// sub-directories, to the files to embed.
+fn expand_dir(dir:&Path, dir_name:&str, sources:&mut Vec<SourceFile>) -> io::Result<()> {
+    info!("Adding directory: {}", dir.display());
+    let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<fs::DirEntry>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = entry.path();
+        let file_name = format!("{}/{}", dir_name, entry.file_name().to_string_lossy());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            expand_dir(&path, &file_name, sources)?;
+        }
+        else if path.is_file() {
+            sources.push(SourceFile { path: path.to_string_lossy().into_owned(), file_name, memory: None });
+        }
+        else {
+            warn!("Skipping: {}", path.display());
+        }
+    }
+    Ok(())
+}
+
+// Helper function to read the metadata to record for a file.
+pub fn read_meta(path:&str) -> io::Result<FileMeta> {
+    let metadata = fs::metadata(path)?;
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    #[cfg(unix)]
+    let mode = std::os::unix::fs::PermissionsExt::mode(&metadata.permissions()) & 0o7777;
+    #[cfg(not(unix))]
+    let mode = if metadata.permissions().readonly() { 0o444 } else { 0o644 };
+    Ok(FileMeta { modified, mode })
+}
+
+// Helper function to get the number of bytes used by the metadata.
+pub fn meta_len(settings:&Settings) -> u64 {
+    settings.file_time_chars as u64 + settings.file_mode_chars as u64
+}
+
+// Helper function to format the metadata for embedding.
+pub fn format_meta(settings:&Settings, meta:&FileMeta) -> String {
+    format!(
+        "{:0>time_width$}{:0>mode_width$o}",
+        meta.modified, meta.mode,
+        time_width=settings.file_time_chars as usize,
+        mode_width=settings.file_mode_chars as usize,
+    )
+}
+
+// Helper function to parse the metadata from embedded bytes.
+// Returns None if the metadata is not valid.
+pub fn parse_meta(settings:&Settings, bytes:&[u8]) -> Option<FileMeta> {
+    let text = std::str::from_utf8(bytes).ok()?;
+    if text.len() != meta_len(settings) as usize || !text.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let (modified, mode) = text.split_at(settings.file_time_chars as usize);
+    Some(FileMeta {
+        modified: modified.parse().ok()?,
+        mode: u32::from_str_radix(mode, 8).ok()?,
+    })
+}
+
+// Helper function to check an embedded file name is a safe relative path,
+// and convert it to a path. Returns None for absolute paths, paths that
+// go up a directory, or anything else that could escape the folder the
+// file is extracted to.
+pub fn safe_relative_path(file_name:&str) -> Option<PathBuf> {
+    let mut path = PathBuf::new();
+    for component in file_name.split('/') {
+        if component.is_empty() || component == "." || component == ".."
+            || component.contains(['\\', ':', '\0']) {
+            return None;
+        }
+        path.push(component);
+    }
+    Some(path)
+}
+
+// Helper function to restore the recorded metadata to an extracted file.
+// The modification time is set before the permissions, in case the
+// file is read only.
+pub fn restore_meta(path:&str, meta:&FileMeta) -> io::Result<()> {
+    let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(meta.modified);
+    File::options().write(true).open(path)?.set_modified(modified)?;
+    #[cfg(unix)]
+    fs::set_permissions(path, std::os::unix::fs::PermissionsExt::from_mode(meta.permissions()))?;
+    Ok(())
+}
+
+// Helper function to securely delete a file, overwriting its contents with
+// zeros, and flushing them to disk, before unlinking it. Read only files are
+// made writable first. Anything other than a regular file is just unlinked.
+pub fn secure_delete(path:&Path) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(path)?;
+    if metadata.is_file() {
+        #[cfg(unix)]
+        if metadata.permissions().readonly() {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(path, fs::Permissions::from_mode(metadata.permissions().mode() | 0o200))?;
+        }
+        let mut file = File::options().write(true).open(path)?;
+        let zeros = [0u8; 65536];
+        let mut remaining = metadata.len();
+        while remaining > 0 {
+            let len = remaining.min(zeros.len() as u64) as usize;
+            file.write_all(&zeros[..len])?;
+            remaining -= len as u64;
+        }
+        file.sync_all()?;
+    }
+    info!("Securely deleting file: {}", path.display());
+    fs::remove_file(path)
+}
+
+// Helper function to securely delete a folder, and everything in it.
+pub fn secure_delete_dir(path:&Path) -> io::Result<()> {
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            secure_delete_dir(&entry.path())?;
+        }
+        else {
+            secure_delete(&entry.path())?;
+        }
+    }
+    fs::remove_dir(path)
+}
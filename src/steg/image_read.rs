@@ -1,90 +1,82 @@
 // Image pixel read methods.
 
+use crate::steg::bit_stream;
 use crate::steg::Steganography;
 
-use image::{GenericImageView, Pixel};
+use log::error;
 
 // Method to read a certain number of bytes from an image.
+// Bytes are read straight from the image colour samples,
+// skipping over any reserved header copy regions.
 impl Steganography {
     pub fn read_data_from_image(&mut self, bytes_to_read:u32) {
 
-        // Initial loop counters.
-        let mut bytes_read:u32 = 0;
-        let mut row_cnt:u32 = self.row;
-        let mut col_cnt:u32 = self.col;
-        let mut col_plane:usize = self.plane;
-        let mut _bits_read:u8 = self.bit;
-        let mut _col_part:u8 = 0;
-        let mut _code_data:u8 = 0;
-        let mut _byte_bit:u8 = 0;
-        let mut _mask:u8 = 0;
-
         // Initialise byte vector for read data.
-        self.code_bytes = Vec::with_capacity(bytes_to_read as usize);
-
-        // Initialise a colour bit mask.
-        // This is so we can read an individual
-        // bit in a pixel colour byte.
-        _mask = 1 << _bits_read;
+        let mut code_bytes = vec![0u8; bytes_to_read as usize];
 
-        // Loop while there are still bytes to read.
-        while bytes_read < bytes_to_read {
-            _code_data = 0;
+        // Get the colour samples of the image to read from.
+        let image = self.image.take();
+        match image.as_ref().and_then(bit_stream::image_samples) {
+            Some((samples, channels)) => {
+                let mut cursor = self.bit_cursor(channels);
+                let mut stream_pos = self.stream_pos;
+                let mut bytes_read:usize = 0;
 
-            // Extract 1 byte of data from image.
-            // one bit at a time.
-            for _idx in 1..9 {
-                // Get the pixel colour for the pixel we are at.
-                if let Some(image) = &self.image {
-                    if self.pic_col_planes == 3 { 
-                        _col_part = image.get_pixel(col_cnt, row_cnt).to_rgb()[col_plane];
-                    } else {
-                        _col_part = image.get_pixel(col_cnt, row_cnt).to_rgba()[col_plane];
+                // Loop while there are still bytes to read.
+                while bytes_read < code_bytes.len() {
+                    // If we have run into a reserved header copy region
+                    // then jump over it to the next payload byte.
+                    if self.skip_reserved {
+                        if let Some(end) = self.reserved_end(stream_pos) {
+                            stream_pos = end;
+                            self.seek_stream(stream_pos);
+                            cursor = self.bit_cursor(channels);
+                        }
                     }
-                }
 
-                // Update the code data bit with the bit from the pixel.
-                _byte_bit = _col_part & _mask;
-                _byte_bit = _byte_bit >> _bits_read;
-                _code_data = _code_data << 1;
-                _code_data = _code_data | _byte_bit;
-
-                // Next time around we need to point to the next pixel in the row.
-                col_cnt = col_cnt + 1;
-                // Until we get to the end of the row.
-                // Then move to the start of the next row.
-                if col_cnt == self.pic_width {
-                    col_cnt = 0;
-                    row_cnt = row_cnt + 1;
-                    // If we have reached the end of the image then go
-                    // back to the top and go to the next bit.
-                    if row_cnt == self.pic_height {
-                        row_cnt = 0;
-                        col_plane = col_plane + 1;
-                        // If we have processed the last plane (colour)
-                        // ee go back to the next bit of the first plane,
-                        if col_plane == 3 {
-                            col_plane = 0;
-                            _bits_read = _bits_read + 1;
-                            _mask = _mask << 1;
-                        }
+                    // Read up to the next reserved region in one go.
+                    let segment_len = self.bytes_to_reserved(stream_pos)
+                        .min((code_bytes.len() - bytes_read) as u64) as usize;
+                    // Large segments are split across threads,
+                    // if there is more than one thread to use.
+                    let piece_len = self.settings.parallel_chunk as usize;
+                    if piece_len > 0 && segment_len > piece_len && rayon::current_num_threads() > 1 {
+                        bit_stream::read_bits_parallel(samples, &mut cursor, &mut code_bytes[bytes_read..bytes_read + segment_len], piece_len);
                     }
+                    else {
+                        bit_stream::read_bits(samples, &mut cursor, &mut code_bytes[bytes_read..bytes_read + segment_len]);
+                    }
+
+                    // Increment bytes read, and position in the stream.
+                    bytes_read += segment_len;
+                    stream_pos += segment_len as u64;
                 }
-            }
-            // Push the completed byte into the byte vector.
-            self.code_bytes.push(_code_data);
 
-            // Increment bytes read.
-            bytes_read = bytes_read + 1;
+                // Save the state of the reading.
+                // This allows us to carry on reading from where we
+                // left off on the next chunk of reading.
+                self.save_bit_cursor(&cursor, stream_pos);
+            }
+            None => error!("Image is not a supported rgb colour type."),
         }
+        self.image = image;
 
-        // Save the state of the reading.
-        // This allows us to carry on reading from where we
-        // left off on the next chunk of reading.
-        self.row = row_cnt;
-        self.col = col_cnt;
-        self.plane = col_plane;
-        self.bit = _bits_read;
-        self.bytes_read = bytes_read;
+        self.code_bytes = code_bytes;
+        self.bytes_read = bytes_to_read;
+    }
+}
+
+// Method to read bytes from the image a chunk at a time.
+impl Steganography {
+    pub fn read_chunks(&mut self, num_bytes:u64) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(num_bytes as usize);
+        let mut bytes_remaining = num_bytes;
+        while bytes_remaining > 0 {
+            let bytes_to_read = bytes_remaining.min(self.settings.byte_chunk as u64) as u32;
+            self.read_data_from_image(bytes_to_read);
+            bytes.extend(&self.code_bytes);
+            bytes_remaining -= bytes_to_read as u64;
+        }
+        bytes
     }
 }
@@ -0,0 +1,251 @@
+// Key slot methods.
+//
+// Files can be embedded so that several different passwords can extract
+// them, with passwords added and removed later without re-embedding the
+// files. The files are packed into a container and encrypted with a
+// random data key. The data key is then encrypted with a key derived from
+// each password, and stored in a key slot.
+//
+// In key slot mode the header password flag is 'S', the password hash is
+// zeros, and the number of files is the number of key slots. The file
+// data area is then:
+//
+// For each key slot :
+//     Slot status : 1 byte, '1' if in use, '0' if free.
+//     Slot salt : (slot_salt_len) random bytes.
+//     Slot key : data key encrypted with the password derived key.
+// Container length in bytes : (file_len_chars) digit integer, leading zeros.
+// Container : encrypted container in container length bytes.
+//
+// Free slots are filled with random bytes.
+
+use crate::settings::Settings;
+use crate::steg::container::{self, TAG_LEN};
+use crate::steg::{Steganography, SteganographyError};
+
+use log::{info, warn};
+use std::io;
+use std::time::Instant;
+
+// Length of the random data key.
+const DATA_KEY_LEN : usize = 32;
+
+// Length of the salt for each key slot.
+const SLOT_SALT_LEN : usize = 16;
+
+// Length of a key slot.
+const SLOT_LEN : usize = 1 + SLOT_SALT_LEN + DATA_KEY_LEN + TAG_LEN;
+
+// Helper function to build a key slot holding the data key
+// encrypted for a password.
+fn make_slot(settings:&Settings, password:&str, data_key:&[u8; DATA_KEY_LEN]) -> Vec<u8> {
+    let salt = container::random_bytes(SLOT_SALT_LEN);
+    let key = container::derive_key(settings, password, &salt, 1);
+    [b"1".as_slice(), &salt, &container::seal(&key.key, data_key.to_vec())].concat()
+}
+
+// Helper function to build a free key slot.
+fn free_slot() -> Vec<u8> {
+    [b"0".as_slice(), &container::random_bytes(SLOT_LEN - 1)].concat()
+}
+
+// Method to get the number of key slots in the loaded image.
+impl Steganography {
+    pub fn num_key_slots(&self) -> u64 {
+        self.header_num_files().unwrap_or(0) as u64
+    }
+}
+
+// Method to embed files into a loaded image with a key slot for each password.
+impl Steganography {
+    pub fn embed_key_slots(&mut self, passwords:&[&str], files:&[&str]) -> io::Result<()> {
+        // Initialise timer for function.
+        let embed_start = Instant::now();
+
+        if !self.img_to_proc {
+            info!("No image to embed files into.");
+            return Err(io::Error::other("No image loaded"));
+        }
+        let num_slots = self.settings.key_slots as usize;
+        if passwords.is_empty() || passwords.len() > num_slots || passwords.contains(&"") {
+            return Err(io::Error::other(format!("Between 1 and {} non-empty passwords are needed", num_slots)));
+        }
+        self.set_layout(false);
+
+        // Check that the key slots and files will fit in the image.
+        let bytes_to_embed = (num_slots * SLOT_LEN) as u64
+            + self.settings.file_len_chars as u64
+            + container::packed_len(&self.settings, files)?
+            + TAG_LEN as u64;
+        if bytes_to_embed > self.embed_capacity {
+            warn!("Exceeded image emdedding: {}", self.embed_capacity);
+            return Err(io::Error::other(format!("Files exceed capacity of {} bytes", self.embed_capacity)));
+        }
+        info!("Total data to embed: {} bytes", bytes_to_embed);
+
+        // Fill the key slots, the first for each password, and the rest free.
+        let data_key: [u8; DATA_KEY_LEN] = container::random_bytes(DATA_KEY_LEN).try_into().unwrap();
+        let mut slots = Vec::with_capacity(num_slots * SLOT_LEN);
+        for slot in 0..num_slots {
+            match passwords.get(slot) {
+                Some(password) => slots.extend(make_slot(&self.settings, password, &data_key)),
+                None => slots.extend(free_slot()),
+            }
+        }
+
+        // Write the header with the key slot flag.
+        self.embed_preamble();
+        info!("Embedding key slot flag.");
+        self.write_chunks(&[b"S".as_slice(), &vec![0u8; self.settings.pw_chars as usize]].concat());
+        self.embed_num_of_files(num_slots as u16);
+        self.embed_header_copies();
+
+        // Write the key slots, then the encrypted container.
+        info!("Embedding files with {} key slot(s) in use.", passwords.len());
+        let sealed = container::seal(&data_key, container::pack_files(&self.settings, files)?);
+        let sealed_len = format!("{:0>width$}", sealed.len(), width=self.settings.file_len_chars as usize);
+        self.write_chunks(&slots);
+        self.write_chunks(sealed_len.as_bytes());
+        self.write_chunks(&sealed);
+
+        if self.noise_fill {
+            self.embed_noise();
+        }
+
+        // Determine delta time for function.
+        self.embed_duration = embed_start.elapsed();
+        info!("Time to embed key slot file(s): {:?}", self.embed_duration);
+        Ok(())
+    }
+}
+
+// Method to list which key slots are in use in the loaded image.
+impl Steganography {
+    pub fn list_key_slots(&mut self) -> Vec<bool> {
+        let mut slots = Vec::new();
+        for slot in 0..self.num_key_slots() {
+            self.seek_payload(slot * SLOT_LEN as u64);
+            self.read_data_from_image(1);
+            slots.push(self.code_bytes == b"1");
+        }
+        slots
+    }
+}
+
+// Method to get the data key using a password.
+// Returns the data key, and the slot that the password opened.
+impl Steganography {
+    pub fn unlock_key_slot(&mut self, password:&str) -> Result<([u8; DATA_KEY_LEN], u64), SteganographyError> {
+        for slot in 0..self.num_key_slots() {
+            self.seek_payload(slot * SLOT_LEN as u64);
+            let slot_bytes = self.read_chunks(SLOT_LEN as u64);
+            if slot_bytes[0] != b'1' {
+                continue;
+            }
+            let key = container::derive_key(&self.settings, password, &slot_bytes[1..1 + SLOT_SALT_LEN], 1);
+            let data_key = container::open(&key.key, slot_bytes[1 + SLOT_SALT_LEN..].to_vec())
+                .and_then(|data_key| <[u8; DATA_KEY_LEN]>::try_from(data_key).ok());
+            if let Some(data_key) = data_key {
+                info!("Password opens key slot: {}", slot);
+                return Ok((data_key, slot));
+            }
+        }
+        info!("Password does not open any key slot.");
+        Err(SteganographyError::IncorrectPassword)
+    }
+}
+
+// Method to add a key slot for a new password to the loaded image.
+// An existing password is needed to get the data key.
+// Returns the slot used. The image needs saving afterwards.
+impl Steganography {
+    pub fn add_key_slot(&mut self, password:&str, new_password:&str) -> Result<u64, SteganographyError> {
+        if !self.pic_key_slots {
+            return Err(SteganographyError::NoKeySlots);
+        }
+        if new_password.is_empty() {
+            return Err(SteganographyError::IncorrectPassword);
+        }
+        let (data_key, _) = self.unlock_key_slot(password)?;
+        let slot = self.list_key_slots()
+            .iter()
+            .position(|in_use| !in_use)
+            .ok_or(SteganographyError::NoFreeKeySlot)? as u64;
+
+        info!("Adding password to key slot: {}", slot);
+        let slot_bytes = make_slot(&self.settings, new_password, &data_key);
+        self.seek_payload(slot * SLOT_LEN as u64);
+        self.write_chunks(&slot_bytes);
+        Ok(slot)
+    }
+}
+
+// Method to change the password of a key slot in the loaded image.
+// The new password replaces the old one in the slot it opens.
+// The image needs saving afterwards.
+impl Steganography {
+    pub fn rekey_key_slot(&mut self, password:&str, new_password:&str) -> Result<u64, SteganographyError> {
+        if !self.pic_key_slots {
+            return Err(SteganographyError::NoKeySlots);
+        }
+        if new_password.is_empty() {
+            return Err(SteganographyError::RekeyNotSupported);
+        }
+        let (data_key, slot) = self.unlock_key_slot(password)?;
+
+        info!("Changing password of key slot: {}", slot);
+        let slot_bytes = make_slot(&self.settings, new_password, &data_key);
+        self.seek_payload(slot * SLOT_LEN as u64);
+        self.write_chunks(&slot_bytes);
+        Ok(slot)
+    }
+}
+
+// Method to remove a key slot from the loaded image.
+// Any password in use is needed, and the last slot in use can't be removed.
+// The image needs saving afterwards.
+impl Steganography {
+    pub fn remove_key_slot(&mut self, password:&str, slot:u64) -> Result<(), SteganographyError> {
+        if !self.pic_key_slots {
+            return Err(SteganographyError::NoKeySlots);
+        }
+        self.unlock_key_slot(password)?;
+        let slots = self.list_key_slots();
+        if !slots.get(slot as usize).copied().unwrap_or(false) {
+            return Err(SteganographyError::InvalidKeySlot);
+        }
+        if slots.iter().filter(|in_use| **in_use).count() == 1 {
+            return Err(SteganographyError::LastKeySlot);
+        }
+
+        info!("Removing key slot: {}", slot);
+        self.seek_payload(slot * SLOT_LEN as u64);
+        self.write_chunks(&free_slot());
+        Ok(())
+    }
+}
+
+// Method to extract the files from a key slot image using a password.
+impl Steganography {
+    pub fn extract_key_slots(&mut self, password:&str) -> Result<(), SteganographyError> {
+        let (data_key, _) = self.unlock_key_slot(password)?;
+        self.user_permit = true;
+
+        // Read and decrypt the container after the key slots.
+        self.seek_payload(self.num_key_slots() * SLOT_LEN as u64);
+        let sealed_len:u64 = self.read_number(self.settings.file_len_chars as u32).unwrap_or(0);
+        let sealed = self.read_chunks(sealed_len.min(self.embed_capacity));
+        let packed = container::open(&data_key, sealed).unwrap_or_default();
+
+        // Save each of the files.
+        let files = container::unpack_files(&self.settings, &packed).unwrap_or_default();
+        info!("Number of files in key slot container: {}", files.len());
+        for file in files {
+            if let Err(err) = self.save_extracted_file(&file) {
+                warn!("Failed to extract file: {}", err);
+            }
+        }
+        self.analyse_extracted_files();
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+// Original layout methods.
+//
+// Images coded before the header copies and file sync markers were
+// added have a shorter header, and file sections straight after it:
+//
+// Pic coded signature : specific, but arbitray number of bytes.
+// Password enabled : 1 byte, 'Y' or 'N'.
+// Password hash : 32 byte hash of password, only if password enabled.
+// Number of files embedded : (num_files_chars) digit integer, leading zeros.
+// For each file section the following applies:
+//
+// File name length : (len_filename_chars) digit integer, leading zeros.
+// File name : file name string in file name length bytes.
+// File length in bytes : (file_len_chars) digit integer, leading zeros.
+// File contents : file bytes in file length bytes.
+//
+// The current format always has a marker, or a header copy, straight
+// after the header, and a zero hash if there is no password, so the
+// original layout can be told apart by digits in those places.
+
+use crate::steg::{file_tree, Steganography, SteganographyError, PROG_CODE};
+
+use log::{info, warn};
+use std::io;
+
+// Method to check if the loaded image uses the original layout.
+// If so the layout is set up without header copies.
+impl Steganography {
+    pub fn check_for_legacy(&mut self) {
+        self.pic_legacy = false;
+        let flag_pos = PROG_CODE.len();
+        let num_files_chars = self.settings.num_files_chars as usize;
+
+        // Without a password the number of files follows the flag,
+        // where the current format has a zero hash.
+        // With a password the header is the same length as now, so
+        // look for the first file name length after it.
+        let digits = match self.header_bytes.get(flag_pos) {
+            Some(b'N') => {
+                let num_start = flag_pos + self.settings.pw_protected_chars as usize;
+                self.header_bytes.get(num_start..num_start + num_files_chars).map(|b| b.to_vec())
+            }
+            Some(b'Y') => {
+                if self.stream_len() < (self.header_len() + self.settings.len_filename_chars as u32) as u64 {
+                    return;
+                }
+                self.skip_reserved = false;
+                self.seek_stream(self.header_len() as u64);
+                self.read_data_from_image(self.settings.len_filename_chars as u32);
+                Some(self.code_bytes.clone())
+            }
+            _ => None,
+        };
+        if !digits.is_some_and(|d| !d.is_empty() && d.iter().all(|b| b.is_ascii_digit())) {
+            return;
+        }
+
+        info!("Image uses the original layout.");
+        self.pic_legacy = true;
+        self.header_copy_locs = Vec::new();
+        self.embed_capacity = self.stream_len().saturating_sub(self.header_len() as u64);
+    }
+}
+
+// Method to get embedded data from an image with the original layout.
+// Files are read in sequence, so a damaged file section ends the extraction.
+// If the extraction is cancelled, the files extracted so far are removed.
+impl Steganography {
+    pub fn get_legacy_data(&mut self) -> Result<(), SteganographyError> {
+        // The header is only as long as the parts that were written.
+        let mut header_len = PROG_CODE.len() + self.settings.pw_protected_chars as usize;
+        if self.pic_has_pw {
+            header_len += self.settings.pw_chars as usize;
+        }
+        self.skip_reserved = false;
+        self.seek_stream(header_len as u64);
+
+        let num_files:u16 = match self.read_number(self.settings.num_files_chars as u32) {
+            Some(num_files) => num_files,
+            None => {
+                warn!("Invalid number of files length.");
+                return Ok(());
+            }
+        };
+        info!("Number of embedded files: {}", num_files);
+        self.file_digests = Vec::new();
+
+        for file_idx in 1..=num_files {
+            let file_name = match self.read_number::<u32>(self.settings.len_filename_chars as u32) {
+                Some(name_len) if name_len > 0 && self.payload_pos() + name_len as u64 <= self.embed_capacity => {
+                    self.read_data_from_image(name_len);
+                    String::from_utf8(self.code_bytes.clone()).ok()
+                        .filter(|name| file_tree::safe_relative_path(name).is_some())
+                }
+                _ => None,
+            };
+            let file_len = self.read_number::<u32>(self.settings.file_len_chars as u32)
+                .filter(|len| self.payload_pos() + *len as u64 <= self.embed_capacity);
+            let (Some(file_name), Some(file_len)) = (file_name, file_len) else {
+                warn!("Invalid file section, no further files can be found.");
+                break;
+            };
+
+            info!("Embedded file {}: {}, length: {}", file_idx, file_name, file_len);
+            match self.extract_file(file_len, file_name, None) {
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {
+                    info!("Extraction cancelled.");
+                    self.remove_extracted_files();
+                    self.memory_files = Vec::new();
+                    return Err(SteganographyError::Cancelled);
+                }
+                Err(err) => {
+                    warn!("Failed to extract file: {}", err);
+                    break;
+                }
+                Ok(()) => (),
+            }
+        }
+
+        // Go through extracted files and check if embedded.
+        self.analyse_extracted_files();
+        Ok(())
+    }
+}
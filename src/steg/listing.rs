@@ -0,0 +1,96 @@
+// File listing and selective extraction methods.
+//
+// The files embedded in an image can be listed without extracting them,
+// giving the name, size, MIME type and SHA-256 checksum of each file,
+// and its modification time and permissions if they were recorded.
+// Nothing is written to disk while listing. Files are read just as for
+// extraction, so every kind of pic coded image can be listed, and a
+// signature is still checked.
+//
+// Extraction can also be limited to chosen files, selected by file name
+// or by index. Indexes start from 1, in the order the files are embedded.
+
+use crate::steg::file_tree::FileMeta;
+use crate::steg::{get_mime_type, Steganography, SteganographyError};
+
+use log::info;
+use std::path::Path;
+
+// Struct to hold the details of an embedded file,
+// for listing without extracting it.
+#[derive(Clone, Debug)]
+pub struct ListedFile {
+    pub file_idx: u16,
+    pub file_name: String,
+    pub file_len: u64,
+    pub file_type: String,
+    pub checksum: String,
+    pub meta: Option<FileMeta>,
+}
+
+// Method to reset the files seen, and the files listed,
+// before reading the files from an image.
+impl Steganography {
+    pub fn reset_listing(&mut self) {
+        self.files_seen = 0;
+        self.listed_files = Vec::new();
+    }
+}
+
+// Method to check if the next file read from the image is one of
+// the files selected for extraction. All files are selected if
+// none have been chosen.
+impl Steganography {
+    pub fn select_file(&mut self, file_name:&str) -> bool {
+        self.files_seen += 1;
+        let file_idx = self.files_seen.to_string();
+        self.extract_selection.is_empty()
+            || self.extract_selection.iter().any(|selected| selected == file_name || *selected == file_idx)
+    }
+}
+
+// Method to add a file that has been read to the list of files.
+impl Steganography {
+    pub fn add_listed_file(&mut self, file_name:&str, file_len:u64, digest:&[u8], meta:Option<FileMeta>) {
+        let file_extension = Path::new(file_name)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("");
+        self.listed_files.push(ListedFile {
+            file_idx: self.files_seen,
+            file_name: file_name.to_string(),
+            file_len,
+            file_type: get_mime_type(file_extension).to_string(),
+            checksum: digest.iter().map(|b| format!("{:02x}", b)).collect(),
+            meta,
+        });
+    }
+}
+
+// Method to list the files embedded in the loaded image,
+// without writing anything to disk.
+// Password string required, empty string if no password required.
+impl Steganography {
+    pub fn list_manifest(&mut self, pw:String) -> Result<Vec<ListedFile>, SteganographyError> {
+        info!("Listing embedded files.");
+        self.manifest_only = true;
+        self.extract_selection = Vec::new();
+        let result = self.extract_data(pw);
+        self.manifest_only = false;
+        result?;
+        Ok(std::mem::take(&mut self.listed_files))
+    }
+}
+
+// Method to extract only the chosen files from the loaded image.
+// Files are chosen by file name or index, with all files
+// extracted if none are chosen.
+impl Steganography {
+    pub fn extract_selected(&mut self, pw:String, selection:Vec<String>) -> Result<(), SteganographyError> {
+        info!("Extracting selected files: {:?}", selection);
+        self.extract_selection = selection;
+        let result = self.extract_data(pw);
+        self.extract_selection = Vec::new();
+        result
+    }
+}
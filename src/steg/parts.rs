@@ -0,0 +1,269 @@
+// Split set methods.
+//
+// A set of files can be split over several carrier images when it is too
+// large for one. The files are packed into a container (see the container
+// module), and the container is cut into parts in sequence, filling each
+// carrier in turn. Each carrier is a normal pic coded image with a header,
+// followed by a single part section instead of file sections:
+//
+// Part marker : specific, but arbitrary number of bytes.
+// Set ID : 16 hex digits, the same for every part of the set.
+// Part index : (num_files_chars) digit integer, leading zeros, from 1.
+// Part count : (num_files_chars) digit integer, leading zeros.
+// Part length in bytes : (file_len_chars) digit integer, leading zeros.
+// Part contents : part bytes in part length bytes.
+
+use crate::steg::container;
+use crate::steg::{Steganography, SteganographyError};
+
+use log::{info, warn};
+use std::collections::BTreeMap;
+use std::io;
+use std::time::Instant;
+
+// Marker written at the start of a part section.
+const PART_CODE : &str = "PKPART";
+
+// Number of hex digits in a set ID.
+pub const SET_ID_CHARS : usize = 16;
+
+// Struct to hold the details of the part of a split set
+// embedded in an image.
+#[derive(Clone, Debug)]
+pub struct PartInfo {
+    pub set_id: String,
+    pub part_idx: u16,
+    pub part_count: u16,
+    pub part_len: u64,
+}
+
+// Helper function to get a random ID for a new set of images.
+pub fn new_set_id() -> String {
+    container::random_bytes(SET_ID_CHARS / 2)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+// Method to get the number of bytes used by the part details
+// at the start of a part section.
+impl Steganography {
+    pub fn part_overhead(&self) -> u64 {
+        (PART_CODE.len() + SET_ID_CHARS) as u64
+            + self.settings.num_files_chars as u64 * 2
+            + self.settings.file_len_chars as u64
+    }
+}
+
+// Method to embed files split over a number of carrier images.
+// Carriers are filled in order, so not all carriers are used if the
+// files fit in fewer. Each carrier used is saved to the matching output file.
+// Returns the number of carriers used.
+impl Steganography {
+    pub fn embed_split(&mut self, carriers:&[String], out_files:&[String], pw:bool, pw_str:&str, files:&[&str]) -> io::Result<usize> {
+        // Initialise timer for function.
+        let embed_start = Instant::now();
+
+        if carriers.is_empty() || carriers.len() != out_files.len() {
+            return Err(io::Error::other("An output file is needed for each carrier image"));
+        }
+        let num_carriers = carriers.len().min(10_usize.pow(self.settings.num_files_chars as u32) - 1);
+
+        // Pack all the files into a single container to be split.
+        let packed = container::pack_files(&self.settings, files)?;
+        info!("Total data to split: {} bytes", packed.len());
+
+        // Work out how much of the container goes in each carrier.
+        let mut part_lens = Vec::new();
+        let mut remaining = packed.len() as u64;
+        for carrier in &carriers[..num_carriers] {
+            if remaining == 0 {
+                break;
+            }
+            self.load_new_file(carrier.clone());
+            if !self.img_to_proc {
+                return Err(io::Error::other(format!("Unable to load carrier image: {}", carrier)));
+            }
+            let part_len = remaining.min(self.embed_capacity.saturating_sub(self.part_overhead()));
+            part_lens.push(part_len);
+            remaining -= part_len;
+        }
+        if remaining > 0 {
+            warn!("Exceeded carrier embedding capacity by: {}", remaining);
+            return Err(io::Error::other(format!("Files exceed capacity of carrier images by {} bytes", remaining)));
+        }
+
+        // Embed each part in its carrier, and save it.
+        let set_id = new_set_id();
+        let part_count = part_lens.len() as u16;
+        let mut offset:usize = 0;
+        for (idx, part_len) in part_lens.iter().enumerate() {
+            info!("Embedding part {} of {} in: {}", idx + 1, part_count, carriers[idx]);
+            self.load_new_file(carriers[idx].clone());
+            self.embed_preamble();
+            self.embed_password(pw, pw_str);
+            self.embed_num_of_files(1);
+            self.embed_header_copies();
+
+            let part_info = PartInfo {
+                set_id: set_id.clone(),
+                part_idx: idx as u16 + 1,
+                part_count,
+                part_len: *part_len,
+            };
+            let part_end = offset + *part_len as usize;
+            self.embed_part(&part_info, &packed[offset..part_end]);
+            offset = part_end;
+
+            if self.noise_fill {
+                self.embed_noise();
+            }
+            self.save_image(out_files[idx].clone());
+        }
+
+        // Determine delta time for function.
+        self.embed_duration = embed_start.elapsed();
+        info!("Time to embed split file(s): {:?}", self.embed_duration);
+        Ok(part_lens.len())
+    }
+}
+
+// Method to embed a part section.
+impl Steganography {
+    pub fn embed_part(&mut self, part_info:&PartInfo, data:&[u8]) {
+        let num_width = self.settings.num_files_chars as usize;
+        let len_width = self.settings.file_len_chars as usize;
+        let part_details = format!(
+            "{}{}{:0>num_width$}{:0>num_width$}{:0>len_width$}",
+            PART_CODE, part_info.set_id, part_info.part_idx, part_info.part_count, part_info.part_len,
+        );
+        self.write_chunks(part_details.as_bytes());
+        self.write_chunks(data);
+    }
+}
+
+// Method to check if the loaded image holds a part of a split set.
+// If so the part details are read and kept.
+impl Steganography {
+    pub fn check_for_part(&mut self) {
+        self.pic_part = None;
+        if self.embed_capacity < self.part_overhead() {
+            return;
+        }
+
+        // Part details are at the very start of the file data.
+        self.seek_payload(0);
+        self.read_data_from_image(PART_CODE.len() as u32);
+        if self.code_bytes != PART_CODE.as_bytes() {
+            return;
+        }
+        self.read_data_from_image(SET_ID_CHARS as u32);
+        if !self.code_bytes.iter().all(|b| b.is_ascii_hexdigit()) {
+            return;
+        }
+        let set_id = String::from_utf8(self.code_bytes.clone()).unwrap_or_default();
+        let part_idx = self.read_number(self.settings.num_files_chars as u32);
+        let part_count = self.read_number(self.settings.num_files_chars as u32);
+        let part_len = self.read_number(self.settings.file_len_chars as u32);
+        let (Some(part_idx), Some(part_count), Some(part_len)) = (part_idx, part_count, part_len) else {
+            return;
+        };
+
+        // Part details that don't fit the image can't be from a real part.
+        if part_idx == 0 || part_idx > part_count || part_len > self.embed_capacity - self.part_overhead() {
+            warn!("Invalid part details, image is not treated as a part.");
+            return;
+        }
+        info!("Image holds part {} of {} of set: {}", part_idx, part_count, set_id);
+        self.pic_part = Some(PartInfo { set_id, part_idx, part_count, part_len });
+    }
+}
+
+// Method to extract files split over a number of images.
+// Images can be in any order. If any parts of the set are
+// missing then nothing is extracted, and the missing parts are reported.
+impl Steganography {
+    pub fn extract_parts(&mut self, images:&[String], pw:String) -> Result<(), SteganographyError> {
+        self.extract_part_set(images.len(), |steg, idx| steg.load_new_file(images[idx].clone()), pw)
+    }
+}
+
+// Method to extract files from the loaded image, when it holds the
+// only part of a split set. The image isn't loaded again, so
+// this works for images only held in memory.
+impl Steganography {
+    pub fn extract_loaded_part(&mut self, pw:String) -> Result<(), SteganographyError> {
+        self.extract_part_set(1, |_, _| (), pw)
+    }
+}
+
+// Method to extract files from a number of images, each loaded
+// in turn by the load function given.
+impl Steganography {
+    fn extract_part_set(&mut self, num_images:usize, mut load:impl FnMut(&mut Self, usize), pw:String) -> Result<(), SteganographyError> {
+        // Initialise timer for function.
+        let extract_start = Instant::now();
+        self.embedded_files = Vec::new();
+        self.reset_listing();
+
+        // Read the part from each of the images.
+        let mut parts: BTreeMap<u16, Vec<u8>> = BTreeMap::new();
+        let mut set: Option<PartInfo> = None;
+        for idx in 0..num_images {
+            load(self, idx);
+            let part_info = match self.pic_part.clone() {
+                Some(part_info) => part_info,
+                None => {
+                    warn!("Image is not part of a split set: {}", self.image_file);
+                    continue;
+                }
+            };
+
+            // All images must be from the same set.
+            match &set {
+                Some(set_info) if set_info.set_id != part_info.set_id => {
+                    return Err(SteganographyError::MixedSets);
+                }
+                None => set = Some(part_info.clone()),
+                _ => {}
+            }
+
+            // Check the password for each part.
+            if self.pic_has_pw {
+                self.check_valid_password(pw.clone());
+                if !self.user_permit {
+                    self.extract_duration = extract_start.elapsed();
+                    return Err(SteganographyError::IncorrectPassword);
+                }
+            }
+
+            self.seek_payload(self.part_overhead());
+            let data = self.read_chunks(part_info.part_len);
+            parts.insert(part_info.part_idx, data);
+        }
+
+        // Check we have every part of the set.
+        let set_info = set.ok_or(SteganographyError::NoParts)?;
+        let missing: Vec<u16> = (1..=set_info.part_count).filter(|idx| !parts.contains_key(idx)).collect();
+        if !missing.is_empty() {
+            self.extract_duration = extract_start.elapsed();
+            return Err(SteganographyError::MissingParts { missing, count: set_info.part_count });
+        }
+
+        // Join the parts back together and save the files.
+        let packed: Vec<u8> = parts.into_values().flatten().collect();
+        let files = container::unpack_files(&self.settings, &packed).unwrap_or_default();
+        info!("Number of files in split set: {}", files.len());
+        for file in files {
+            if let Err(err) = self.save_extracted_file(&file) {
+                warn!("Failed to extract file: {}", err);
+            }
+        }
+        self.analyse_extracted_files();
+
+        // Determine delta time for function.
+        self.extract_duration = extract_start.elapsed();
+        info!("Time for split file(s) extraction: {:?}", self.extract_duration);
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+// Progress reporting.
+//
+// Embedding and extraction record how many bytes of file data have been
+// processed, out of how many, and which file is being processed, as they
+// go through their chunk loops. The progress is shared, so it can be read
+// from another thread while a job is running.
+//
+// When embedding, the total is known up front. When extracting, the size
+// of each file is only known once its file section has been read, so the
+// total grows as each file is found.
+//
+// The progress is also how a running embedding or extraction is
+// cancelled. Cancelling sets a flag that is checked between chunks,
+// so the work stops at the next chunk boundary.
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+
+// Struct to hold the progress of an embedding or extraction.
+#[derive(Debug, Default)]
+pub struct Progress {
+    bytes_done: AtomicU64,
+    bytes_total: AtomicU64,
+    current_file: Mutex<String>,
+    cancelled: AtomicBool,
+}
+
+// Struct to hold a snapshot of the progress, for reporting.
+#[derive(Clone, Debug, Serialize)]
+pub struct ProgressReport {
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    pub current_file: String,
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Set the total number of bytes to be processed.
+    pub fn set_total(&self, bytes:u64) {
+        self.bytes_total.store(bytes, Ordering::Relaxed);
+    }
+
+    // Start processing a file, adding its length to the total if required.
+    pub fn start_file(&self, file_name:&str, add_to_total:Option<u64>) {
+        *self.current_file.lock().unwrap() = file_name.to_string();
+        if let Some(bytes) = add_to_total {
+            self.bytes_total.fetch_add(bytes, Ordering::Relaxed);
+        }
+    }
+
+    // Record that a number of bytes have been processed.
+    pub fn advance(&self, bytes:u64) {
+        self.bytes_done.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    // Ask for the embedding or extraction to stop at the next chunk.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    // Check if the embedding or extraction has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    // Take a snapshot of the progress.
+    pub fn report(&self) -> ProgressReport {
+        ProgressReport {
+            bytes_done: self.bytes_done.load(Ordering::Relaxed),
+            bytes_total: self.bytes_total.load(Ordering::Relaxed),
+            current_file: self.current_file.lock().unwrap().clone(),
+        }
+    }
+}
@@ -0,0 +1,193 @@
+// Public key recipient methods.
+//
+// Files can be embedded for one or more recipients, identified by their
+// X25519 public keys, so no password needs to be shared. Only holders of
+// a matching private key can extract the files.
+//
+// In recipient mode the header password flag is 'K', the password hash is
+// replaced by a one-off X25519 public key, and the number of files is the
+// number of recipients. The files are packed into a container and
+// encrypted with a random content key. For each recipient the content key
+// is encrypted with a key agreed between the one-off key and the
+// recipient's public key. The file data area is then:
+//
+// For each recipient : content key encrypted for the recipient.
+// Container length in bytes : (file_len_chars) digit integer, leading zeros.
+// Container : encrypted container in container length bytes.
+//
+// Keys are stored in files as 64 hex digits, private keys in a .key file
+// and public keys in a .pub file.
+
+use crate::steg::container::{self, TAG_LEN};
+use crate::steg::{Steganography, SteganographyError};
+
+use log::{info, warn};
+use ring::hkdf;
+use std::io;
+use std::time::Instant;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+// Length of an X25519 key, and of the content key.
+pub const KEY_LEN : usize = 32;
+
+// Length of the content key once encrypted for a recipient.
+const WRAPPED_KEY_LEN : usize = KEY_LEN + TAG_LEN;
+
+// Helper function to generate a new X25519 key pair.
+// Returns the private key and the public key.
+pub fn generate_keypair() -> ([u8; KEY_LEN], [u8; KEY_LEN]) {
+    let private_key: [u8; KEY_LEN] = container::random_bytes(KEY_LEN).try_into().unwrap();
+    let public_key = PublicKey::from(&StaticSecret::from(private_key)).to_bytes();
+    (private_key, public_key)
+}
+
+// Helper function to get the public key for a private key.
+pub fn public_key(private_key:&[u8; KEY_LEN]) -> [u8; KEY_LEN] {
+    PublicKey::from(&StaticSecret::from(*private_key)).to_bytes()
+}
+
+// Helper function to format a key as hex digits for a key file.
+pub fn key_to_hex(key:&[u8]) -> String {
+    key.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Helper function to parse a key from the hex digits in a key file.
+// Returns None if the text is not a valid key.
+pub fn parse_key(text:&str) -> Option<[u8; KEY_LEN]> {
+    let text = text.trim();
+    if text.len() != KEY_LEN * 2 || !text.is_ascii() {
+        return None;
+    }
+    let mut key = [0u8; KEY_LEN];
+    for (idx, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[idx * 2..idx * 2 + 2], 16).ok()?;
+    }
+    Some(key)
+}
+
+// Helper function to derive the key used to encrypt the content key
+// for one recipient, from the X25519 shared secret.
+fn recipient_key(shared:&[u8], one_off_public:&[u8], recipient_public:&[u8]) -> [u8; KEY_LEN] {
+    let mut key = [0u8; KEY_LEN];
+    hkdf::Salt::new(hkdf::HKDF_SHA256, one_off_public)
+        .extract(shared)
+        .expand(&[recipient_public], hkdf::HKDF_SHA256)
+        .and_then(|okm| okm.fill(&mut key))
+        .expect("Failed to derive recipient key");
+    key
+}
+
+// Method to embed files into a loaded image for a set of recipients.
+// Each recipient can extract the files with their private key.
+impl Steganography {
+    pub fn embed_recipients(&mut self, recipients:&[[u8; KEY_LEN]], files:&[&str]) -> io::Result<()> {
+        // Initialise timer for function.
+        let embed_start = Instant::now();
+
+        if !self.img_to_proc {
+            info!("No image to embed files into.");
+            return Err(io::Error::other("No image loaded"));
+        }
+        let max_recipients = 10_usize.pow(self.settings.num_files_chars as u32) - 1;
+        if recipients.is_empty() || recipients.len() > max_recipients {
+            return Err(io::Error::other(format!("Number of recipients must be between 1 and {}", max_recipients)));
+        }
+        self.set_layout(false);
+
+        // Check that the recipients and files will fit in the image.
+        let bytes_to_embed = (recipients.len() * WRAPPED_KEY_LEN) as u64
+            + self.settings.file_len_chars as u64
+            + container::packed_len(&self.settings, files)?
+            + TAG_LEN as u64;
+        if bytes_to_embed > self.embed_capacity {
+            warn!("Exceeded image emdedding: {}", self.embed_capacity);
+            return Err(io::Error::other(format!("Files exceed capacity of {} bytes", self.embed_capacity)));
+        }
+        info!("Total data to embed: {} bytes", bytes_to_embed);
+
+        // Encrypt the content key for each recipient.
+        let content_key: [u8; KEY_LEN] = container::random_bytes(KEY_LEN).try_into().unwrap();
+        let one_off_private = StaticSecret::from(<[u8; KEY_LEN]>::try_from(container::random_bytes(KEY_LEN)).unwrap());
+        let one_off_public = PublicKey::from(&one_off_private).to_bytes();
+        let mut wrapped_keys = Vec::with_capacity(recipients.len() * WRAPPED_KEY_LEN);
+        for recipient in recipients {
+            let shared = one_off_private.diffie_hellman(&PublicKey::from(*recipient));
+            let key = recipient_key(shared.as_bytes(), &one_off_public, recipient);
+            wrapped_keys.extend(container::seal(&key, content_key.to_vec()));
+        }
+
+        // Write the header with the recipient flag and one-off public key.
+        self.embed_preamble();
+        info!("Embedding recipient public key.");
+        self.write_chunks(&[b"K", one_off_public.as_slice()].concat());
+        self.embed_num_of_files(recipients.len() as u16);
+        self.embed_header_copies();
+
+        // Write the encrypted content keys, then the encrypted container.
+        info!("Embedding files for {} recipient(s).", recipients.len());
+        let sealed = container::seal(&content_key, container::pack_files(&self.settings, files)?);
+        let sealed_len = format!("{:0>width$}", sealed.len(), width=self.settings.file_len_chars as usize);
+        self.write_chunks(&wrapped_keys);
+        self.write_chunks(sealed_len.as_bytes());
+        self.write_chunks(&sealed);
+
+        if self.noise_fill {
+            self.embed_noise();
+        }
+
+        // Determine delta time for function.
+        self.embed_duration = embed_start.elapsed();
+        info!("Time to embed recipient file(s): {:?}", self.embed_duration);
+        Ok(())
+    }
+}
+
+// Method to extract files embedded for recipients, using a private key.
+// Private keys that don't match any recipient are reported as incorrect.
+impl Steganography {
+    pub fn extract_recipient(&mut self, private_key:&[u8; KEY_LEN]) -> Result<(), SteganographyError> {
+        // One-off public key is stored in place of the password hash.
+        let key_start = super::PROG_CODE.len() + self.settings.pw_protected_chars as usize;
+        let one_off_public: [u8; KEY_LEN] = self.header_bytes
+            .get(key_start..key_start + KEY_LEN)
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or(SteganographyError::IncorrectKey)?;
+        let num_recipients = self.header_num_files().unwrap_or(0) as u64;
+
+        // Try to decrypt each of the content keys with our key.
+        let own_private = StaticSecret::from(*private_key);
+        let own_public = PublicKey::from(&own_private).to_bytes();
+        let shared = own_private.diffie_hellman(&PublicKey::from(one_off_public));
+        let key = recipient_key(shared.as_bytes(), &one_off_public, &own_public);
+        self.seek_payload(0);
+        let wrapped_keys = self.read_chunks(num_recipients * WRAPPED_KEY_LEN as u64);
+        let content_key = wrapped_keys
+            .chunks(WRAPPED_KEY_LEN)
+            .find_map(|wrapped| container::open(&key, wrapped.to_vec()))
+            .and_then(|content_key| <[u8; KEY_LEN]>::try_from(content_key).ok());
+        let content_key = match content_key {
+            Some(content_key) => content_key,
+            None => {
+                info!("Private key does not match a recipient.");
+                return Err(SteganographyError::IncorrectKey);
+            }
+        };
+        self.user_permit = true;
+
+        // Read and decrypt the container.
+        let sealed_len:u64 = self.read_number(self.settings.file_len_chars as u32).unwrap_or(0);
+        let sealed = self.read_chunks(sealed_len.min(self.embed_capacity));
+        let packed = container::open(&content_key, sealed).unwrap_or_default();
+
+        // Save each of the files.
+        let files = container::unpack_files(&self.settings, &packed).unwrap_or_default();
+        info!("Number of files for recipient: {}", files.len());
+        for file in files {
+            if let Err(err) = self.save_extracted_file(&file) {
+                warn!("Failed to extract file: {}", err);
+            }
+        }
+        self.analyse_extracted_files();
+        Ok(())
+    }
+}
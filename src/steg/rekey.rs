@@ -0,0 +1,116 @@
+// Rekey methods.
+//
+// The password of an already pic coded image can be changed in place,
+// without extracting and re-embedding the files. For images with a
+// password hash in the header only the header, and its copies, are
+// rewritten, so protection can also be added or removed. For key slot
+// images the slot opened by the old password is rewritten.
+//
+// Volumes and recipient images can't be rekeyed, as the password or key
+// selects where the files are and how they are encrypted. Nor can images
+// with the original layout, as their header is shorter than the current one.
+
+use crate::steg::{Steganography, SteganographyError, PROG_CODE};
+
+use log::info;
+use ring::digest;
+
+// Method to change the password of the loaded image.
+// An empty new password removes password protection.
+// The image needs saving afterwards.
+impl Steganography {
+    pub fn rekey(&mut self, old_pw:&str, new_pw:&str) -> Result<(), SteganographyError> {
+        if !self.pic_coded || self.pic_volumes || self.pic_recipients || self.pic_legacy {
+            info!("Image can't be rekeyed.");
+            return Err(SteganographyError::RekeyNotSupported);
+        }
+        if self.pic_key_slots {
+            self.rekey_key_slot(old_pw, new_pw)?;
+            return Ok(());
+        }
+
+        // Check the old password first, if there is one.
+        if self.pic_has_pw {
+            self.check_valid_password(old_pw.to_string());
+            if !self.user_permit {
+                return Err(SteganographyError::IncorrectPassword);
+            }
+        }
+
+        // Replace the password flag and hash in the header.
+        // The header is taken from what was read on loading, so a
+        // damaged header recovered from the copies is also repaired.
+        let flag_pos = PROG_CODE.len();
+        let hash_start = flag_pos + self.settings.pw_protected_chars as usize;
+        let hash_end = hash_start + self.settings.pw_chars as usize;
+        let mut header = self.header_bytes.clone();
+        if new_pw.is_empty() {
+            info!("Removing password protection.");
+            header[flag_pos] = b'N';
+            header[hash_start..hash_end].fill(0);
+        }
+        else {
+            info!("Changing password.");
+            header[flag_pos] = b'Y';
+            let hash = digest::digest(&digest::SHA256, new_pw.as_bytes());
+            header[hash_start..hash_end].copy_from_slice(hash.as_ref());
+        }
+
+        // Write the header, and the copies of the header.
+        self.write_header(&header);
+        self.pic_has_pw = !new_pw.is_empty();
+        self.user_permit = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::steg::bit_stream::{self, BitCursor};
+    use image::{DynamicImage, ImageFormat, RgbImage};
+    use std::io::Cursor;
+
+    // Helper function to get a PNG with one file embedded in the original
+    // layout, with or without a password.
+    fn legacy_png(steg:&Steganography, password:Option<&str>) -> Vec<u8> {
+        let settings = &steg.settings;
+        let mut data = PROG_CODE.as_bytes().to_vec();
+        match password {
+            Some(password) => {
+                data.push(b'Y');
+                data.extend_from_slice(digest::digest(&digest::SHA256, password.as_bytes()).as_ref());
+            }
+            None => data.push(b'N'),
+        }
+        data.extend(format!("{:0>1$}", 1, settings.num_files_chars as usize).bytes());
+        data.extend(format!("{:0>1$}", 5, settings.len_filename_chars as usize).bytes());
+        data.extend(b"a.txt");
+        data.extend(format!("{:0>1$}", 5, settings.file_len_chars as usize).bytes());
+        data.extend(b"hello");
+
+        let mut image = DynamicImage::ImageRgb8(RgbImage::from_pixel(64, 64, image::Rgb([128, 128, 128])));
+        let (samples, channels) = bit_stream::image_samples_mut(&mut image).unwrap();
+        let mut cursor = BitCursor { pixel: 0, plane: 0, bit: 0, pixels: 64 * 64, channels };
+        bit_stream::write_bits(samples, &mut cursor, &data);
+        let mut png = Vec::new();
+        image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png).unwrap();
+        png
+    }
+
+    // Settings are read with tokio, so the test needs a runtime.
+    #[actix_web::test]
+    async fn rekey_refuses_legacy_images() {
+        for password in [None, Some("old")] {
+            let mut steg = Steganography::init();
+            let png = legacy_png(&steg, password);
+            steg.load_image_bytes("legacy.png".to_string(), &png);
+            assert!(steg.pic_coded && steg.pic_legacy, "password {:?}", password);
+
+            let header = steg.header_bytes.clone();
+            let result = steg.rekey(password.unwrap_or(""), "new");
+            assert!(matches!(result, Err(SteganographyError::RekeyNotSupported)), "password {:?}", password);
+            assert_eq!(steg.header_bytes, header);
+        }
+    }
+}
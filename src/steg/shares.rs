@@ -0,0 +1,297 @@
+// Threshold share methods.
+//
+// A set of files can be shared over n carrier images so that any k of
+// them are needed to get the files back. The files are packed into a
+// container and encrypted with a random key, and the key and encrypted
+// container together are split into n shares with Shamir secret sharing.
+// Fewer than k shares give no information about the files.
+// Each carrier is a normal pic coded image with a header, followed by
+// a single share section instead of file sections:
+//
+// Share marker : specific, but arbitrary number of bytes.
+// Set ID : 16 hex digits, the same for every share of the set.
+// Share index : (num_files_chars) digit integer, leading zeros, from 1.
+// Threshold : (num_files_chars) digit integer, leading zeros.
+// Share count : (num_files_chars) digit integer, leading zeros.
+// Share length in bytes : (file_len_chars) digit integer, leading zeros.
+// Share contents : share bytes in share length bytes.
+
+use crate::steg::container;
+use crate::steg::parts::{new_set_id, SET_ID_CHARS};
+use crate::steg::{Steganography, SteganographyError};
+
+use log::{info, warn};
+use sharks::{Share, Sharks};
+use std::collections::BTreeMap;
+use std::io;
+use std::time::Instant;
+
+// Marker written at the start of a share section.
+const SHARE_CODE : &str = "PKSHARE";
+
+// Length of the random key used to encrypt the shared container.
+const SHARE_KEY_LEN : usize = 32;
+
+// Shamir sharing uses the share index as a byte,
+// so there can be no more shares than this.
+const MAX_SHARES : usize = 255;
+
+// Struct to hold the details of the share of a threshold set
+// embedded in an image.
+#[derive(Clone, Debug)]
+pub struct ShareInfo {
+    pub set_id: String,
+    pub share_idx: u16,
+    pub threshold: u16,
+    pub share_count: u16,
+    pub share_len: u64,
+}
+
+// Method to get the number of bytes used by the share details
+// at the start of a share section.
+impl Steganography {
+    pub fn share_overhead(&self) -> u64 {
+        (SHARE_CODE.len() + SET_ID_CHARS) as u64
+            + self.settings.num_files_chars as u64 * 3
+            + self.settings.file_len_chars as u64
+    }
+}
+
+// Method to embed files shared over a number of carrier images, so
+// that any (threshold) of them can be used to extract the files.
+// Every carrier is used, and saved to the matching output file.
+impl Steganography {
+    pub fn embed_shares(&mut self, carriers:&[String], out_files:&[String], threshold:u16, pw:bool, pw_str:&str, files:&[&str]) -> io::Result<()> {
+        // Initialise timer for function.
+        let embed_start = Instant::now();
+
+        if carriers.is_empty() || carriers.len() != out_files.len() {
+            return Err(io::Error::other("An output file is needed for each carrier image"));
+        }
+        let max_shares = MAX_SHARES.min(10_usize.pow(self.settings.num_files_chars as u32) - 1);
+        if carriers.len() > max_shares {
+            return Err(io::Error::other(format!("No more than {} carrier images can be used", max_shares)));
+        }
+        if threshold == 0 || threshold as usize > carriers.len() {
+            return Err(io::Error::other(format!("Threshold must be between 1 and {}", carriers.len())));
+        }
+
+        // Encrypt the container with a random key, and share
+        // the key and the encrypted container together.
+        let key: [u8; SHARE_KEY_LEN] = container::random_bytes(SHARE_KEY_LEN).try_into().unwrap();
+        let packed = container::pack_files(&self.settings, files)?;
+        let secret = [key.as_slice(), &container::seal(&key, packed)].concat();
+        let shares: Vec<Share> = Sharks(threshold as u8).dealer(&secret).take(carriers.len()).collect();
+        info!("Sharing {} bytes over {} images, {} needed.", secret.len(), carriers.len(), threshold);
+
+        // Every carrier must be able to hold a whole share.
+        for carrier in carriers {
+            self.load_new_file(carrier.clone());
+            if !self.img_to_proc {
+                return Err(io::Error::other(format!("Unable to load carrier image: {}", carrier)));
+            }
+            let share_capacity = self.embed_capacity.saturating_sub(self.share_overhead());
+            if secret.len() as u64 > share_capacity {
+                warn!("Exceeded carrier embedding capacity: {}", share_capacity);
+                return Err(io::Error::other(format!("Files exceed capacity of carrier image {} by {} bytes", carrier, secret.len() as u64 - share_capacity)));
+            }
+        }
+
+        // Embed each share in its carrier, and save it.
+        let set_id = new_set_id();
+        let share_count = carriers.len() as u16;
+        for (idx, share) in shares.iter().enumerate() {
+            // Share bytes are the x coordinate followed by the y values.
+            // The x coordinate is stored as the share index.
+            let share_bytes = Vec::from(share);
+            info!("Embedding share {} of {} in: {}", share_bytes[0], share_count, carriers[idx]);
+            self.load_new_file(carriers[idx].clone());
+            self.embed_preamble();
+            self.embed_password(pw, pw_str);
+            self.embed_num_of_files(1);
+            self.embed_header_copies();
+
+            let share_info = ShareInfo {
+                set_id: set_id.clone(),
+                share_idx: share_bytes[0] as u16,
+                threshold,
+                share_count,
+                share_len: share_bytes.len() as u64 - 1,
+            };
+            self.embed_share(&share_info, &share_bytes[1..]);
+
+            if self.noise_fill {
+                self.embed_noise();
+            }
+            self.save_image(out_files[idx].clone());
+        }
+
+        // Determine delta time for function.
+        self.embed_duration = embed_start.elapsed();
+        info!("Time to embed shared file(s): {:?}", self.embed_duration);
+        Ok(())
+    }
+}
+
+// Method to embed a share section.
+impl Steganography {
+    pub fn embed_share(&mut self, share_info:&ShareInfo, data:&[u8]) {
+        let num_width = self.settings.num_files_chars as usize;
+        let len_width = self.settings.file_len_chars as usize;
+        let share_details = format!(
+            "{}{}{:0>num_width$}{:0>num_width$}{:0>num_width$}{:0>len_width$}",
+            SHARE_CODE, share_info.set_id, share_info.share_idx, share_info.threshold,
+            share_info.share_count, share_info.share_len,
+        );
+        self.write_chunks(share_details.as_bytes());
+        self.write_chunks(data);
+    }
+}
+
+// Method to check if the loaded image holds a share of a threshold set.
+// If so the share details are read and kept.
+impl Steganography {
+    pub fn check_for_share(&mut self) {
+        self.pic_share = None;
+        if self.embed_capacity < self.share_overhead() {
+            return;
+        }
+
+        // Share details are at the very start of the file data.
+        self.seek_payload(0);
+        self.read_data_from_image(SHARE_CODE.len() as u32);
+        if self.code_bytes != SHARE_CODE.as_bytes() {
+            return;
+        }
+        self.read_data_from_image(SET_ID_CHARS as u32);
+        if !self.code_bytes.iter().all(|b| b.is_ascii_hexdigit()) {
+            return;
+        }
+        let set_id = String::from_utf8(self.code_bytes.clone()).unwrap_or_default();
+        let share_idx = self.read_number(self.settings.num_files_chars as u32);
+        let threshold = self.read_number(self.settings.num_files_chars as u32);
+        let share_count = self.read_number(self.settings.num_files_chars as u32);
+        let share_len = self.read_number(self.settings.file_len_chars as u32);
+        let (Some(share_idx), Some(threshold), Some(share_count), Some(share_len)) = (share_idx, threshold, share_count, share_len) else {
+            return;
+        };
+
+        // Share details that don't fit the image, or that couldn't
+        // have been dealt, can't be from a real share.
+        if threshold == 0 || threshold > share_count || share_count as usize > MAX_SHARES
+            || share_idx == 0 || share_idx > share_count
+            || share_len > self.embed_capacity - self.share_overhead() {
+            warn!("Invalid share details, image is not treated as a share.");
+            return;
+        }
+        info!("Image holds share {} of {} of set: {}, {} needed", share_idx, share_count, set_id, threshold);
+        self.pic_share = Some(ShareInfo { set_id, share_idx, threshold, share_count, share_len });
+    }
+}
+
+// Method to extract files shared over a number of images.
+// Images can be in any order. If there are fewer shares than the
+// threshold then nothing is extracted, and the number of shares
+// still needed is reported.
+impl Steganography {
+    pub fn extract_shares(&mut self, images:&[String], pw:String) -> Result<(), SteganographyError> {
+        self.extract_share_set(images.len(), |steg, idx| steg.load_new_file(images[idx].clone()), pw)
+    }
+}
+
+// Method to extract files from the loaded image, when one share of
+// a threshold set is enough on its own. The image isn't loaded again, so
+// this works for images only held in memory.
+impl Steganography {
+    pub fn extract_loaded_share(&mut self, pw:String) -> Result<(), SteganographyError> {
+        self.extract_share_set(1, |_, _| (), pw)
+    }
+}
+
+// Method to extract files from a number of images, each loaded
+// in turn by the load function given.
+impl Steganography {
+    fn extract_share_set(&mut self, num_images:usize, mut load:impl FnMut(&mut Self, usize), pw:String) -> Result<(), SteganographyError> {
+        // Initialise timer for function.
+        let extract_start = Instant::now();
+        self.embedded_files = Vec::new();
+        self.reset_listing();
+
+        // Read the share from each of the images.
+        let mut shares: BTreeMap<u16, Vec<u8>> = BTreeMap::new();
+        let mut set: Option<ShareInfo> = None;
+        for idx in 0..num_images {
+            load(self, idx);
+            let share_info = match self.pic_share.clone() {
+                Some(share_info) => share_info,
+                None => {
+                    warn!("Image is not a share of a threshold set: {}", self.image_file);
+                    continue;
+                }
+            };
+
+            // All images must be from the same set.
+            match &set {
+                Some(set_info) if set_info.set_id != share_info.set_id => {
+                    return Err(SteganographyError::MixedSets);
+                }
+                None => set = Some(share_info.clone()),
+                _ => {}
+            }
+
+            // Check the password for each share.
+            if self.pic_has_pw {
+                self.check_valid_password(pw.clone());
+                if !self.user_permit {
+                    self.extract_duration = extract_start.elapsed();
+                    return Err(SteganographyError::IncorrectPassword);
+                }
+            }
+
+            self.seek_payload(self.share_overhead());
+            let data = self.read_chunks(share_info.share_len);
+            shares.insert(share_info.share_idx, data);
+        }
+
+        // Check we have enough shares to recover the files.
+        let set_info = set.ok_or(SteganographyError::NoShares)?;
+        if shares.len() < set_info.threshold as usize {
+            self.extract_duration = extract_start.elapsed();
+            return Err(SteganographyError::NotEnoughShares { needed: set_info.threshold - shares.len() as u16 });
+        }
+
+        // Recover the key and encrypted container, and decrypt it.
+        let shares: Vec<Share> = shares
+            .iter()
+            .filter_map(|(idx, data)| Share::try_from([&[*idx as u8], data.as_slice()].concat().as_slice()).ok())
+            .collect();
+        let secret = Sharks(set_info.threshold as u8).recover(&shares).unwrap_or_default();
+        let packed = match secret.split_at_checked(SHARE_KEY_LEN) {
+            Some((key, sealed)) => container::open(key.try_into().unwrap(), sealed.to_vec()),
+            None => None,
+        };
+        let packed = match packed {
+            Some(packed) => packed,
+            None => {
+                self.extract_duration = extract_start.elapsed();
+                warn!("Shares do not recover a valid container.");
+                return Err(SteganographyError::InvalidShares);
+            }
+        };
+
+        // Save each of the files.
+        let files = container::unpack_files(&self.settings, &packed).unwrap_or_default();
+        info!("Number of files in threshold set: {}", files.len());
+        for file in files {
+            if let Err(err) = self.save_extracted_file(&file) {
+                warn!("Failed to extract file: {}", err);
+            }
+        }
+        self.analyse_extracted_files();
+
+        // Determine delta time for function.
+        self.extract_duration = extract_start.elapsed();
+        info!("Time for shared file(s) extraction: {:?}", self.extract_duration);
+        Ok(())
+    }
+}
@@ -0,0 +1,191 @@
+// Signature methods.
+//
+// Embedded files can optionally be signed with an Ed25519 key, so the
+// person extracting them knows who embedded them and that they haven't
+// been changed. The signature covers a manifest of the embedded files:
+//
+// Number of files : (num_files_chars) digit integer, leading zeros.
+// For each file the following applies:
+//
+// File name length : (len_filename_chars) digit integer, leading zeros.
+// File name : file name string in file name length bytes.
+// File length in bytes : (file_len_chars) digit integer, leading zeros.
+// File digest : 32 byte SHA-256 hash of the file contents.
+//
+// The signature is stored in a signature section straight after the
+// header, before the first file section:
+//
+// Signature marker : specific, but arbitrary number of bytes.
+// Signer public key : 32 byte Ed25519 public key.
+// Signature : 64 byte Ed25519 signature of the manifest.
+//
+// Signing keys are stored in files as hex digits, the private key as a
+// PKCS#8 document in a .sign.key file, and the public key in a .sign.pub file.
+// Public keys listed in trusted_keys in the settings are reported as trusted.
+
+use crate::settings::Settings;
+use crate::steg::file_tree::SourceFile;
+use crate::steg::Steganography;
+
+use log::{info, warn};
+use ring::digest;
+use ring::rand::SystemRandom;
+use ring::signature::{self, Ed25519KeyPair, KeyPair, UnparsedPublicKey};
+use std::io;
+
+// Marker written at the start of a signature section.
+const SIGN_CODE : &str = "PKSIGN";
+
+// Length of an Ed25519 public key and signature.
+const PUBLIC_KEY_LEN : usize = 32;
+const SIGNATURE_LEN : usize = 64;
+
+// Length of a signature section.
+pub const SIGNATURE_SECTION_LEN : u64 = (SIGN_CODE.len() + PUBLIC_KEY_LEN + SIGNATURE_LEN) as u64;
+
+// Struct to hold a file in the manifest.
+pub struct ManifestEntry {
+    pub file_name: String,
+    pub file_len: u64,
+    pub digest: Vec<u8>,
+}
+
+// Struct to hold the signature found on extraction,
+// and the result of verifying it.
+#[derive(Clone, Debug)]
+pub struct SignatureInfo {
+    pub public_key: Vec<u8>,
+    pub fingerprint: String,
+    pub verified: bool,
+    pub trusted: bool,
+}
+
+// Helper function to generate a new Ed25519 signing key.
+// Returns the PKCS#8 private key document and the public key.
+pub fn generate_signing_key() -> (Vec<u8>, Vec<u8>) {
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).expect("Failed to generate signing key");
+    let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).expect("Failed to parse signing key");
+    (pkcs8.as_ref().to_vec(), key_pair.public_key().as_ref().to_vec())
+}
+
+// Helper function to parse a signing key from hex digits.
+// Returns None if the text is not a valid Ed25519 PKCS#8 document.
+pub fn parse_signing_key(text:&str) -> Option<Vec<u8>> {
+    let pkcs8 = parse_hex(text)?;
+    Ed25519KeyPair::from_pkcs8(&pkcs8).ok()?;
+    Some(pkcs8)
+}
+
+// Helper function to parse bytes from hex digits.
+fn parse_hex(text:&str) -> Option<Vec<u8>> {
+    let text = text.trim();
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(&text[idx..idx + 2], 16).ok())
+        .collect()
+}
+
+// Helper function to get the fingerprint of a public key.
+// This is the first 16 bytes of the SHA-256 hash of the key,
+// as colon separated hex digits.
+pub fn fingerprint(public_key:&[u8]) -> String {
+    digest::digest(&digest::SHA256, public_key).as_ref()[..16]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<String>>()
+        .join(":")
+}
+
+// Helper function to check if a public key is in the trusted keys list.
+pub fn is_trusted(settings:&Settings, public_key:&[u8]) -> bool {
+    settings.trusted_keys
+        .iter()
+        .filter_map(|key| parse_hex(key))
+        .any(|key| key == public_key)
+}
+
+// Helper function to get the manifest entry for a file to be embedded.
+pub fn manifest_entry(source:&SourceFile) -> io::Result<ManifestEntry> {
+    let data = source.read()?;
+    Ok(ManifestEntry {
+        file_name: source.file_name.clone(),
+        file_len: data.len() as u64,
+        digest: digest::digest(&digest::SHA256, &data).as_ref().to_vec(),
+    })
+}
+
+// Helper function to build the manifest that is signed.
+pub fn manifest(settings:&Settings, entries:&[ManifestEntry]) -> Vec<u8> {
+    let mut manifest = format!("{:0>width$}", entries.len(), width=settings.num_files_chars as usize).into_bytes();
+    for entry in entries {
+        manifest.extend(format!("{:0>width$}", entry.file_name.len(), width=settings.len_filename_chars as usize).as_bytes());
+        manifest.extend(entry.file_name.as_bytes());
+        manifest.extend(format!("{:0>width$}", entry.file_len, width=settings.file_len_chars as usize).as_bytes());
+        manifest.extend(&entry.digest);
+    }
+    manifest
+}
+
+// Method to get the signature section for the files to be embedded.
+impl Steganography {
+    pub fn signature_section(&self, pkcs8:&[u8], sources:&[SourceFile]) -> io::Result<Vec<u8>> {
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8)
+            .map_err(|_| io::Error::other("Invalid signing key"))?;
+        let entries = sources
+            .iter()
+            .map(manifest_entry)
+            .collect::<io::Result<Vec<ManifestEntry>>>()?;
+        let signature = key_pair.sign(&manifest(&self.settings, &entries));
+
+        info!("Signing files by: {}", fingerprint(key_pair.public_key().as_ref()));
+        Ok([SIGN_CODE.as_bytes(), key_pair.public_key().as_ref(), signature.as_ref()].concat())
+    }
+}
+
+// Method to read the signature section, if there is one, at the
+// current position. Returns the public key and signature, and leaves
+// the position after the section. If there is no signature section
+// the position is left unchanged.
+impl Steganography {
+    pub fn read_signature(&mut self) -> Option<(Vec<u8>, Vec<u8>)> {
+        let section_start = self.payload_pos();
+        if section_start + SIGNATURE_SECTION_LEN > self.embed_capacity {
+            return None;
+        }
+        self.read_data_from_image(SIGN_CODE.len() as u32);
+        if self.code_bytes != SIGN_CODE.as_bytes() {
+            self.seek_payload(section_start);
+            return None;
+        }
+        let public_key = self.read_chunks(PUBLIC_KEY_LEN as u64);
+        let signature = self.read_chunks(SIGNATURE_LEN as u64);
+        Some((public_key, signature))
+    }
+}
+
+// Method to verify a signature against the files that were extracted,
+// and keep the result for reporting.
+impl Steganography {
+    pub fn verify_signature(&mut self, public_key:Vec<u8>, signature_bytes:&[u8], entries:&[ManifestEntry]) {
+        let manifest = manifest(&self.settings, entries);
+        let verified = UnparsedPublicKey::new(&signature::ED25519, &public_key)
+            .verify(&manifest, signature_bytes)
+            .is_ok();
+        let signature_info = SignatureInfo {
+            fingerprint: fingerprint(&public_key),
+            trusted: is_trusted(&self.settings, &public_key),
+            public_key,
+            verified,
+        };
+        if signature_info.verified {
+            info!("Signature verified, signed by: {} trusted: {}", signature_info.fingerprint, signature_info.trusted);
+        }
+        else {
+            warn!("Signature NOT verified, claims signed by: {}", signature_info.fingerprint);
+        }
+        self.signature = Some(signature_info);
+    }
+}
@@ -0,0 +1,215 @@
+// Deniable volume methods.
+//
+// In volume mode the header password flag is 'V', and the password hash
+// is replaced by a random salt. The file data area in the lowest
+// (volume_bit_planes) bit planes is split into (volume_slots) equal slots,
+// and each volume is an encrypted container in the slot selected by its
+// password.
+// Slots without a volume, and the padding of each volume, are filled
+// with random bytes, so without the password a volume can't be told
+// apart from an unused slot, and the number of volumes can't be known.
+//
+// Headerless images have no signature or header copies. The header area
+// at the start of the image holds only the random salt, so nothing in the
+// image shows it was made by this application. The encrypted container
+// is the only way to tell if the image holds a volume.
+
+use crate::steg::container::{self, ContainerKey, TAG_LEN};
+use crate::steg::{Steganography, SteganographyError};
+
+use log::{info, warn};
+use std::collections::HashSet;
+use std::io;
+use std::time::Instant;
+
+// Number of salts to try when looking for one where
+// all the volume passwords select different slots.
+const SALT_ATTEMPTS : u32 = 1000;
+
+// Struct to hold the files to embed in one volume,
+// and the password for the volume.
+pub struct Volume<'a> {
+    pub password: &'a str,
+    pub files: Vec<&'a str>,
+}
+
+// Method to get the length of the file data area used for volumes.
+// Only the lowest bit planes are used, so the image is not visibly changed.
+impl Steganography {
+    pub fn volume_area_len(&self) -> u64 {
+        let planes_len = self.stream_len() * self.settings.volume_bit_planes.min(8) as u64 / 8;
+        let header_bytes = self.header_len() as u64 * (1 + self.header_copy_locs.len() as u64);
+        planes_len.saturating_sub(header_bytes).min(self.embed_capacity)
+    }
+}
+
+// Method to get the length of each volume slot in the image.
+impl Steganography {
+    pub fn volume_slot_len(&self) -> u64 {
+        self.volume_area_len() / (self.settings.volume_slots.max(1) as u64)
+    }
+}
+
+// Method to get the number of bytes of files that can be
+// embedded in each volume.
+impl Steganography {
+    pub fn volume_capacity(&self) -> u64 {
+        self.volume_slot_len().saturating_sub(TAG_LEN as u64)
+    }
+}
+
+// Method to embed one or more volumes into a loaded image.
+// Each volume is encrypted with its own password, and extracting
+// with a password only ever reveals the files in that volume.
+impl Steganography {
+    pub fn embed_volumes(&mut self, volumes:&[Volume]) -> io::Result<()> {
+        // Initialise timer for function.
+        let embed_start = Instant::now();
+
+        if !self.img_to_proc {
+            info!("No image to embed volumes into.");
+            return Err(io::Error::other("No image loaded"));
+        }
+
+        // Set up header copies, or not, depending on the mode.
+        self.set_layout(self.headerless);
+
+        // Check that the volumes will fit in the image.
+        let num_slots = self.settings.volume_slots as u64;
+        if volumes.is_empty() || volumes.len() as u64 > num_slots {
+            return Err(io::Error::other(format!("Number of volumes must be between 1 and {}", num_slots)));
+        }
+        let passwords: HashSet<&str> = volumes.iter().map(|v| v.password).collect();
+        if passwords.len() != volumes.len() || passwords.contains("") {
+            return Err(io::Error::other("Each volume needs a different, non-empty password"));
+        }
+        for volume in volumes {
+            let packed_len = container::packed_len(&self.settings, &volume.files)?;
+            if packed_len > self.volume_capacity() {
+                warn!("Exceeded volume embedding capacity: {}", self.volume_capacity());
+                return Err(io::Error::other(format!("Volume exceeds capacity of {} bytes", self.volume_capacity())));
+            }
+        }
+
+        // Find a salt where every password selects a different slot.
+        let mut salt = Vec::new();
+        let mut keys: Vec<ContainerKey> = Vec::new();
+        for _attempt in 0..SALT_ATTEMPTS {
+            salt = container::random_bytes(self.settings.pw_chars as usize);
+            keys = volumes
+                .iter()
+                .map(|v| container::derive_key(&self.settings, v.password, &salt, num_slots))
+                .collect();
+            let slots: HashSet<u64> = keys.iter().map(|k| k.slot).collect();
+            if slots.len() == keys.len() {
+                break;
+            }
+            keys.clear();
+        }
+        if keys.is_empty() {
+            return Err(io::Error::other("Unable to place volumes in separate slots"));
+        }
+
+        // Write the header with the volume flag and salt.
+        // Headerless images only get the salt, padded with random bytes.
+        if self.headerless {
+            info!("Embedding headerless volume salt.");
+            self.init_embed_params();
+            let padding = container::random_bytes(self.header_len() as usize - salt.len());
+            self.write_chunks(&[salt.as_slice(), &padding].concat());
+            self.seek_payload(0);
+        }
+        else {
+            self.embed_preamble();
+            self.embed_volume_salt(&salt);
+            self.embed_num_of_files(0);
+            self.embed_header_copies();
+        }
+
+        // Fill each slot in turn, either with a volume or with random bytes.
+        let slot_len = self.volume_slot_len();
+        for slot in 0..num_slots {
+            let slot_bytes = match keys.iter().position(|k| k.slot == slot) {
+                Some(idx) => {
+                    info!("Embedding volume with {} file(s).", volumes[idx].files.len());
+                    let mut packed = container::pack_files(&self.settings, &volumes[idx].files)?;
+                    let padding = self.volume_capacity() as usize - packed.len();
+                    packed.extend(container::random_bytes(padding));
+                    container::seal(&keys[idx].key, packed)
+                }
+                None => container::random_bytes(slot_len as usize),
+            };
+            self.seek_payload(slot * slot_len);
+            self.write_chunks(&slot_bytes);
+        }
+
+        // Anything left over at the end of the volume area is also random.
+        let remainder = self.volume_area_len() - num_slots * slot_len;
+        if remainder > 0 {
+            self.seek_payload(num_slots * slot_len);
+            self.write_chunks(&container::random_bytes(remainder as usize));
+        }
+
+        // Determine delta time for function.
+        self.embed_duration = embed_start.elapsed();
+        info!("Time to embed volume(s): {:?}", self.embed_duration);
+        Ok(())
+    }
+}
+
+// Method to embed the volume flag and salt into the header,
+// in place of the password flag and hash.
+impl Steganography {
+    pub fn embed_volume_salt(&mut self, salt:&[u8]) {
+        info!("Embedding volume salt.");
+        let salt_bytes:Vec<u8> = [b"V", salt].concat();
+        self.write_chunks(&salt_bytes);
+    }
+}
+
+// Method to extract the files from the volume selected by a password.
+// Wrong passwords, and passwords for no volume, are both reported
+// as an incorrect password.
+impl Steganography {
+    pub fn extract_volume(&mut self, pw:&str) -> Result<(), SteganographyError> {
+        // Salt is stored in place of the password hash in the header,
+        // or at the very start of the image if headerless.
+        let salt = if self.pic_volumes {
+            let salt_start = super::PROG_CODE.len() + self.settings.pw_protected_chars as usize;
+            let salt_end = salt_start + self.settings.pw_chars as usize;
+            self.header_bytes.get(salt_start..salt_end).unwrap_or_default().to_vec()
+        }
+        else {
+            info!("Checking for headerless volume.");
+            self.set_layout(true);
+            self.skip_reserved = false;
+            self.seek_stream(0);
+            self.read_chunks(self.settings.pw_chars as u64)
+        };
+
+        // Read and decrypt the slot selected by the password.
+        let key = container::derive_key(&self.settings, pw, &salt, self.settings.volume_slots as u64);
+        let slot_len = self.volume_slot_len();
+        self.seek_payload(key.slot * slot_len);
+        let slot_bytes = self.read_chunks(slot_len);
+        let packed = match container::open(&key.key, slot_bytes) {
+            Some(packed) => packed,
+            None => {
+                info!("Password does not open a volume.");
+                return Err(SteganographyError::IncorrectPassword);
+            }
+        };
+        self.user_permit = true;
+
+        // Save each of the files in the volume.
+        let files = container::unpack_files(&self.settings, &packed).unwrap_or_default();
+        info!("Number of files in volume: {}", files.len());
+        for file in files {
+            if let Err(err) = self.save_extracted_file(&file) {
+                warn!("Failed to extract file: {}", err);
+            }
+        }
+        self.analyse_extracted_files();
+        Ok(())
+    }
+}
@@ -8,6 +8,70 @@ function showSpinner() {
 function hideSpinner() {
     console.log("Hiding spinner...");
     document.getElementById('overlay').classList.remove('active');
+    document.getElementById('progressContainer').style.display = 'none';
+}
+
+// Function to update the progress bar under the wait spinner.
+function showProgress(status) {
+    const progressContainer = document.getElementById('progressContainer');
+    progressContainer.style.display = 'block';
+    const percent = status.bytes_total > 0 ? Math.floor(100 * status.bytes_done / status.bytes_total) : 0;
+    document.getElementById('progressBar').style.width = `${percent}%`;
+    document.getElementById('progressText').textContent = status.file ? `${status.file} (${percent}%)` : `${percent}%`;
+}
+
+// ID of the background job being followed, if any.
+let currentJob = null;
+
+// Function to cancel the background job being followed.
+function cancelJob() {
+    if (currentJob) {
+        console.log(`Cancelling job ${currentJob}.`);
+        document.getElementById('progressText').textContent = 'Cancelling...';
+        fetch(`/jobs/${currentJob}`, { method: 'DELETE' });
+    }
+}
+
+// Function to post to an endpoint as a background job, following its
+// progress until it has finished. Resolves with a response holding
+// the job's result, so it can be used in place of fetch.
+// Rejects if the job was cancelled.
+function runJob(endpoint, body) {
+    body.append('async', 'true');
+    return fetch(endpoint, {
+        method: 'POST',
+        body: body
+    })
+    .then(response => {
+        if (response.status !== 202) {
+            return response;
+        }
+        return response.json().then(data => new Promise((resolve, reject) => {
+            console.log(`Following progress of job ${data.job}.`);
+            currentJob = data.job;
+            const events = new EventSource(`/jobs/${data.job}/events`);
+            events.addEventListener('progress', event => showProgress(JSON.parse(event.data)));
+            events.addEventListener('done', event => {
+                events.close();
+                currentJob = null;
+                const status = JSON.parse(event.data);
+                if (status.state === 'cancelled') {
+                    hideSpinner();
+                    const resultsElement = document.getElementById('processingResults');
+                    resultsElement.textContent = 'Processing cancelled.';
+                    reject(new Error('Job cancelled.'));
+                    return;
+                }
+                showProgress(status);
+                resolve(new Response(JSON.stringify(status.result), { status: status.status }));
+            });
+            events.onerror = () => {
+                events.close();
+                currentJob = null;
+                reject(new Error('Lost progress of job.'));
+            };
+        }));
+    });
 }
 
 // Initialise value of image file browsed.
@@ -16,6 +80,22 @@ document.addEventListener('DOMContentLoaded', function() {
     document.getElementById('imageUpload').value = '';
 });
 
+// Show who is logged in, with a way to log out, when logins are on.
+// Admins also get a link to the usage of every user.
+document.addEventListener('DOMContentLoaded', function() {
+    fetch('/account')
+    .then(response => response.json())
+    .then(account => {
+        if (!account.auth) {
+            return;
+        }
+        document.getElementById('accountName').textContent = account.name;
+        document.getElementById('usageLink').style.display = account.role === 'admin' ? 'inline' : 'none';
+        document.getElementById('accountBar').style.display = 'block';
+    })
+    .catch(error => console.error('Error getting account:', error));
+});
+
 // Function to clear the thumbnails previously displayed.
 function clearThumbnails() {
     console.log("Clearing any result thumbnails.");
@@ -28,6 +108,7 @@ function clearThumbnails() {
 }
 
 let requiresPassword = false;
+let requiresKey = false;
 
 // Function to clear the processing results.
 function clearProcessingResults() {
@@ -187,11 +268,22 @@ document.getElementById('uploadButton').addEventListener('click', function() {
                                         Embed capacity: ${embeddingCapacity} bytes`;
  
         requiresPassword = data.password === "True";
+        requiresKey = data.recipients === "True";
+
+        // Files in the image can be edited if it holds plain file sections.
+        if (data.editable === "True") {
+            document.getElementById('editButton').style.display = 'block';
+        }
 
         if (data.coded === "True") {
             resultsElement.className = 'results-text coded';
             extractButton.style.display = 'block';
             embedButton.style.display = 'block';
+        } else if (data.coded === "Unknown") {
+            // Image may hold a headerless volume, only a password will tell.
+            resultsElement.className = 'results-text not-coded';
+            extractButton.style.display = 'block';
+            embedButton.style.display = 'block';
         } else {
             resultsElement.className = 'results-text not-coded';
             embedButton.style.display = 'block';
@@ -213,6 +305,7 @@ document.getElementById('embedButton').addEventListener('click', function() {
     console.log("Hiding Embed and Extract buttons as not needed.");
     extractButton.style.display = 'none';
     embedButton.style.display = 'none';
+    document.getElementById('editButton').style.display = 'none';
 
     // Initialize the list of files to embed.
     console.log("Initialising list of files to embed.");
@@ -395,10 +488,7 @@ function performEmbedding(password = '') {
     showSpinner();
 
     console.log("Posting to /embed endpoint.");
-    fetch('/embed', {
-        method: 'POST',
-        body: formData
-    })
+    runJob('/embed', formData)
     .then(response => {
         if (!response.ok) {
             throw new Error('Failed to embed data.');
@@ -478,8 +568,13 @@ document.getElementById('extractButton').addEventListener('click', function() {
     console.log("Hiding Embed and Extract buttons as not needed.");
     extractButton.style.display = 'none';
     embedButton.style.display = 'none';
+    document.getElementById('editButton').style.display = 'none';
 
-    if (requiresPassword) {
+    if (requiresKey) {
+        // Display private key modal dialog.
+        const modal = document.getElementById('extractKeyModal');
+        modal.style.display = 'block';
+    } else if (requiresPassword) {
         // Display password modal dialog.
         const modal = document.getElementById('extractPasswordModal');
         modal.style.display = 'block';
@@ -510,6 +605,22 @@ document.getElementById('extractPasswordSubmitButton').addEventListener('click',
     performExtraction(password);
 });
 
+// Event listener for extract private key submit.
+// The key file is read here and sent in place of the password.
+document.getElementById('extractKeySubmitButton').addEventListener('click', function() {
+    const keyFile = document.getElementById('extractKeyInput').files[0];
+    const modal = document.getElementById('extractKeyModal');
+    modal.style.display = 'none';
+    if (!keyFile) {
+        console.log("No private key file selected.");
+        return;
+    }
+    keyFile.text().then(key => {
+        console.log("Performing embedded file extraction (with private key).");
+        performExtraction(key);
+    });
+});
+
 // Event listener for enter key to submit extraction password.
 document.getElementById('extractPasswordInput').addEventListener('keypress', function(event) {
     if (event.key === 'Enter') {
@@ -531,10 +642,7 @@ function performExtraction(password = '') {
     formData.append('password', password);
 
     console.log("Posting to /extract endpoint.");
-    fetch('/extract', {
-        method: 'POST',
-        body: new URLSearchParams(formData)
-    })
+    runJob('/extract', new URLSearchParams(formData))
     .then(response => {
         if (!response.ok) {
             throw new Error('Failed to extract data.');
@@ -549,6 +657,12 @@ function performExtraction(password = '') {
         const resultsElement = document.getElementById('processingResults');
         resultsElement.textContent = `File(s) extracted: ${data.extracted}, Duration: ${data.time}`;
 
+        // Include who signed the files, if they were signed.
+        if (data.signature && data.signature !== "Unsigned") {
+            const trusted = data.trusted === "True" ? "trusted" : "not trusted";
+            resultsElement.textContent += `, Signature: ${data.signature} (${data.signer}, ${trusted})`;
+        }
+
         // Check for errors in extraction.
         // Check for wrong password entered.
         if (data.extracted === "Incorrect password provided") {
@@ -570,6 +684,12 @@ function performExtraction(password = '') {
             if (passwordAttempts < 3){
                 extract_listener();
             }
+        } else if (data.extracted === "Private key does not match a recipient") {
+            // Wrong private key, so let the user try another key file.
+            resultsElement.textContent = `Error: ${data.extracted}, Duration: ${data.time}`;
+            resultsElement.className = 'results-text error';
+            alert('Private key does not match a recipient of the embedded files.');
+            extract_listener();
         } else {
             // Reinitialise password attempt counter.
             passwordAttempts = 0;
@@ -769,6 +889,208 @@ function performExtraction(password = '') {
     });
 }
 
+// Password for the embedded files, kept while they are being edited.
+let editPassword = '';
+
+// Name of the embedded file being replaced.
+let replaceFileName = '';
+
+// Event listener for Edit files button.
+document.getElementById('editButton').addEventListener('click', function() {
+    console.log("Request to edit files embedded in uploaded image.");
+
+    // Don't need the embed, extract or edit buttons any more, so hide them.
+    console.log("Hiding Embed, Extract and Edit buttons as not needed.");
+    extractButton.style.display = 'none';
+    embedButton.style.display = 'none';
+    document.getElementById('editButton').style.display = 'none';
+
+    if (requiresPassword) {
+        // Display password modal dialog.
+        const modal = document.getElementById('editPasswordModal');
+        modal.style.display = 'block';
+
+        // Focus on the password input field.
+        const passwordInput = document.getElementById('editPasswordInput');
+        if (passwordInput) {
+            passwordInput.focus();
+        }
+    } else {
+        listEmbeddedFiles();
+    }
+});
+
+// Event listener for edit password submit.
+document.getElementById('editPasswordSubmitButton').addEventListener('click', function() {
+    const password = document.getElementById('editPasswordInput').value;
+    const modal = document.getElementById('editPasswordModal');
+    modal.style.display = 'none';
+    console.log("Listing embedded files (with password).");
+    listEmbeddedFiles(password);
+});
+
+// Event listener for enter key to submit edit password.
+document.getElementById('editPasswordInput').addEventListener('keypress', function(event) {
+    if (event.key === 'Enter') {
+        console.log("Enter key pressed to list embedded files.");
+        event.preventDefault();
+        document.getElementById('editPasswordSubmitButton').click();
+    }
+});
+
+// Worker function to get the list of embedded files for editing.
+function listEmbeddedFiles(password = '') {
+    editPassword = password;
+
+    // Show the progress spinner.
+    showSpinner();
+
+    const formData = new FormData();
+    formData.append('password', password);
+
+    console.log("Posting to /files endpoint.");
+    fetch('/files', {
+        method: 'POST',
+        body: new URLSearchParams(formData)
+    })
+    .then(response => {
+        if (!response.ok) {
+            throw new Error('Failed to list embedded files.');
+        }
+
+        // Hide the progress spinner.
+        hideSpinner();
+        return response.json();
+    })
+    .then(data => {
+        console.log("Received data from /files endpoint.");
+        if (data.error) {
+            const resultsElement = document.getElementById('processingResults');
+            resultsElement.textContent = `Error: ${data.error}`;
+            resultsElement.className = 'results-text error';
+            return;
+        }
+        displayEmbeddedFiles(JSON.parse(data.files));
+    })
+    .catch(error => {
+        console.error('Error:', error);
+        // Hide the progress spinner.
+        hideSpinner();
+    });
+}
+
+// Function to display the embedded files, each with buttons to remove or replace it.
+function displayEmbeddedFiles(files) {
+    const editFileList = document.getElementById('editFileList');
+    editFileList.innerHTML = '';
+    files.forEach(file => {
+        const li = document.createElement('li');
+        li.textContent = `${file.name} (${file.size} bytes) `;
+
+        const removeButton = document.createElement('button');
+        removeButton.textContent = 'Remove';
+        removeButton.addEventListener('click', function() {
+            if (!confirm(`Remove ${file.name} from the image?`)) {
+                return;
+            }
+            console.log("Removing embedded file: " + file.name);
+            const formData = new FormData();
+            formData.append('password', editPassword);
+            formData.append('file', file.name);
+            submitEdit('/files/remove', new URLSearchParams(formData));
+        });
+
+        const replaceButton = document.createElement('button');
+        replaceButton.textContent = 'Replace';
+        replaceButton.addEventListener('click', function() {
+            console.log("Browsing for file to replace: " + file.name);
+            replaceFileName = file.name;
+            document.getElementById('editReplaceFile').click();
+        });
+
+        li.appendChild(removeButton);
+        li.appendChild(replaceButton);
+        editFileList.appendChild(li);
+    });
+    document.getElementById('editSection').style.display = 'block';
+}
+
+// Event listener for files to add to the embedded files.
+document.getElementById('editAddFiles').addEventListener('change', function(event) {
+    const files = Array.from(event.target.files);
+    if (files.length === 0) {
+        return;
+    }
+    console.log("Adding files to embedded files: ", files);
+    const formData = new FormData();
+    formData.append('password', editPassword);
+    files.forEach(file => {
+        formData.append('files', file);
+    });
+    event.target.value = '';
+    submitEdit('/files/add', formData);
+});
+
+// Event listener for file to replace an embedded file with.
+document.getElementById('editReplaceFile').addEventListener('change', function(event) {
+    const file = event.target.files[0];
+    if (!file) {
+        return;
+    }
+    console.log("Replacing embedded file " + replaceFileName + " with: " + file.name);
+    const formData = new FormData();
+    formData.append('password', editPassword);
+    formData.append('file', replaceFileName);
+    formData.append('files', file);
+    event.target.value = '';
+    submitEdit('/files/replace', formData);
+});
+
+// Worker function to post a change to the embedded files,
+// and show the changed image and files.
+function submitEdit(endpoint, body) {
+    // Show the progress spinner.
+    showSpinner();
+
+    console.log("Posting to " + endpoint + " endpoint.");
+    fetch(endpoint, {
+        method: 'POST',
+        body: body
+    })
+    .then(response => {
+        if (!response.ok) {
+            throw new Error('Failed to edit embedded files.');
+        }
+
+        // Hide the progress spinner.
+        hideSpinner();
+        return response.json();
+    })
+    .then(data => {
+        console.log("Received data from " + endpoint + " endpoint.");
+        const resultsElement = document.getElementById('processingResults');
+        if (data.edited === "True") {
+            // Link to the changed image, which has been saved as a new file.
+            resultsElement.textContent = 'Files changed, saved to: ';
+            const a = document.createElement('a');
+            a.href = data.link;
+            a.target = '_blank';
+            a.textContent = data.filename;
+            resultsElement.appendChild(a);
+            resultsElement.className = 'results-text coded';
+            displayEmbeddedFiles(JSON.parse(data.files));
+        } else {
+            resultsElement.textContent = `Error: ${data.error}`;
+            resultsElement.className = 'results-text error';
+        }
+    })
+    .catch(error => {
+        console.error('Error:', error);
+        // Hide the progress spinner.
+        hideSpinner();
+    });
+}
+
 // Close the modal when the user commits.
 document.querySelector('.close').addEventListener('click', function() {
     const modal = document.getElementById('passwordModal');
@@ -823,8 +1145,11 @@ function resetWorkflow() {
     document.getElementById('uploadButton').style.display = 'none';
     document.getElementById('embedButton').style.display = 'none';
     document.getElementById('extractButton').style.display = 'none';
+    document.getElementById('editButton').style.display = 'none';
 
-    // Hide embed section and any previous embedded image.
+    // Hide embed and edit sections and any previous embedded image.
     document.getElementById('embedSection').style.display = 'none';
+    document.getElementById('editSection').style.display = 'none';
+    document.getElementById('editFileList').innerHTML = '';
     document.getElementById('embeddedImageContainer').style.display = 'none';
 }